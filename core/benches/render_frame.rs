@@ -0,0 +1,21 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use herod_gba_core::gba::HerodGBA;
+
+// See render_frame.s for what the ROM does
+const ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/render_frame.gba");
+
+fn render_frame(c: &mut Criterion) {
+    let mut gba = HerodGBA::new();
+    gba.load_cartridge(ROM)
+        .expect("Could not load the benchmark ROM");
+    gba.power();
+
+    c.bench_function("render_frame", |b| {
+        b.iter(|| {
+            gba.render_frame();
+        })
+    });
+}
+
+criterion_group!(benches, render_frame);
+criterion_main!(benches);
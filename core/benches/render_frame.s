@@ -0,0 +1,84 @@
+@ A small ROM for the render_frame benchmark. It keeps the CPU busy moving
+@ memory around the way games do, from ROM to work RAM to VRAM, in both
+@ ARM and Thumb state while showing the result in mode 3. Rebuild with:
+@
+@   llvm-mc -triple=armv4t-none-eabi -filetype=obj render_frame.s -o render_frame.o
+@   llvm-objcopy -O binary render_frame.o render_frame.gba
+
+.syntax unified
+.arm
+.text
+
+header:
+    b main
+    .space 156                  @ Nintendo logo, left empty
+    .ascii "HEROD BENCH\0"      @ Title
+    .ascii "BHBE"               @ Game code
+    .ascii "01"                 @ Maker code
+    .byte 0x96                  @ Fixed value
+    .byte 0x00                  @ Main unit code
+    .byte 0x00                  @ Device type
+    .space 7
+    .byte 0x00                  @ Software version
+    .byte 0xED                  @ Header checksum
+    .space 2
+
+main:
+    mov r0, #0x04000000
+    ldr r1, =0x0403             @ Mode 3 with BG2 on
+    strh r1, [r0]
+    ldr sp, =0x03007F00
+
+frame:
+    @ Copy 8kb of ROM to EWRAM a word at a time
+    mov r0, #0x08000000
+    mov r1, #0x02000000
+    mov r2, #0x800
+1:
+    ldr r3, [r0], #4
+    str r3, [r1], #4
+    subs r2, r2, #1
+    bne 1b
+
+    @ Then on to VRAM as halfwords
+    mov r0, #0x02000000
+    mov r1, #0x06000000
+    mov r2, #0x1000
+2:
+    ldrh r3, [r0], #2
+    strh r3, [r1], #2
+    subs r2, r2, #1
+    bne 2b
+
+    @ Block transfers into IWRAM
+    mov r0, #0x02000000
+    mov r1, #0x03000000
+    mov r2, #0x100
+3:
+    ldmia r0!, {r3-r10}
+    stmia r1!, {r3-r10}
+    subs r2, r2, #1
+    bne 3b
+
+    ldr r0, =thumb_copy + 0x08000001
+    bx r0
+
+.thumb
+thumb_copy:
+    @ And back out to EWRAM from Thumb code, with the stack in use
+    ldr r0, =0x03000000
+    ldr r1, =0x02010000
+    ldr r2, =0x800
+4:
+    push {r0-r2}
+    ldr r3, [r0]
+    pop {r0-r2}
+    str r3, [r1]
+    adds r0, #4
+    adds r1, #4
+    subs r2, #1
+    bne 4b
+
+    ldr r0, =frame + 0x08000000
+    bx r0
+.ltorg
@@ -0,0 +1,103 @@
+// The 16kb BIOS ROM at 0x00000000, see here:
+// https://problemkaputt.de/gbatek-gba-memory-map.htm
+// https://problemkaputt.de/gbatek-bios-functions.htm
+
+use std::io;
+
+pub const BIOS_SIZE: usize = 16 * 1024;
+
+// Our own BIOS, assembled from replacement.s. It doesn't have the boot
+// logo but otherwise goes through the same vectors as the real one.
+const REPLACEMENT_BIOS: &[u8] = include_bytes!("replacement.bin");
+
+// Without a BIOS file the SWIs are handled in the CPU, but IRQs still go
+// through the vector at 0x18. This is the same dispatcher the BIOS has,
+// which calls the user handler at 0x3007FFC and returns from the IRQ.
+const HLE_IRQ_HANDLER: [(usize, u32); 7] = [
+    (0x18, 0xEA_00_00_42),  // b 0x128
+    (0x128, 0xE9_2D_50_0F), // stmfd sp!, {r0-r3, r12, lr}
+    (0x12C, 0xE3_A0_03_01), // mov r0, #0x4000000
+    (0x130, 0xE2_8F_E0_00), // add lr, pc, #0
+    (0x134, 0xE5_10_F0_04), // ldr pc, [r0, #-4]
+    (0x138, 0xE8_BD_50_0F), // ldmfd sp!, {r0-r3, r12, lr}
+    (0x13C, 0xE2_5E_F0_04), // subs pc, lr, #4
+];
+
+pub struct Bios {
+    data: Vec<u8>,
+    loaded: bool,
+    // The BIOS can only be read while we're executing it. Otherwise reads
+    // return the last opcode that was fetched from it instead.
+    last_opcode: u32,
+}
+
+impl Bios {
+    pub fn new() -> Bios {
+        let mut data = vec![0; BIOS_SIZE];
+        for (address, opcode) in HLE_IRQ_HANDLER {
+            data[address..address + 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+
+        Bios {
+            data,
+            loaded: false,
+            // What's left behind after the BIOS has finished booting,
+            // which is the MSR at 0xDC.
+            last_opcode: 0xE1_29_F0_00,
+        }
+    }
+
+    /// Loads a BIOS dump, which has to be exactly 16kb
+    pub fn load(&mut self, file_name: &str) -> io::Result<()> {
+        let data = std::fs::read(file_name)?;
+        if data.len() != BIOS_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("BIOS should be {} bytes but got {}", BIOS_SIZE, data.len()),
+            ));
+        }
+
+        self.copy_image(&data);
+        Ok(())
+    }
+
+    /// Uses the bundled replacement BIOS instead of a dump of the real one
+    pub fn load_replacement(&mut self) {
+        self.copy_image(REPLACEMENT_BIOS);
+    }
+
+    fn copy_image(&mut self, data: &[u8]) {
+        let len = data.len().min(BIOS_SIZE);
+        self.data[..len].copy_from_slice(&data[..len]);
+        self.data[len..].fill(0);
+        self.loaded = true;
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn loaded(&self) -> bool {
+        self.loaded
+    }
+
+    /// Remembers the opcode at `address` as it's being fetched
+    pub fn fetch(&mut self, address: u32) {
+        let index = (address as usize & (BIOS_SIZE - 1)) & !3;
+        self.last_opcode = u32::from_le_bytes([
+            self.data[index],
+            self.data[index + 1],
+            self.data[index + 2],
+            self.data[index + 3],
+        ]);
+    }
+
+    pub fn read(&self, address: u32, executing: bool) -> u8 {
+        let index = address as usize & (BIOS_SIZE - 1);
+        if executing {
+            self.data[index]
+        } else {
+            (self.last_opcode >> ((index & 3) * 8)) as u8
+        }
+    }
+}
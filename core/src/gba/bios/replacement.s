@@ -0,0 +1,1003 @@
+@ A small replacement for the GBA BIOS, used when there's no BIOS dump
+@ around. It has the same exception vectors, IRQ dispatcher and SWI
+@ calling conventions as the real one, see here:
+@ https://problemkaputt.de/gbatek-bios-functions.htm
+@
+@ There's no boot logo, reset goes straight to the cartridge. The sound
+@ driver and multiboot functions aren't implemented and return right away.
+@
+@ Rebuild replacement.bin after changing this file with:
+@   llvm-mc -triple=armv4t-none-eabi -filetype=obj replacement.s -o replacement.o
+@   llvm-objcopy -O binary replacement.o replacement.bin
+@
+@ The object never gets linked, so BL can't be used for local calls.
+@ CALL does the same thing with a plain branch.
+
+.syntax unified
+.arm
+
+.macro call target
+    mov lr, pc
+    b \target
+.endm
+
+@ ---------------------------------------------------------------------------
+@ Exception vectors
+@ ---------------------------------------------------------------------------
+
+vectors:
+    b soft_reset            @ Reset
+    movs pc, lr             @ Undefined instruction, skipped
+    b swi_handler           @ SWI
+    subs pc, lr, #4         @ Prefetch abort
+    subs pc, lr, #8         @ Data abort
+    b vectors + 0x14        @ Reserved
+    b irq_handler           @ IRQ
+    subs pc, lr, #4         @ FIQ
+
+@ Same place as on hardware, as some games look at the return address
+@ their IRQ handler gets called with.
+.org 0x128
+irq_handler:
+    stmfd sp!, {r0-r3, r12, lr}
+    mov r0, #0x04000000
+    add lr, pc, #0
+    ldr pc, [r0, #-4]       @ User handler at 0x3007FFC
+    ldmfd sp!, {r0-r3, r12, lr}
+    subs pc, lr, #4
+
+@ ---------------------------------------------------------------------------
+@ SWI dispatcher
+@
+@ The function runs in System mode with the IRQ disable bit of the caller,
+@ so interrupts can still be taken while waiting. r11 and r12 are
+@ restored afterwards and can be used as scratch registers.
+@ ---------------------------------------------------------------------------
+
+swi_handler:
+    stmfd sp!, {r11, r12, lr}
+    mrs r11, spsr
+    tst r11, #0x20
+    ldrhne r12, [lr, #-2]   @ Thumb, the number is in the bottom byte
+    ldreq r12, [lr, #-4]    @ ARM, the number is in bits 16-23
+    moveq r12, r12, lsr #16
+    and r12, r12, #0xFF
+    stmfd sp!, {r11}
+
+    and r11, r11, #0x80
+    orr r11, r11, #0x1F
+    msr cpsr_c, r11
+    stmfd sp!, {lr}
+
+    cmp r12, #0x20
+    ldrhs r12, =swi_unknown
+    ldrlo r12, [pc, r12, lsl #2]
+    b swi_call
+
+swi_table:
+    .word soft_reset            @ 0x00
+    .word swi_register_ram_reset
+    .word swi_halt
+    .word swi_stop
+    .word swi_intr_wait
+    .word swi_vblank_intr_wait
+    .word swi_div
+    .word swi_div_arm
+    .word swi_sqrt              @ 0x08
+    .word swi_arctan
+    .word swi_arctan2
+    .word swi_cpu_set
+    .word swi_cpu_fast_set
+    .word swi_get_bios_checksum
+    .word swi_bg_affine_set
+    .word swi_obj_affine_set
+    .word swi_bit_unpack        @ 0x10
+    .word swi_lz77_wram
+    .word swi_lz77_vram
+    .word swi_huffman
+    .word swi_rle_wram
+    .word swi_rle_vram
+    .word swi_diff8_wram
+    .word swi_diff8_vram
+    .word swi_diff16            @ 0x18
+    .word swi_sound_bias
+    .word swi_unknown
+    .word swi_unknown
+    .word swi_unknown
+    .word swi_unknown
+    .word swi_unknown
+    .word swi_midi_key_2_freq
+
+swi_call:
+    mov lr, pc
+    bx r12
+
+    ldmfd sp!, {lr}
+    mov r12, #0xD3
+    msr cpsr_c, r12
+    ldmfd sp!, {r11}
+    msr spsr_cxsf, r11
+    ldmfd sp!, {r11, r12, lr}
+    movs pc, lr
+
+swi_unknown:
+    bx lr
+
+@ ---------------------------------------------------------------------------
+@ Reset and halting
+@ ---------------------------------------------------------------------------
+
+@ Clears the top of IWRAM, sets up the stacks and jumps to the cartridge,
+@ or to EWRAM if the byte at 0x3007FFA is non-zero.
+soft_reset:
+    ldr r12, =0x03007FFA
+    ldrb r2, [r12]
+    ldr r0, =0x03007E00
+    mov r1, #0
+    mov r3, #0x200
+1:  str r1, [r0], #4
+    subs r3, r3, #4
+    bgt 1b
+
+    msr cpsr_c, #0xD3
+    ldr sp, =0x03007FE0
+    mov lr, #0
+    msr spsr_cxsf, lr
+    msr cpsr_c, #0xD2
+    ldr sp, =0x03007FA0
+    mov lr, #0
+    msr spsr_cxsf, lr
+    msr cpsr_c, #0x1F
+    ldr sp, =0x03007F00
+
+    cmp r2, #0
+    moveq lr, #0x08000000
+    movne lr, #0x02000000
+    mov r0, #0
+    mov r1, #0
+    mov r2, #0
+    mov r3, #0
+    mov r4, #0
+    mov r5, #0
+    mov r6, #0
+    mov r7, #0
+    mov r8, #0
+    mov r9, #0
+    mov r10, #0
+    mov r11, #0
+    mov r12, #0
+    bx lr
+
+@ r0 = which memory and registers to clear
+swi_register_ram_reset:
+    stmfd sp!, {r0-r4, lr}
+    mov r4, r0
+    mov r12, #0x04000000
+    mov r11, #0x80
+    strh r11, [r12]         @ The screen is always forced blank
+
+    tst r4, #0x01
+    movne r0, #0x02000000   @ EWRAM
+    movne r1, #0x40000
+    movne lr, pc
+    bne clear
+    tst r4, #0x02
+    movne r0, #0x03000000   @ IWRAM, apart from the last 0x200 bytes
+    movne r1, #0x7E00
+    movne lr, pc
+    bne clear
+    tst r4, #0x04
+    movne r0, #0x05000000   @ Palette
+    movne r1, #0x400
+    movne lr, pc
+    bne clear
+    tst r4, #0x08
+    movne r0, #0x06000000   @ VRAM
+    movne r1, #0x18000
+    movne lr, pc
+    bne clear
+    tst r4, #0x10
+    movne r0, #0x07000000   @ OAM
+    movne r1, #0x400
+    movne lr, pc
+    bne clear
+
+    tst r4, #0x20
+    beq 1f
+    ldr r0, =0x04000120     @ Serial registers
+    mov r1, #0x10
+    call clear
+    ldr r0, =0x04000134
+    mov r1, #0x8000
+    strh r1, [r0]
+    ldr r0, =0x04000140
+    mov r1, #0x04
+    call clear
+    ldr r0, =0x04000150
+    mov r1, #0x10
+    call clear
+
+1:  tst r4, #0x40
+    beq 2f
+    ldr r0, =0x04000060     @ Sound registers
+    mov r1, #0x28
+    call clear
+    ldr r0, =0x04000088
+    mov r1, #0x200
+    strh r1, [r0]
+    ldr r0, =0x04000090
+    mov r1, #0x18
+    call clear
+
+2:  tst r4, #0x80
+    beq 3f
+    ldr r0, =0x04000002     @ Display registers
+    mov r1, #0x5E
+    call clear
+    ldr r0, =0x040000B0     @ DMA
+    mov r1, #0x30
+    call clear
+    ldr r0, =0x04000100     @ Timers
+    mov r1, #0x10
+    call clear
+    ldr r0, =0x04000200
+    mov r1, #0
+    strh r1, [r0]           @ IE
+    ldr r1, =0xFFFF
+    strh r1, [r0, #2]       @ IF, acknowledges everything
+    mov r1, #0
+    strh r1, [r0, #4]       @ WAITCNT
+    strh r1, [r0, #8]       @ IME
+
+3:  ldmfd sp!, {r0-r4, lr}
+    bx lr
+
+@ Zeroes r1 bytes at r0 a halfword at a time
+clear:
+    mov r2, #0
+1:  subs r1, r1, #2
+    strhge r2, [r0], #2
+    bgt 1b
+    bx lr
+
+swi_halt:
+    mov r12, #0x04000000
+    mov r11, #0x00
+    strb r11, [r12, #0x301]
+    bx lr
+
+swi_stop:
+    mov r12, #0x04000000
+    mov r11, #0x80
+    strb r11, [r12, #0x301]
+    bx lr
+
+swi_vblank_intr_wait:
+    mov r0, #1
+    mov r1, #1
+
+@ r0 = discard old flags, r1 = interrupts to wait for. The user IRQ
+@ handler has to acknowledge them in the flags at 0x3007FF8, which we
+@ get to through the mirror at 0x3FFFFF8.
+swi_intr_wait:
+    stmfd sp!, {r2}
+    mov r12, #0x04000000
+    mov r2, #1
+    strb r2, [r12, #0x208]  @ IME
+
+    cmp r0, #0
+    ldrhne r2, [r12, #-8]
+    bicne r2, r2, r1
+    strhne r2, [r12, #-8]
+
+1:  ldrh r2, [r12, #-8]
+    ands r0, r2, r1
+    bne 2f
+    strb r0, [r12, #0x301]  @ Halt until the next interrupt
+    b 1b
+
+2:  eor r2, r2, r0
+    strh r2, [r12, #-8]
+    ldmfd sp!, {r2}
+    bx lr
+
+@ ---------------------------------------------------------------------------
+@ Arithmetic
+@ ---------------------------------------------------------------------------
+
+swi_div_arm:
+    mov r3, r0
+    mov r0, r1
+    mov r1, r3
+
+@ r0 = numerator, r1 = denominator
+@ Returns r0 = quotient, r1 = remainder, r3 = abs(quotient)
+swi_div:
+    cmp r1, #0
+    beq div_by_zero
+    stmfd sp!, {r2, r4}
+    eor r4, r0, r1          @ Sign of the quotient
+    mov r12, r0             @ Sign of the remainder
+    cmp r0, #0
+    rsblt r0, r0, #0
+    cmp r1, #0
+    rsblt r1, r1, #0
+
+    mov r2, r1
+    mov r3, #0
+1:  cmp r2, r0, lsr #1
+    movls r2, r2, lsl #1
+    bls 1b
+2:  cmp r0, r2
+    subhs r0, r0, r2
+    adc r3, r3, r3
+    cmp r2, r1
+    movhi r2, r2, lsr #1
+    bhi 2b
+
+    mov r1, r0
+    cmp r12, #0
+    rsblt r1, r1, #0
+    mov r0, r3
+    cmp r4, #0
+    rsblt r0, r0, #0
+    ldmfd sp!, {r2, r4}
+    bx lr
+
+@ Hardware hangs here, we return something sensible instead
+div_by_zero:
+    mov r1, r0
+    cmp r0, #0
+    movge r0, #1
+    mvnlt r0, #0
+    mov r3, #1
+    bx lr
+
+@ r0 = unsigned 32 bit value, returns the 16 bit square root in r0
+swi_sqrt:
+    stmfd sp!, {r2}
+    mov r1, #0
+    mov r3, #0x40000000
+1:  cmp r3, r0
+    movhi r3, r3, lsr #2
+    bhi 1b
+2:  cmp r3, #0
+    beq 3f
+    add r2, r1, r3
+    cmp r0, r2
+    subhs r0, r0, r2
+    mov r1, r1, lsr #1
+    addhs r1, r1, r3
+    mov r3, r3, lsr #2
+    b 2b
+3:  mov r0, r1
+    ldmfd sp!, {r2}
+    bx lr
+
+@ r0 = tan in 1.14 fixed point, returns the angle in r0 with
+@ 0x4000 = 90 degrees. Also leaves the intermediate values in r1 and r3.
+swi_arctan:
+    stmfd sp!, {r2}
+    mul r1, r0, r0
+    mov r1, r1, asr #14
+    rsb r1, r1, #0
+    mov r3, #0xA9
+    mul r3, r1, r3
+    mov r3, r3, asr #14
+    add r3, r3, #0x390
+    ldr r12, =arctan_table
+    mov r2, #6
+1:  mul r3, r1, r3
+    mov r3, r3, asr #14
+    ldr r11, [r12], #4
+    add r3, r3, r11
+    subs r2, r2, #1
+    bne 1b
+    mul r0, r3, r0
+    mov r0, r0, asr #16
+    mov r0, r0, lsl #16
+    mov r0, r0, asr #16
+    ldmfd sp!, {r2}
+    bx lr
+
+arctan_table:
+    .word 0x91C, 0xFB6, 0x16AA, 0x2081, 0x3651, 0xA2F9
+
+@ r0 = x, r1 = y, returns the angle in r0 with 0x10000 = 360 degrees
+swi_arctan2:
+    stmfd sp!, {r2, r4, r5, lr}
+    mov r4, r0
+    mov r5, r1
+    cmp r5, #0
+    bne 1f
+    cmp r4, #0
+    movge r0, #0
+    movlt r0, #0x8000
+    b 9f
+1:  cmp r4, #0
+    bne 2f
+    cmp r5, #0
+    movge r0, #0x4000
+    movlt r0, #0xC000
+    b 9f
+
+2:  cmp r5, #0
+    blt 3f
+    cmp r4, #0
+    blt 1f
+    cmp r4, r5
+    bge 4f                  @ atan(y / x)
+    b 6f                    @ 0x4000 - atan(x / y)
+1:  rsb r0, r4, #0
+    cmp r0, r5
+    bge 5f                  @ atan(y / x) + 0x8000
+    b 6f
+
+3:  cmp r4, #0
+    bgt 1f
+    rsb r0, r4, #0
+    rsb r1, r5, #0
+    cmp r0, r1
+    bgt 5f
+    b 7f                    @ 0xC000 - atan(x / y)
+1:  rsb r1, r5, #0
+    cmp r4, r1
+    bge 8f                  @ atan(y / x) + 0x10000
+    b 7f
+
+4:  mov r0, r5, lsl #14
+    mov r1, r4
+    call swi_div
+    call swi_arctan
+    b 9f
+5:  mov r0, r5, lsl #14
+    mov r1, r4
+    call swi_div
+    call swi_arctan
+    add r0, r0, #0x8000
+    b 9f
+6:  mov r0, r4, lsl #14
+    mov r1, r5
+    call swi_div
+    call swi_arctan
+    rsb r0, r0, #0x4000
+    b 9f
+7:  mov r0, r4, lsl #14
+    mov r1, r5
+    call swi_div
+    call swi_arctan
+    rsb r0, r0, #0xC000
+    b 9f
+8:  mov r0, r5, lsl #14
+    mov r1, r4
+    call swi_div
+    call swi_arctan
+    add r0, r0, #0x10000
+
+9:  mov r0, r0, lsl #16
+    mov r0, r0, lsr #16
+    ldmfd sp!, {r2, r4, r5, lr}
+    bx lr
+
+swi_get_bios_checksum:
+    ldr r0, =0xBAAE187F     @ What the real BIOS returns
+    bx lr
+
+@ ---------------------------------------------------------------------------
+@ Memory copies
+@ ---------------------------------------------------------------------------
+
+@ r0 = source, r1 = destination, r2 = control. Bits 0-20 are the amount
+@ of units, bit 24 fills with the first unit and bit 26 copies words.
+swi_cpu_set:
+    stmfd sp!, {r0-r4}
+    bic r3, r2, #0xFF000000
+    bic r3, r3, #0x00E00000
+    tst r2, #0x04000000
+    bne 3f
+
+    bic r0, r0, #1
+    bic r1, r1, #1
+    tst r2, #0x01000000
+    ldrhne r4, [r0]
+    bne 2f
+1:  subs r3, r3, #1
+    blt 9f
+    ldrh r4, [r0], #2
+    strh r4, [r1], #2
+    b 1b
+2:  subs r3, r3, #1
+    blt 9f
+    strh r4, [r1], #2
+    b 2b
+
+3:  bic r0, r0, #3
+    bic r1, r1, #3
+    tst r2, #0x01000000
+    ldrne r4, [r0]
+    bne 2f
+1:  subs r3, r3, #1
+    blt 9f
+    ldr r4, [r0], #4
+    str r4, [r1], #4
+    b 1b
+2:  subs r3, r3, #1
+    blt 9f
+    str r4, [r1], #4
+    b 2b
+
+9:  ldmfd sp!, {r0-r4}
+    bx lr
+
+@ Same as CpuSet, but always copies words in blocks of 8
+swi_cpu_fast_set:
+    stmfd sp!, {r0-r10}
+    bic r0, r0, #3
+    bic r1, r1, #3
+    bic r12, r2, #0xFF000000
+    bic r12, r12, #0x00E00000
+    add r12, r12, #7
+    mov r12, r12, lsr #3
+    tst r2, #0x01000000
+    bne 2f
+
+1:  subs r12, r12, #1
+    blt 9f
+    ldmia r0!, {r2-r9}
+    stmia r1!, {r2-r9}
+    b 1b
+
+2:  ldr r2, [r0]
+    mov r3, r2
+    mov r4, r2
+    mov r5, r2
+    mov r6, r2
+    mov r7, r2
+    mov r8, r2
+    mov r9, r2
+3:  subs r12, r12, #1
+    blt 9f
+    stmia r1!, {r2-r9}
+    b 3b
+
+9:  ldmfd sp!, {r0-r10}
+    bx lr
+
+@ ---------------------------------------------------------------------------
+@ Rotation and scaling
+@ ---------------------------------------------------------------------------
+
+@ r0 = source, r1 = destination, r2 = count. Each source entry holds the
+@ center of rotation in texture and screen coordinates, the scaling ratios
+@ and the angle. The result is the BG parameters and reference point.
+swi_bg_affine_set:
+    stmfd sp!, {r0-r10}
+    ldr r12, =sine_table
+1:  subs r2, r2, #1
+    blt 9f
+    ldrh r3, [r0, #16]
+    mov r3, r3, lsr #8
+    mov r4, r3, lsl #1
+    ldrsh r4, [r12, r4]     @ sin
+    add r3, r3, #64
+    and r3, r3, #0xFF
+    mov r3, r3, lsl #1
+    ldrsh r3, [r12, r3]     @ cos
+
+    ldrsh r5, [r0, #12]
+    ldrsh r6, [r0, #14]
+    mul r7, r5, r3
+    mov r7, r7, asr #14     @ pa = sx * cos
+    mul r8, r5, r4
+    rsb r8, r8, #0
+    mov r8, r8, asr #14     @ pb = -sx * sin
+    mul r9, r6, r4
+    mov r9, r9, asr #14     @ pc = sy * sin
+    mul r10, r6, r3
+    mov r10, r10, asr #14   @ pd = sy * cos
+    strh r7, [r1]
+    strh r8, [r1, #2]
+    strh r9, [r1, #4]
+    strh r10, [r1, #6]
+
+    ldrsh r5, [r0, #8]
+    ldrsh r6, [r0, #10]
+    ldr r3, [r0]
+    mul r11, r7, r5
+    sub r3, r3, r11
+    mul r11, r8, r6
+    sub r3, r3, r11
+    str r3, [r1, #8]
+    ldr r3, [r0, #4]
+    mul r11, r9, r5
+    sub r3, r3, r11
+    mul r11, r10, r6
+    sub r3, r3, r11
+    str r3, [r1, #12]
+
+    add r0, r0, #20
+    add r1, r1, #16
+    b 1b
+9:  ldmfd sp!, {r0-r10}
+    bx lr
+
+@ r0 = source, r1 = destination, r2 = count, r3 = bytes between each
+@ parameter. Source entries are the scaling ratios and the angle.
+swi_obj_affine_set:
+    stmfd sp!, {r0-r8}
+    ldr r12, =sine_table
+1:  subs r2, r2, #1
+    blt 9f
+    ldrh r4, [r0, #4]
+    mov r4, r4, lsr #8
+    add r5, r4, #64
+    and r5, r5, #0xFF
+    mov r5, r5, lsl #1
+    ldrsh r5, [r12, r5]     @ cos
+    mov r4, r4, lsl #1
+    ldrsh r4, [r12, r4]     @ sin
+
+    ldrsh r6, [r0]
+    ldrsh r7, [r0, #2]
+    mul r8, r6, r5
+    mov r8, r8, asr #14
+    strh r8, [r1], r3
+    mul r8, r6, r4
+    rsb r8, r8, #0
+    mov r8, r8, asr #14
+    strh r8, [r1], r3
+    mul r8, r7, r4
+    mov r8, r8, asr #14
+    strh r8, [r1], r3
+    mul r8, r7, r5
+    mov r8, r8, asr #14
+    strh r8, [r1], r3
+
+    add r0, r0, #8
+    b 1b
+9:  ldmfd sp!, {r0-r8}
+    bx lr
+
+@ ---------------------------------------------------------------------------
+@ Decompression
+@
+@ Every stream starts with a header word, with the type in bits 4-7 and
+@ the decompressed size in bits 8-31. The VRAM variants can only write
+@ halfwords, which write_byte takes care of when r9 is set.
+@ ---------------------------------------------------------------------------
+
+@ r0 = source, r1 = destination, r2 = info. The info struct holds the
+@ source length, the source and destination unit widths and an offset
+@ added to non-zero units (or all of them if bit 31 is set).
+swi_bit_unpack:
+    stmfd sp!, {r0-r10}
+    ldrh r3, [r2]
+    ldrb r4, [r2, #2]
+    ldrb r5, [r2, #3]
+    ldr r6, [r2, #4]
+    mov r7, #1
+    mov r7, r7, lsl r4
+    sub r7, r7, #1
+    mov r8, #0
+    mov r9, #0
+
+1:  subs r3, r3, #1
+    blt 9f
+    ldrb r10, [r0], #1
+    mov r11, #0
+2:  cmp r11, #8
+    bge 1b
+    and r2, r7, r10, lsr r11
+    add r11, r11, r4
+    cmp r2, #0
+    bne 3f
+    tst r6, #0x80000000
+    beq 4f
+3:  bic r12, r6, #0x80000000
+    add r2, r2, r12
+4:  orr r8, r8, r2, lsl r9
+    add r9, r9, r5
+    cmp r9, #32
+    blt 2b
+    str r8, [r1], #4
+    mov r8, #0
+    mov r9, #0
+    b 2b
+
+9:  ldmfd sp!, {r0-r10}
+    bx lr
+
+@ Writes the byte in r12 to r1 and moves r1 along. VRAM writes go through
+@ r7, which holds the byte written to the even address before it.
+write_byte:
+    cmp r9, #0
+    strbeq r12, [r1], #1
+    bxeq lr
+    tst r1, #1
+    andeq r7, r12, #0xFF
+    orrne r7, r7, r12, lsl #8
+    strheq r7, [r1]
+    subne r1, r1, #1
+    strhne r7, [r1]
+    addne r1, r1, #1
+    add r1, r1, #1
+    bx lr
+
+swi_lz77_wram:
+    stmfd sp!, {r0-r9, lr}
+    mov r9, #0
+    b lz77
+swi_lz77_vram:
+    stmfd sp!, {r0-r9, lr}
+    mov r9, #1
+
+@ Blocks of 8 units led by a flag byte, top bit first. A 0 bit is a
+@ literal byte, a 1 bit copies 3-18 bytes from 1-4096 bytes back.
+lz77:
+    ldr r2, [r0], #4
+    mov r2, r2, lsr #8
+1:  cmp r2, #0
+    ble 9f
+    ldrb r3, [r0], #1
+    mov r4, #8
+2:  subs r4, r4, #1
+    blt 1b
+    cmp r2, #0
+    ble 9f
+    tst r3, #0x80
+    mov r3, r3, lsl #1
+    bne 3f
+
+    ldrb r12, [r0], #1
+    call write_byte
+    sub r2, r2, #1
+    b 2b
+
+3:  ldrb r5, [r0], #1
+    ldrb r6, [r0], #1
+    orr r6, r6, r5, lsl #8
+    mov r5, r5, lsr #4
+    add r5, r5, #3          @ Length
+    mov r6, r6, lsl #20
+    mov r6, r6, lsr #20
+    add r6, r6, #1
+    sub r6, r1, r6          @ Where to copy from
+4:  ldrb r12, [r6], #1
+    call write_byte
+    sub r2, r2, #1
+    cmp r2, #0
+    ble 9f
+    subs r5, r5, #1
+    bgt 4b
+    b 2b
+
+9:  ldmfd sp!, {r0-r9, lr}
+    bx lr
+
+@ The tree comes after the header, and the bitstream after the tree in
+@ words that are read from the top bit down. Output is always in words.
+swi_huffman:
+    stmfd sp!, {r0-r11}
+    ldr r2, [r0]
+    and r3, r2, #0x0F       @ Bits per unit
+    mov r2, r2, lsr #8
+    ldrb r4, [r0, #4]
+    add r5, r0, #5          @ Root node
+    add r6, r0, #4
+    add r4, r4, #1
+    add r6, r6, r4, lsl #1  @ Bitstream
+    mov r7, r5
+    ldrb r8, [r7]
+    mov r9, #0
+    mov r10, #0
+
+1:  cmp r2, #0
+    ble 9f
+    ldr r11, [r6], #4
+    mov r4, #32
+2:  subs r4, r4, #1
+    blt 1b
+    cmp r2, #0
+    ble 9f
+    bic r12, r7, #1
+    and r0, r8, #0x3F
+    add r12, r12, r0, lsl #1
+    add r12, r12, #2        @ Address of node 0
+    movs r11, r11, lsl #1
+    addcs r12, r12, #1
+    movcc r0, #0x80
+    movcs r0, #0x40
+    tst r8, r0
+    beq 3f
+
+    ldrb r0, [r12]
+    orr r9, r9, r0, lsl r10
+    add r10, r10, r3
+    mov r7, r5
+    ldrb r8, [r7]
+    cmp r10, #32
+    blt 2b
+    str r9, [r1], #4
+    sub r2, r2, #4
+    mov r9, #0
+    mov r10, #0
+    b 2b
+
+3:  mov r7, r12
+    ldrb r8, [r7]
+    b 2b
+
+9:  ldmfd sp!, {r0-r11}
+    bx lr
+
+swi_rle_wram:
+    stmfd sp!, {r0-r9, lr}
+    mov r9, #0
+    b rle
+swi_rle_vram:
+    stmfd sp!, {r0-r9, lr}
+    mov r9, #1
+
+@ Each flag byte either leads 1-128 bytes stored as they are, or a run
+@ of 3-130 copies of the next byte if bit 7 is set.
+rle:
+    ldr r2, [r0], #4
+    mov r2, r2, lsr #8
+1:  cmp r2, #0
+    ble 9f
+    ldrb r3, [r0], #1
+    tst r3, #0x80
+    and r3, r3, #0x7F
+    bne 3f
+
+    add r3, r3, #1
+2:  ldrb r12, [r0], #1
+    call write_byte
+    sub r2, r2, #1
+    cmp r2, #0
+    ble 9f
+    subs r3, r3, #1
+    bgt 2b
+    b 1b
+
+3:  add r3, r3, #3
+    ldrb r4, [r0], #1
+4:  mov r12, r4
+    call write_byte
+    sub r2, r2, #1
+    cmp r2, #0
+    ble 9f
+    subs r3, r3, #1
+    bgt 4b
+    b 1b
+
+9:  ldmfd sp!, {r0-r9, lr}
+    bx lr
+
+swi_diff8_wram:
+    stmfd sp!, {r0-r9, lr}
+    mov r9, #0
+    b diff8
+swi_diff8_vram:
+    stmfd sp!, {r0-r9, lr}
+    mov r9, #1
+
+@ Every byte is the difference to the previous one
+diff8:
+    ldr r2, [r0], #4
+    mov r2, r2, lsr #8
+    mov r3, #0
+1:  subs r2, r2, #1
+    blt 9f
+    ldrb r12, [r0], #1
+    add r3, r3, r12
+    and r12, r3, #0xFF
+    call write_byte
+    b 1b
+
+9:  ldmfd sp!, {r0-r9, lr}
+    bx lr
+
+swi_diff16:
+    stmfd sp!, {r0-r3}
+    ldr r2, [r0], #4
+    mov r2, r2, lsr #8
+    mov r3, #0
+1:  subs r2, r2, #2
+    blt 9f
+    ldrh r12, [r0], #2
+    add r3, r3, r12
+    strh r3, [r1], #2
+    b 1b
+9:  ldmfd sp!, {r0-r3}
+    bx lr
+
+@ ---------------------------------------------------------------------------
+@ Sound
+@ ---------------------------------------------------------------------------
+
+@ r0 = 0 to drop the bias level to 0, otherwise it goes up to 0x200.
+@ The real BIOS ramps it slowly, we set it straight away.
+swi_sound_bias:
+    ldr r12, =0x04000088
+    ldrh r11, [r12]
+    mov r11, r11, lsr #10
+    mov r11, r11, lsl #10
+    cmp r0, #0
+    orrne r11, r11, #0x200
+    strh r11, [r12]
+    bx lr
+
+@ r0 = wave data, r1 = MIDI key, r2 = fine adjustment. Returns the
+@ frequency in r0, which is the one in the wave data divided by
+@ 2^((180 - key - fine / 256) / 12).
+swi_midi_key_2_freq:
+    stmfd sp!, {r2-r5, lr}
+    ldr r4, [r0, #4]
+    rsb r1, r1, #180
+    mov r1, r1, lsl #8
+    sub r0, r1, r2
+    mov r1, #3072
+    call swi_div            @ r0 = octaves, r1 = 1/256ths of a semitone
+    mov r5, r0
+    mov r2, r1, lsr #8
+    and r1, r1, #0xFF
+    ldr r12, =pitch_table
+    ldr r3, [r12, r2, lsl #2]!
+    ldr r2, [r12, #4]
+    sub r2, r3, r2
+    mul r2, r1, r2
+    sub r3, r3, r2, lsr #8  @ Interpolated between both semitones
+    umull r0, r1, r4, r3
+    add r5, r5, #16
+    mov r0, r0, lsr r5
+    rsb r5, r5, #32
+    orr r0, r0, r1, lsl r5
+    ldmfd sp!, {r2-r5, lr}
+    bx lr
+
+.ltorg
+
+@ ---------------------------------------------------------------------------
+@ Tables
+@ ---------------------------------------------------------------------------
+
+@ 2^(-n / 12) in 0.16 fixed point for every semitone of an octave
+pitch_table:
+    .word 65536, 61858, 58386, 55109, 52016, 49097, 46341, 43740
+    .word 41285, 38968, 36781, 34716, 32768
+
+@ sin(n * 2pi / 256) in 1.14 fixed point
+sine_table:
+    .hword 0, 402, 804, 1205, 1606, 2006, 2404, 2801
+    .hword 3196, 3590, 3981, 4370, 4756, 5139, 5520, 5897
+    .hword 6270, 6639, 7005, 7366, 7723, 8076, 8423, 8765
+    .hword 9102, 9434, 9760, 10080, 10394, 10702, 11003, 11297
+    .hword 11585, 11866, 12140, 12406, 12665, 12916, 13160, 13395
+    .hword 13623, 13842, 14053, 14256, 14449, 14635, 14811, 14978
+    .hword 15137, 15286, 15426, 15557, 15679, 15791, 15893, 15986
+    .hword 16069, 16143, 16207, 16261, 16305, 16340, 16364, 16379
+    .hword 16384, 16379, 16364, 16340, 16305, 16261, 16207, 16143
+    .hword 16069, 15986, 15893, 15791, 15679, 15557, 15426, 15286
+    .hword 15137, 14978, 14811, 14635, 14449, 14256, 14053, 13842
+    .hword 13623, 13395, 13160, 12916, 12665, 12406, 12140, 11866
+    .hword 11585, 11297, 11003, 10702, 10394, 10080, 9760, 9434
+    .hword 9102, 8765, 8423, 8076, 7723, 7366, 7005, 6639
+    .hword 6270, 5897, 5520, 5139, 4756, 4370, 3981, 3590
+    .hword 3196, 2801, 2404, 2006, 1606, 1205, 804, 402
+    .hword 0, -402, -804, -1205, -1606, -2006, -2404, -2801
+    .hword -3196, -3590, -3981, -4370, -4756, -5139, -5520, -5897
+    .hword -6270, -6639, -7005, -7366, -7723, -8076, -8423, -8765
+    .hword -9102, -9434, -9760, -10080, -10394, -10702, -11003, -11297
+    .hword -11585, -11866, -12140, -12406, -12665, -12916, -13160, -13395
+    .hword -13623, -13842, -14053, -14256, -14449, -14635, -14811, -14978
+    .hword -15137, -15286, -15426, -15557, -15679, -15791, -15893, -15986
+    .hword -16069, -16143, -16207, -16261, -16305, -16340, -16364, -16379
+    .hword -16384, -16379, -16364, -16340, -16305, -16261, -16207, -16143
+    .hword -16069, -15986, -15893, -15791, -15679, -15557, -15426, -15286
+    .hword -15137, -14978, -14811, -14635, -14449, -14256, -14053, -13842
+    .hword -13623, -13395, -13160, -12916, -12665, -12406, -12140, -11866
+    .hword -11585, -11297, -11003, -10702, -10394, -10080, -9760, -9434
+    .hword -9102, -8765, -8423, -8076, -7723, -7366, -7005, -6639
+    .hword -6270, -5897, -5520, -5139, -4756, -4370, -3981, -3590
+    .hword -3196, -2801, -2404, -2006, -1606, -1205, -804, -402
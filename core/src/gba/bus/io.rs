@@ -0,0 +1,248 @@
+// https://problemkaputt.de/gbatek-gba-i-o-map.htm
+//
+// Every I/O register is described by the halfword it sits at. Wider
+// accesses are made up of halfword accesses and byte accesses only touch
+// one half of it, so the owners only ever see 16 bit reads and writes.
+
+pub const IO_SIZE: u32 = 0x4_00;
+
+/// The subsystem a register belongs to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Owner {
+    Ppu,
+    Sound,
+    Dma,
+    Timer,
+    Serial,
+    Keypad,
+    Interrupt,
+    // WAITCNT, which is handled by the bus itself
+    Waitstate,
+    System,
+    // The unused upper half of a 32 bit register, which reads as 0
+    Unused,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Register {
+    pub name: &'static str,
+    pub owner: Owner,
+    // Bits that can be read back, the rest read as 0
+    pub readable: u16,
+    // Bits that can be written, the rest are left alone
+    pub writable: u16,
+}
+
+const fn rw(name: &'static str, owner: Owner, mask: u16) -> Option<Register> {
+    Some(Register {
+        name,
+        owner,
+        readable: mask,
+        writable: mask,
+    })
+}
+
+const fn ro(name: &'static str, owner: Owner, mask: u16) -> Option<Register> {
+    Some(Register {
+        name,
+        owner,
+        readable: mask,
+        writable: 0x0,
+    })
+}
+
+const fn wo(name: &'static str, owner: Owner, mask: u16) -> Option<Register> {
+    Some(Register {
+        name,
+        owner,
+        readable: 0x0,
+        writable: mask,
+    })
+}
+
+const fn masked(
+    name: &'static str,
+    owner: Owner,
+    readable: u16,
+    writable: u16,
+) -> Option<Register> {
+    Some(Register {
+        name,
+        owner,
+        readable,
+        writable,
+    })
+}
+
+const fn unused() -> Option<Register> {
+    ro("-", Owner::Unused, 0x0)
+}
+
+static REGISTERS: [Option<Register>; (IO_SIZE / 2) as usize] = build_table();
+
+/// Looks up the register at `address`, which is `None` for addresses
+/// nothing responds to.
+pub fn register(address: u32) -> Option<&'static Register> {
+    REGISTERS
+        .get(((address & 0x00_FF_FF_FF) / 2) as usize)
+        .and_then(Option::as_ref)
+}
+
+/// Puts the bytes of `value` selected by `mask` into `old`, for the
+/// owners to apply partial writes with.
+pub fn merge(old: u16, value: u16, mask: u16) -> u16 {
+    (old & !mask) | (value & mask)
+}
+
+const fn slot(offset: usize) -> usize {
+    offset / 2
+}
+
+const fn build_table() -> [Option<Register>; (IO_SIZE / 2) as usize] {
+    use Owner::*;
+
+    let mut t = [None; (IO_SIZE / 2) as usize];
+
+    // LCD
+    // Bit 3 is the CGB mode flag, which only the BIOS can set
+    t[slot(0x000)] = masked("DISPCNT", Ppu, 0xFF_FF, 0xFF_F7);
+    t[slot(0x002)] = rw("GREENSWAP", Ppu, 0x00_01);
+    // The VBLANK, HBLANK and VCOUNT flags are read only
+    t[slot(0x004)] = masked("DISPSTAT", Ppu, 0xFF_3F, 0xFF_38);
+    t[slot(0x006)] = ro("VCOUNT", Ppu, 0x00_FF);
+    t[slot(0x008)] = rw("BG0CNT", Ppu, 0xDF_FF);
+    t[slot(0x00A)] = rw("BG1CNT", Ppu, 0xDF_FF);
+    t[slot(0x00C)] = rw("BG2CNT", Ppu, 0xFF_FF);
+    t[slot(0x00E)] = rw("BG3CNT", Ppu, 0xFF_FF);
+    t[slot(0x010)] = wo("BG0HOFS", Ppu, 0x01_FF);
+    t[slot(0x012)] = wo("BG0VOFS", Ppu, 0x01_FF);
+    t[slot(0x014)] = wo("BG1HOFS", Ppu, 0x01_FF);
+    t[slot(0x016)] = wo("BG1VOFS", Ppu, 0x01_FF);
+    t[slot(0x018)] = wo("BG2HOFS", Ppu, 0x01_FF);
+    t[slot(0x01A)] = wo("BG2VOFS", Ppu, 0x01_FF);
+    t[slot(0x01C)] = wo("BG3HOFS", Ppu, 0x01_FF);
+    t[slot(0x01E)] = wo("BG3VOFS", Ppu, 0x01_FF);
+    t[slot(0x020)] = wo("BG2PA", Ppu, 0xFF_FF);
+    t[slot(0x022)] = wo("BG2PB", Ppu, 0xFF_FF);
+    t[slot(0x024)] = wo("BG2PC", Ppu, 0xFF_FF);
+    t[slot(0x026)] = wo("BG2PD", Ppu, 0xFF_FF);
+    t[slot(0x028)] = wo("BG2X_L", Ppu, 0xFF_FF);
+    t[slot(0x02A)] = wo("BG2X_H", Ppu, 0x0F_FF);
+    t[slot(0x02C)] = wo("BG2Y_L", Ppu, 0xFF_FF);
+    t[slot(0x02E)] = wo("BG2Y_H", Ppu, 0x0F_FF);
+    t[slot(0x030)] = wo("BG3PA", Ppu, 0xFF_FF);
+    t[slot(0x032)] = wo("BG3PB", Ppu, 0xFF_FF);
+    t[slot(0x034)] = wo("BG3PC", Ppu, 0xFF_FF);
+    t[slot(0x036)] = wo("BG3PD", Ppu, 0xFF_FF);
+    t[slot(0x038)] = wo("BG3X_L", Ppu, 0xFF_FF);
+    t[slot(0x03A)] = wo("BG3X_H", Ppu, 0x0F_FF);
+    t[slot(0x03C)] = wo("BG3Y_L", Ppu, 0xFF_FF);
+    t[slot(0x03E)] = wo("BG3Y_H", Ppu, 0x0F_FF);
+    t[slot(0x040)] = wo("WIN0H", Ppu, 0xFF_FF);
+    t[slot(0x042)] = wo("WIN1H", Ppu, 0xFF_FF);
+    t[slot(0x044)] = wo("WIN0V", Ppu, 0xFF_FF);
+    t[slot(0x046)] = wo("WIN1V", Ppu, 0xFF_FF);
+    t[slot(0x048)] = rw("WININ", Ppu, 0x3F_3F);
+    t[slot(0x04A)] = rw("WINOUT", Ppu, 0x3F_3F);
+    t[slot(0x04C)] = wo("MOSAIC", Ppu, 0xFF_FF);
+    t[slot(0x050)] = rw("BLDCNT", Ppu, 0x3F_FF);
+    t[slot(0x052)] = rw("BLDALPHA", Ppu, 0x1F_1F);
+    t[slot(0x054)] = wo("BLDY", Ppu, 0x00_1F);
+
+    // Sound. Lengths, sweep steps and the restart bits are write only.
+    t[slot(0x060)] = rw("SOUND1CNT_L", Sound, 0x00_7F);
+    t[slot(0x062)] = masked("SOUND1CNT_H", Sound, 0xFF_C0, 0xFF_FF);
+    t[slot(0x064)] = masked("SOUND1CNT_X", Sound, 0x40_00, 0xC7_FF);
+    t[slot(0x066)] = unused();
+    t[slot(0x068)] = masked("SOUND2CNT_L", Sound, 0xFF_C0, 0xFF_FF);
+    t[slot(0x06C)] = masked("SOUND2CNT_H", Sound, 0x40_00, 0xC7_FF);
+    t[slot(0x06E)] = unused();
+    t[slot(0x070)] = rw("SOUND3CNT_L", Sound, 0x00_E0);
+    t[slot(0x072)] = masked("SOUND3CNT_H", Sound, 0xE0_00, 0xE0_FF);
+    t[slot(0x074)] = masked("SOUND3CNT_X", Sound, 0x40_00, 0xC7_FF);
+    t[slot(0x076)] = unused();
+    t[slot(0x078)] = masked("SOUND4CNT_L", Sound, 0xFF_00, 0xFF_3F);
+    t[slot(0x07C)] = masked("SOUND4CNT_H", Sound, 0x40_FF, 0xC0_FF);
+    t[slot(0x07E)] = unused();
+    t[slot(0x080)] = rw("SOUNDCNT_L", Sound, 0xFF_77);
+    // The FIFO reset bits always read as 0
+    t[slot(0x082)] = masked("SOUNDCNT_H", Sound, 0x77_0F, 0xFF_0F);
+    // The channel status bits are read only
+    t[slot(0x084)] = masked("SOUNDCNT_X", Sound, 0x00_8F, 0x00_80);
+    t[slot(0x086)] = unused();
+    t[slot(0x088)] = rw("SOUNDBIAS", Sound, 0xC3_FE);
+    t[slot(0x08A)] = unused();
+    let mut offset = 0x90;
+    while offset < 0xA0 {
+        t[slot(offset)] = rw("WAVE_RAM", Sound, 0xFF_FF);
+        offset += 2;
+    }
+    t[slot(0x0A0)] = wo("FIFO_A_L", Sound, 0xFF_FF);
+    t[slot(0x0A2)] = wo("FIFO_A_H", Sound, 0xFF_FF);
+    t[slot(0x0A4)] = wo("FIFO_B_L", Sound, 0xFF_FF);
+    t[slot(0x0A6)] = wo("FIFO_B_H", Sound, 0xFF_FF);
+
+    // DMA. DMA0 can only read from internal memory, and only DMA3 can
+    // write to the Game Pak, do long transfers and use the Game Pak DRQ.
+    let mut dma = 0;
+    while dma < 4 {
+        let base = 0x0B0 + dma * 12;
+        let src_mask = if dma == 0 { 0x07_FF } else { 0x0F_FF };
+        let dst_mask = if dma == 3 { 0x0F_FF } else { 0x07_FF };
+        let count_mask = if dma == 3 { 0xFF_FF } else { 0x3F_FF };
+        let control_mask = if dma == 3 { 0xFF_E0 } else { 0xF7_E0 };
+        t[slot(base)] = wo("DMAxSAD_L", Dma, 0xFF_FF);
+        t[slot(base + 0x2)] = wo("DMAxSAD_H", Dma, src_mask);
+        t[slot(base + 0x4)] = wo("DMAxDAD_L", Dma, 0xFF_FF);
+        t[slot(base + 0x6)] = wo("DMAxDAD_H", Dma, dst_mask);
+        t[slot(base + 0x8)] = wo("DMAxCNT_L", Dma, count_mask);
+        t[slot(base + 0xA)] = rw("DMAxCNT_H", Dma, control_mask);
+        dma += 1;
+    }
+
+    // Timers. Timer 0 has nothing to count up from.
+    let mut timer = 0;
+    while timer < 4 {
+        let base = 0x100 + timer * 4;
+        let control_mask = if timer == 0 { 0x00_C3 } else { 0x00_C7 };
+        t[slot(base)] = rw("TMxCNT_L", Timer, 0xFF_FF);
+        t[slot(base + 0x2)] = rw("TMxCNT_H", Timer, control_mask);
+        timer += 1;
+    }
+
+    // Serial communication
+    t[slot(0x120)] = rw("SIOMULTI0", Serial, 0xFF_FF);
+    t[slot(0x122)] = rw("SIOMULTI1", Serial, 0xFF_FF);
+    t[slot(0x124)] = rw("SIOMULTI2", Serial, 0xFF_FF);
+    t[slot(0x126)] = rw("SIOMULTI3", Serial, 0xFF_FF);
+    t[slot(0x128)] = rw("SIOCNT", Serial, 0x7F_FF);
+    t[slot(0x12A)] = rw("SIOMLT_SEND", Serial, 0xFF_FF);
+
+    // Keypad
+    t[slot(0x130)] = ro("KEYINPUT", Keypad, 0x03_FF);
+    t[slot(0x132)] = rw("KEYCNT", Keypad, 0xC3_FF);
+
+    t[slot(0x134)] = rw("RCNT", Serial, 0xC1_FF);
+    t[slot(0x136)] = unused();
+    t[slot(0x140)] = rw("JOYCNT", Serial, 0x00_47);
+    t[slot(0x142)] = unused();
+    t[slot(0x150)] = rw("JOY_RECV_L", Serial, 0xFF_FF);
+    t[slot(0x152)] = rw("JOY_RECV_H", Serial, 0xFF_FF);
+    t[slot(0x154)] = rw("JOY_TRANS_L", Serial, 0xFF_FF);
+    t[slot(0x156)] = rw("JOY_TRANS_H", Serial, 0xFF_FF);
+    t[slot(0x158)] = rw("JOYSTAT", Serial, 0x00_3A);
+    t[slot(0x15A)] = unused();
+
+    // Interrupts, waitstates and power down control
+    t[slot(0x200)] = rw("IE", Interrupt, 0x3F_FF);
+    t[slot(0x202)] = rw("IF", Interrupt, 0x3F_FF);
+    // Bit 15 is the Game Pak type flag, which is 0 for GBA carts
+    t[slot(0x204)] = rw("WAITCNT", Waitstate, 0x5F_FF);
+    t[slot(0x206)] = unused();
+    t[slot(0x208)] = rw("IME", Interrupt, 0x00_01);
+    t[slot(0x20A)] = unused();
+    // POSTFLG in the lower byte, the write only HALTCNT in the upper one
+    t[slot(0x300)] = masked("POSTFLG/HALTCNT", System, 0x00_01, 0xFF_01);
+
+    t
+}
@@ -15,6 +15,22 @@ impl Memory {
         }
     }
 
+    pub fn wram_board(&self) -> &[u8] {
+        &self.wram_board
+    }
+
+    pub fn wram_board_mut(&mut self) -> &mut [u8] {
+        &mut self.wram_board
+    }
+
+    pub fn wram_chip(&self) -> &[u8] {
+        &self.wram_chip
+    }
+
+    pub fn wram_chip_mut(&mut self) -> &mut [u8] {
+        &mut self.wram_chip
+    }
+
     pub fn read_wram(&self, address: u32) -> u8 {
         match address >> 24 {
             0x2 => self.wram_board[(address & 0x03_FF_FF) as usize],
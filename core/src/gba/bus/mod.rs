@@ -1,7 +1,21 @@
+use crate::gba::bios;
 use crate::gba::cartridge;
+use crate::gba::dma;
+use crate::gba::interrupt;
+use crate::gba::keypad;
 use crate::gba::ppu;
+use crate::gba::scheduler::{EventKind, Scheduler};
+use crate::gba::serial;
+use crate::gba::sound;
+use crate::gba::system;
+use crate::gba::timer;
 
+pub mod io;
 pub mod memory;
+pub mod pages;
+pub mod timing;
+
+pub use timing::Access;
 
 // I don't think I need this anymore.
 //#[derive(PartialEq, Eq)]
@@ -12,82 +26,510 @@ pub mod memory;
 //}
 
 pub struct Bus {
+    pub bios: bios::Bios,
     pub mem: memory::Memory,
     pub cartridge: cartridge::Cartridge,
     pub ppu: ppu::Ppu,
+    pub interrupt: interrupt::InterruptController,
+    pub keypad: keypad::Keypad,
+    pub system: system::SystemControl,
+    pub dma: dma::Dma,
+    pub timers: timer::Timers,
+    pub sound: sound::DirectSound,
+    pub serial: serial::Serial,
+    pub scheduler: Scheduler,
+    timing: timing::Timing,
+    pages: pages::PageTable,
+    // Cycles taken by accesses since the last call to take_cycles
+    cycles: u32,
+    // Where the next code fetch has to be to continue a sequential burst
+    next_fetch: Option<u32>,
+    // Whether the last code fetch was from the BIOS, which is the
+    // only time it can be read from.
+    executing_bios: bool,
+    // The last opcode that was fetched, which is what reads from unused
+    // memory return as nothing else drives the bus.
+    open_bus: u32,
 }
 
 impl Bus {
-    pub fn new(mem: memory::Memory, cartridge: cartridge::Cartridge, ppu: ppu::Ppu) -> Bus {
-        Bus {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        bios: bios::Bios,
+        mem: memory::Memory,
+        cartridge: cartridge::Cartridge,
+        ppu: ppu::Ppu,
+        interrupt: interrupt::InterruptController,
+        keypad: keypad::Keypad,
+        system: system::SystemControl,
+        dma: dma::Dma,
+        timers: timer::Timers,
+        sound: sound::DirectSound,
+        serial: serial::Serial,
+    ) -> Bus {
+        let mut bus = Bus {
+            bios,
             mem,
             cartridge,
             ppu,
+            interrupt,
+            keypad,
+            system,
+            dma,
+            timers,
+            sound,
+            serial,
+            scheduler: Scheduler::new(),
+            timing: timing::Timing::new(),
+            pages: pages::PageTable::new(),
+            cycles: 0,
+            next_fetch: None,
+            executing_bios: false,
+            open_bus: 0x0,
+        };
+
+        bus.scheduler
+            .schedule(EventKind::HBlankStart, ppu::CYCLES_HDRAW);
+        bus.scheduler
+            .schedule(EventKind::AudioSample, sound::CYCLES_PER_SAMPLE);
+        bus
+    }
+
+    /// Moves time forward and handles every event that has become due.
+    pub fn tick(&mut self, cycles: u32) {
+        self.scheduler.advance(cycles);
+        while let Some((event, timestamp)) = self.scheduler.pop_due() {
+            match event {
+                EventKind::HBlankStart => {
+                    self.ppu.start_hblank(&mut self.interrupt);
+                    self.ppu.render_line();
+
+                    // HBlank DMAs don't run during VBlank
+                    let v_count = self.ppu.v_count();
+                    if v_count < ppu::LINES_VISIBLE {
+                        self.dma.request(dma::Timing::HBlank);
+                    }
+                    self.dma.request_video_capture(v_count);
+
+                    self.scheduler
+                        .schedule_at(EventKind::HBlankEnd, timestamp + ppu::CYCLES_HBLANK);
+                }
+                EventKind::HBlankEnd => {
+                    self.ppu.end_hblank(&mut self.interrupt);
+                    if self.ppu.v_count() == ppu::LINES_VISIBLE {
+                        self.dma.request(dma::Timing::VBlank);
+                    }
+
+                    self.scheduler
+                        .schedule_at(EventKind::HBlankStart, timestamp + ppu::CYCLES_HDRAW);
+                }
+                EventKind::TimerOverflow(id) => {
+                    let overflowed = self.timers.overflow(
+                        id,
+                        timestamp,
+                        &mut self.scheduler,
+                        &mut self.interrupt,
+                    );
+                    // Only timers 0 and 1 can clock the sound FIFOs
+                    for timer in 0..2 {
+                        if (overflowed >> timer) & 0x01 == 1 {
+                            self.sound.timer_overflow(timer, &mut self.dma);
+                        }
+                    }
+                }
+                EventKind::AudioSample => {
+                    self.sound.generate_sample();
+                    self.scheduler
+                        .schedule_at(EventKind::AudioSample, timestamp + sound::CYCLES_PER_SAMPLE);
+                }
+                EventKind::IrqLine => self.interrupt.raise_line(),
+            }
+        }
+        self.interrupt.update_line(&mut self.scheduler);
+    }
+
+    /// Returns the cycles used up by memory accesses and internal cycles
+    /// since the last call.
+    pub fn take_cycles(&mut self) -> u32 {
+        std::mem::take(&mut self.cycles)
+    }
+
+    /// Internal cycles where the CPU doesn't touch the bus
+    pub fn idle(&mut self, cycles: u32) {
+        self.charge(cycles);
+    }
+
+    // The prefetcher gets to use the Game Pak whenever the CPU isn't
+    fn charge(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        self.cartridge.prefetch.run(cycles);
+    }
+
+    // Code fetches work out whether they're sequential by themselves, as
+    // any data access or jump in between breaks the burst.
+    fn fetch_access(&mut self, address: u32, width: u32) -> Access {
+        let access = if self.next_fetch == Some(address) {
+            Access::Sequential
+        } else {
+            Access::NonSequential
+        };
+        self.next_fetch = Some(address.wrapping_add(width));
+        access
+    }
+
+    fn charge_fetch(&mut self, address: u32, width: u32) {
+        let access = self.fetch_access(address, width);
+        if !is_game_pak(address) {
+            self.charge(self.timing.cycles(address, width, access));
+            return;
+        }
+
+        if self.timing.prefetch_enabled() {
+            if let Some(cycles) = self.cartridge.prefetch.fetch(address, width) {
+                self.cycles += cycles;
+                return;
+            }
+
+            // A miss, so the prefetcher starts over from the next opcode
+            self.cycles += self.timing.cycles(address, width, access);
+            let seq_cycles = self.timing.cycles(address, 2, Access::Sequential);
+            self.cartridge
+                .prefetch
+                .start(address.wrapping_add(width), seq_cycles);
+        } else {
+            self.cycles += self.timing.cycles(address, width, access);
+        }
+    }
+
+    fn track_bios_fetch(&mut self, address: u32) {
+        self.executing_bios = address < bios::BIOS_SIZE as u32;
+        if self.executing_bios {
+            self.bios.fetch(address);
+        }
+    }
+
+    pub fn fetch_word(&mut self, address: u32) -> u32 {
+        self.charge_fetch(address, 4);
+        self.track_bios_fetch(address);
+        self.open_bus = self.load_word(address);
+        self.open_bus
+    }
+
+    // In Thumb state the opcode ends up on both halves of the bus. That's
+    // not quite true for BIOS, OAM and IWRAM but close enough.
+    pub fn fetch_half(&mut self, address: u32) -> u32 {
+        self.charge_fetch(address, 2);
+        self.track_bios_fetch(address);
+        let opcode = self.load_half(address);
+        self.open_bus = opcode | (opcode << 16);
+        opcode
+    }
+
+    fn data_access(&mut self, address: u32, width: u32, access: Access) {
+        self.next_fetch = None;
+        let cycles = self.timing.cycles(address, width, access);
+        if is_game_pak(address) {
+            // Data accesses need the Game Pak bus, which stops the prefetch
+            self.cartridge.prefetch.flush();
+            self.cycles += cycles;
+        } else {
+            self.charge(cycles);
+        }
+    }
+
+    pub fn read_word(&mut self, address: u32, access: Access) -> u32 {
+        self.data_access(address, 4, access);
+        self.load_word(address)
+    }
+
+    pub fn read_half(&mut self, address: u32, access: Access) -> u32 {
+        self.data_access(address, 2, access);
+        self.load_half(address)
+    }
+
+    pub fn read_byte(&mut self, address: u32, access: Access) -> u8 {
+        self.data_access(address, 1, access);
+        self.load_byte(address)
+    }
+
+    pub fn write_word(&mut self, address: u32, value: u32, access: Access) {
+        self.data_access(address, 4, access);
+        self.store_word(address, value);
+    }
+
+    pub fn write_half(&mut self, address: u32, value: u32, access: Access) {
+        self.data_access(address, 2, access);
+        self.store_half(address, value);
+    }
+
+    pub fn write_byte(&mut self, address: u32, value: u8, access: Access) {
+        self.data_access(address, 1, access);
+
+        // Video memory is 16 bits wide. Byte writes either end up in
+        // both halves of the halfword or are ignored altogether.
+        let duplicated = u32::from(value) * 0x01_01;
+        match address >> 24 {
+            0x05 => self.store_half(address, duplicated),
+            0x06 if self.ppu.vram_byte_writable(address) => self.store_half(address, duplicated),
+            0x06 | 0x07 => log::debug!("Ignoring byte write to {:#2X}", address),
+            _ => self.store_byte(address, value),
         }
     }
 
-    pub fn read_word(&mut self, address: u32) -> u32 {
+    fn load_word(&mut self, address: u32) -> u32 {
+        if let Some(bytes) = self.read_direct(address & !3) {
+            return u32::from_le_bytes(bytes).rotate_right((address & 3) << 3);
+        }
+
+        // SRAM is on an 8 bit bus, so wider reads get the same byte repeated
+        if is_sram(address) {
+            return u32::from(self.cartridge.read_sram(address)) * 0x01_01_01_01;
+        }
+
         // Memory reads need to be aligned as per
         // https://problemkaputt.de/gbatek-arm-cpu-memory-alignments.htm
         // Reads from forcibly aligned addresses need to be rotated
         // by the amount it was mis-aligned * 8, hence the shift val.
         let aligned_addr = address & !3;
         let shift = address & 3;
-        let value = u32::from(self.read_byte(aligned_addr))
-            | u32::from(self.read_byte(aligned_addr | 1)) << 8
-            | u32::from(self.read_byte(aligned_addr | 2)) << 16
-            | u32::from(self.read_byte(aligned_addr | 3)) << 24;
+        let value = if is_io(address) {
+            u32::from(self.read_io(aligned_addr)) | u32::from(self.read_io(aligned_addr | 2)) << 16
+        } else {
+            u32::from(self.load_byte(aligned_addr))
+                | u32::from(self.load_byte(aligned_addr | 1)) << 8
+                | u32::from(self.load_byte(aligned_addr | 2)) << 16
+                | u32::from(self.load_byte(aligned_addr | 3)) << 24
+        };
 
         value.rotate_right(shift << 3)
     }
 
-    pub fn read_half(&mut self, address: u32) -> u32 {
+    fn load_half(&mut self, address: u32) -> u32 {
+        // The EEPROM only ever gets DMA'd to and from a halfword at a time
+        if self.cartridge.is_eeprom(address) {
+            return u32::from(self.cartridge.read_eeprom());
+        }
+
+        if let Some(bytes) = self.read_direct(address & !1) {
+            return u32::from(u16::from_le_bytes(bytes)).rotate_right((address & 1) << 3);
+        }
+
+        if is_sram(address) {
+            return u32::from(self.cartridge.read_sram(address)) * 0x01_01;
+        }
+
         let aligned_addr = address & !1;
         let shift = address & 1;
         log::debug!("Aligned address is {:#2X}", aligned_addr);
-        let value = u32::from(self.read_byte(aligned_addr))
-            | u32::from(self.read_byte(aligned_addr | 1)) << 8;
+        let value = if is_io(address) {
+            u32::from(self.read_io(aligned_addr))
+        } else {
+            u32::from(self.load_byte(aligned_addr))
+                | u32::from(self.load_byte(aligned_addr | 1)) << 8
+        };
 
         // TODO: Might need to do more stuff here
 
         value.rotate_right(shift << 3)
     }
 
-    pub fn read_byte(&mut self, address: u32) -> u8 {
+    fn region(&self, region: pages::Region) -> &[u8] {
+        match region {
+            pages::Region::Bios => self.bios.data(),
+            pages::Region::BoardWram => self.mem.wram_board(),
+            pages::Region::ChipWram => self.mem.wram_chip(),
+            pages::Region::Vram => self.ppu.vram(),
+            pages::Region::Rom => self.cartridge.rom(),
+        }
+    }
+
+    fn region_mut(&mut self, region: pages::Region) -> &mut [u8] {
+        match region {
+            pages::Region::BoardWram => self.mem.wram_board_mut(),
+            pages::Region::ChipWram => self.mem.wram_chip_mut(),
+            pages::Region::Vram => self.ppu.vram_mut(),
+            _ => unreachable!("{:?} is never mapped for writing", region),
+        }
+    }
+
+    // Reads an aligned halfword or word straight out of the page table,
+    // or None if it has to go through the slow path.
+    fn read_direct<const N: usize>(&self, address: u32) -> Option<[u8; N]> {
+        let (region, offset) = self.pages.read(address)?;
+        if region == pages::Region::Bios && !self.executing_bios {
+            return None;
+        }
+        if region == pages::Region::Rom && self.cartridge.is_gpio(address) {
+            return None;
+        }
+        self.region(region).get(offset..offset + N)?.try_into().ok()
+    }
+
+    // Returns whether the write could be done through the page table
+    fn write_direct<const N: usize>(&mut self, address: u32, bytes: [u8; N]) -> bool {
+        let Some((region, offset)) = self.pages.write(address) else {
+            return false;
+        };
+        self.region_mut(region)[offset..offset + N].copy_from_slice(&bytes);
+        true
+    }
+
+    // https://problemkaputt.de/gbatek-gba-memory-map.htm
+    fn load_byte(&mut self, address: u32) -> u8 {
         match address >> 24 {
-            0x08..=0x0B => self.cartridge.read_rom(address),
-            0x06 => self.ppu.read_vram(address),
-            // This isn't necessarily right because some io registers belong to
-            // sound channels I believe. Need to check for that?
-            0x04 => self.ppu.read_io(address),
+            0x00 if (address as usize) < bios::BIOS_SIZE => {
+                self.bios.read(address, self.executing_bios)
+            }
             0x02..=0x03 => self.mem.read_wram(address),
-            _ => unimplemented!("Invalid address {:#2X}!", address),
+            0x04 if is_io(address) => (self.read_io(address & !1) >> ((address & 1) * 8)) as u8,
+            0x05 => self.ppu.read_pram(address),
+            0x06 => self.ppu.read_vram(address),
+            0x07 => self.ppu.read_oam(address),
+            0x08..=0x0D => self.cartridge.read_rom(address),
+            0x0E..=0x0F => self.cartridge.read_sram(address),
+            _ => {
+                log::debug!("Open bus read from {:#2X}", address);
+                (self.open_bus >> ((address & 3) * 8)) as u8
+            }
         }
     }
 
-    pub fn write_word(&mut self, address: u32, value: u32) {
+    // Reads the I/O register at the halfword aligned `address`
+    fn read_io(&self, address: u32) -> u16 {
+        let Some(register) = io::register(address) else {
+            log::debug!("Open bus read from unused I/O {:#2X}", address);
+            return (self.open_bus >> ((address & 2) * 8)) as u16;
+        };
+
+        let value = match register.owner {
+            io::Owner::Ppu => self.ppu.read_io(address),
+            io::Owner::Sound => self.sound.read_io(address),
+            io::Owner::Dma => self.dma.read_io(address),
+            io::Owner::Timer => self.timers.read_io(address, &self.scheduler),
+            io::Owner::Serial => self.serial.read_io(address),
+            io::Owner::Keypad => self.keypad.read_io(address),
+            io::Owner::Interrupt => self.interrupt.read_io(address),
+            io::Owner::Waitstate => self.timing.read_io(),
+            io::Owner::System => self.system.read_io(),
+            io::Owner::Unused => 0x0,
+        };
+        value & register.readable
+    }
+
+    fn store_word(&mut self, address: u32, value: u32) {
+        if self.write_direct(address & !3, value.to_le_bytes()) {
+            return;
+        }
+
+        // Only the byte at the address makes it into SRAM
+        if is_sram(address) {
+            let value = value.rotate_right((address & 3) * 8);
+            self.cartridge.write_sram(address, value as u8);
+            return;
+        }
+
         let aligned_addr = address & !3;
 
-        self.write_byte(aligned_addr, value as u8);
-        self.write_byte(aligned_addr | 1, (value >> 8) as u8);
-        self.write_byte(aligned_addr | 2, (value >> 16) as u8);
-        self.write_byte(aligned_addr | 3, (value >> 24) as u8);
+        if is_io(address) {
+            self.write_io(aligned_addr, value as u16, 0xFF_FF);
+            self.write_io(aligned_addr | 2, (value >> 16) as u16, 0xFF_FF);
+            return;
+        }
+
+        self.store_byte(aligned_addr, value as u8);
+        self.store_byte(aligned_addr | 1, (value >> 8) as u8);
+        self.store_byte(aligned_addr | 2, (value >> 16) as u8);
+        self.store_byte(aligned_addr | 3, (value >> 24) as u8);
     }
 
-    pub fn write_half(&mut self, address: u32, value: u32) {
+    fn store_half(&mut self, address: u32, value: u32) {
+        if self.cartridge.is_eeprom(address) {
+            self.cartridge.write_eeprom(value as u16);
+            return;
+        }
+
+        if self.write_direct(address & !1, (value as u16).to_le_bytes()) {
+            return;
+        }
+
+        if is_sram(address) {
+            let value = value >> ((address & 1) * 8);
+            self.cartridge.write_sram(address, value as u8);
+            return;
+        }
+
         let aligned_addr = address & !1;
 
-        self.write_byte(aligned_addr, value as u8);
-        self.write_byte(aligned_addr | 1, (value >> 8) as u8);
+        if is_io(address) {
+            self.write_io(aligned_addr, value as u16, 0xFF_FF);
+            return;
+        }
+
+        self.store_byte(aligned_addr, value as u8);
+        self.store_byte(aligned_addr | 1, (value >> 8) as u8);
     }
 
-    pub fn write_byte(&mut self, address: u32, value: u8) {
+    fn store_byte(&mut self, address: u32, value: u8) {
         match address >> 24 {
-            0x06 => self.ppu.write_vram(address, value),
-            0x05 => self.ppu.write_pram(address, value),
-            0x04 => self.ppu.write_io(address, value),
             0x02..=0x03 => self.mem.write_wram(address, value),
-            _ => unimplemented!("Invalid address {:#2X}!", address),
+            0x04 if is_io(address) => {
+                let shift = (address & 1) * 8;
+                self.write_io(address & !1, u16::from(value) << shift, 0xFF << shift);
+            }
+            0x05 => self.ppu.write_pram(address, value),
+            0x06 => self.ppu.write_vram(address, value),
+            0x07 => self.ppu.write_oam(address, value),
+            0x08..=0x0D => self.cartridge.write_rom(address, value),
+            0x0E..=0x0F => self.cartridge.write_sram(address, value),
+            // The BIOS is read only, and nothing else is mapped
+            _ => log::debug!("Ignoring write to {:#2X}", address),
         }
     }
+
+    // Writes the bytes of `value` selected by `mask` to the I/O register
+    // at the halfword aligned `address`.
+    fn write_io(&mut self, address: u32, value: u16, mask: u16) {
+        let Some(register) = io::register(address) else {
+            log::debug!("Ignoring write to unused I/O {:#2X}", address);
+            return;
+        };
+
+        let mask = mask & register.writable;
+        if mask == 0 {
+            log::debug!("Ignoring write to read only {}", register.name);
+            return;
+        }
+
+        match register.owner {
+            io::Owner::Ppu => self.ppu.write_io(address, value, mask),
+            io::Owner::Sound => self.sound.write_io(address, value, mask),
+            io::Owner::Dma => self.dma.write_io(address, value, mask),
+            io::Owner::Timer => self
+                .timers
+                .write_io(address, value, mask, &mut self.scheduler),
+            io::Owner::Serial => self.serial.write_io(address, value, mask),
+            io::Owner::Keypad => self.keypad.write_io(value, mask, &mut self.interrupt),
+            io::Owner::Interrupt => self.interrupt.write_io(address, value, mask),
+            io::Owner::Waitstate => {
+                self.timing.write_io(value, mask);
+                self.cartridge.prefetch.flush();
+            }
+            io::Owner::System => self.system.write_io(value, mask),
+            io::Owner::Unused => {}
+        }
+    }
+}
+
+fn is_io(address: u32) -> bool {
+    address >> 24 == 0x04 && address & 0x00_FF_FF_FF < io::IO_SIZE
+}
+
+fn is_sram(address: u32) -> bool {
+    (0x0E..=0x0F).contains(&(address >> 24))
+}
+
+// ROM in any of the three waitstate regions, which is what the
+// prefetcher reads from.
+fn is_game_pak(address: u32) -> bool {
+    (0x08..=0x0D).contains(&(address >> 24))
 }
@@ -0,0 +1,132 @@
+// Page tables for the regions that are plain memory, so that aligned
+// halfword and word accesses can go straight to the backing buffer instead
+// of being put together a byte at a time. Anything with side effects or
+// odd access rules (I/O, palette RAM, OAM, SRAM) isn't in here and goes
+// through the slow path in the bus.
+
+// 16kb pages, the size of the smallest region we map
+const PAGE_SHIFT: u32 = 14;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+const PAGE_MASK: usize = PAGE_SIZE - 1;
+
+// Only the lower 28 bits of an address select memory, nothing above that
+// is mapped.
+const ADDRESS_BITS: u32 = 28;
+const PAGE_COUNT: usize = 1 << (ADDRESS_BITS - PAGE_SHIFT);
+
+/// Which buffer a page points into
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Region {
+    Bios,
+    BoardWram,
+    ChipWram,
+    Vram,
+    Rom,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Page {
+    pub region: Region,
+    // Where the page starts in the buffer
+    pub offset: usize,
+}
+
+pub struct PageTable {
+    read: Vec<Option<Page>>,
+    write: Vec<Option<Page>>,
+}
+
+impl PageTable {
+    pub fn new() -> PageTable {
+        let mut table = PageTable {
+            read: vec![None; PAGE_COUNT],
+            write: vec![None; PAGE_COUNT],
+        };
+
+        // The BIOS can only be read while it's executing, which the bus
+        // checks before using this page.
+        table.set(
+            0x00_00_00_00,
+            Page {
+                region: Region::Bios,
+                offset: 0,
+            },
+            false,
+        );
+        for base in (0x02_00_00_00..0x04_00_00_00).step_by(PAGE_SIZE) {
+            let page = if base >> 24 == 0x02 {
+                Page {
+                    region: Region::BoardWram,
+                    offset: base & 0x03_FF_FF,
+                }
+            } else {
+                Page {
+                    region: Region::ChipWram,
+                    offset: base & 0x00_7F_FF,
+                }
+            };
+            table.set(base, page, true);
+        }
+
+        // VRAM is mirrored every 128kb, with the last 32kb of each mirror
+        // being the same as the 32kb before them.
+        for base in (0x06_00_00_00..0x07_00_00_00).step_by(PAGE_SIZE) {
+            let mut offset = base & 0x01_FF_FF;
+            if offset >= 0x1_80_00 {
+                offset -= 0x80_00;
+            }
+            table.set(
+                base,
+                Page {
+                    region: Region::Vram,
+                    offset,
+                },
+                true,
+            );
+        }
+
+        // ROM is mirrored across all three waitstate regions. Reads past
+        // the end of the image don't fit in the buffer and fall back to
+        // the slow path. 0xD000000 is left out as the EEPROM can be there,
+        // and for 32mb ROMs the top of the ROM shares it with the EEPROM.
+        for base in (0x08_00_00_00..0x0D_00_00_00).step_by(PAGE_SIZE) {
+            table.set(
+                base,
+                Page {
+                    region: Region::Rom,
+                    offset: base & 0x01_FF_FF_FF,
+                },
+                false,
+            );
+        }
+
+        table
+    }
+
+    fn set(&mut self, base: usize, page: Page, writable: bool) {
+        let index = base >> PAGE_SHIFT;
+        self.read[index] = Some(page);
+        if writable {
+            self.write[index] = Some(page);
+        }
+    }
+
+    /// The region and offset `address` can be read from directly, if any
+    pub fn read(&self, address: u32) -> Option<(Region, usize)> {
+        Self::lookup(&self.read, address)
+    }
+
+    /// The region and offset `address` can be written to directly, if any
+    pub fn write(&self, address: u32) -> Option<(Region, usize)> {
+        Self::lookup(&self.write, address)
+    }
+
+    fn lookup(pages: &[Option<Page>], address: u32) -> Option<(Region, usize)> {
+        if address >> ADDRESS_BITS != 0 {
+            return None;
+        }
+        let address = address as usize;
+        let page = pages[address >> PAGE_SHIFT]?;
+        Some((page.region, page.offset + (address & PAGE_MASK)))
+    }
+}
@@ -0,0 +1,118 @@
+// Memory access timings per region, see here:
+// https://problemkaputt.de/gbatek-gba-memory-map.htm
+// https://problemkaputt.de/gbatek-gba-system-control.htm
+
+use super::io;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    NonSequential,
+    Sequential,
+}
+
+// Waitstate settings selectable through WAITCNT
+const SRAM_WAITS: [u32; 4] = [4, 3, 2, 8];
+const WS_NONSEQ_WAITS: [u32; 4] = [4, 3, 2, 8];
+const WS0_SEQ_WAITS: [u32; 2] = [2, 1];
+const WS1_SEQ_WAITS: [u32; 2] = [4, 1];
+const WS2_SEQ_WAITS: [u32; 2] = [8, 1];
+
+pub struct Timing {
+    // WAITCNT at 0x4000204
+    waitcnt: u16,
+    // Cycles for 8/16 bit and 32 bit accesses, indexed by address >> 24
+    nonseq16: [u32; 16],
+    seq16: [u32; 16],
+    nonseq32: [u32; 16],
+    seq32: [u32; 16],
+}
+
+impl Timing {
+    pub fn new() -> Timing {
+        let mut timing = Timing {
+            waitcnt: 0x0,
+            // BIOS, unused, EWRAM, IWRAM, IO, palette, VRAM and OAM never change
+            nonseq16: [1, 1, 3, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            seq16: [1, 1, 3, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            // EWRAM, palette and VRAM sit on a 16 bit bus, so 32 bit
+            // accesses are split into two.
+            nonseq32: [1, 1, 6, 1, 1, 2, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            seq32: [1, 1, 6, 1, 1, 2, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+        };
+        timing.update_waitstates();
+        timing
+    }
+
+    /// Cycles taken by an access of `width` bytes at `address`
+    pub fn cycles(&self, address: u32, width: u32, access: Access) -> u32 {
+        let region = ((address >> 24) & 0x0F) as usize;
+
+        // The Game Pak can't keep a burst going across a 128kb boundary
+        let access = if (0x08..=0x0D).contains(&region) && address & 0x1_FF_FF == 0 {
+            Access::NonSequential
+        } else {
+            access
+        };
+
+        match (width, access) {
+            (4, Access::NonSequential) => self.nonseq32[region],
+            (4, Access::Sequential) => self.seq32[region],
+            (_, Access::NonSequential) => self.nonseq16[region],
+            (_, Access::Sequential) => self.seq16[region],
+        }
+    }
+
+    /// Whether the Game Pak prefetch buffer is enabled
+    pub fn prefetch_enabled(&self) -> bool {
+        (self.waitcnt >> 14) & 0x01 == 1
+    }
+
+    fn update_waitstates(&mut self) {
+        let sram = 1 + SRAM_WAITS[(self.waitcnt & 0b11) as usize];
+        let ws = [
+            (
+                (self.waitcnt >> 2) & 0b11,
+                WS0_SEQ_WAITS[((self.waitcnt >> 4) & 0x01) as usize],
+            ),
+            (
+                (self.waitcnt >> 5) & 0b11,
+                WS1_SEQ_WAITS[((self.waitcnt >> 7) & 0x01) as usize],
+            ),
+            (
+                (self.waitcnt >> 8) & 0b11,
+                WS2_SEQ_WAITS[((self.waitcnt >> 10) & 0x01) as usize],
+            ),
+        ];
+
+        for (i, (nonseq, seq)) in ws.iter().enumerate() {
+            let nonseq = 1 + WS_NONSEQ_WAITS[*nonseq as usize];
+            let seq = 1 + seq;
+            // Each waitstate covers two 16MB regions
+            for region in [0x08 + i * 2, 0x09 + i * 2] {
+                self.nonseq16[region] = nonseq;
+                self.seq16[region] = seq;
+                // The Game Pak bus is 16 bits wide, so the second half
+                // of a 32 bit access is always sequential.
+                self.nonseq32[region] = nonseq + seq;
+                self.seq32[region] = seq * 2;
+            }
+        }
+
+        // SRAM has an 8 bit bus, but is only meant to be accessed by bytes
+        for region in [0x0E, 0x0F] {
+            self.nonseq16[region] = sram;
+            self.seq16[region] = sram;
+            self.nonseq32[region] = sram;
+            self.seq32[region] = sram;
+        }
+    }
+
+    pub fn read_io(&self) -> u16 {
+        self.waitcnt
+    }
+
+    pub fn write_io(&mut self, value: u16, mask: u16) {
+        self.waitcnt = io::merge(self.waitcnt, value, mask);
+        self.update_waitstates();
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use super::{LoadError, MAX_ROM_SIZE};
+
+// ROMs that come compressed. They're told apart by their magic numbers
+// rather than the file extension, so renamed files still load.
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = b"\x1F\x8B";
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+
+// How much of a 7z we're willing to decompress and throw away while
+// looking for the ROM, so a bad archive can't keep us busy forever
+const MAX_SKIPPED_SIZE: u64 = 4 * MAX_ROM_SIZE as u64;
+
+// What the ROM in an archive can be called, if we're left to find it
+const ROM_EXTENSIONS: [&str; 3] = ["gba", "agb", "bin"];
+
+/// Pulls the ROM out of `data` if it's an archive, otherwise hands it
+/// back as it is. `entry` picks which file in the archive to use, which
+/// otherwise is the first one that looks like a ROM.
+pub fn extract(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    if data.starts_with(ZIP_MAGIC) {
+        extract_zip(&data, entry)
+    } else if data.starts_with(SEVEN_ZIP_MAGIC) {
+        extract_7z(&data, entry)
+    } else if data.starts_with(GZIP_MAGIC) {
+        // Only ever holds the one file, so there's nothing to pick
+        read_limited(flate2::read::GzDecoder::new(data.as_slice()))
+    } else {
+        Ok(data)
+    }
+}
+
+fn extract_zip(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(archive_error)?;
+    for index in 0..archive.len() {
+        let file = archive.by_index(index).map_err(archive_error)?;
+        if file.is_file() && wanted(file.name(), entry) {
+            log::info!("Loading {} from zip", file.name());
+            return read_limited(file);
+        }
+    }
+    Err(missing(entry))
+}
+
+fn extract_7z(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    let mut archive = sevenz_rust::SevenZReader::new(
+        Cursor::new(data),
+        data.len() as u64,
+        sevenz_rust::Password::empty(),
+    )
+    .map_err(archive_error)?;
+
+    let mut rom = None;
+    let mut skipped = 0;
+    archive
+        .for_each_entries(|file, reader| {
+            // Returning false only stops the current folder, not the rest
+            if rom.is_some() {
+                return Ok(false);
+            }
+            if file.is_directory() || !wanted(file.name(), entry) {
+                // Files in a solid archive can only be decompressed in
+                // order, so the ones we skip still have to be read
+                let limit = MAX_SKIPPED_SIZE - skipped;
+                skipped += std::io::copy(&mut reader.take(limit + 1), &mut std::io::sink())?;
+                if skipped > MAX_SKIPPED_SIZE {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "too much to decompress before the ROM",
+                    )
+                    .into());
+                }
+                return Ok(true);
+            }
+            log::info!("Loading {} from 7z", file.name());
+            rom = Some(read_limited(reader));
+            Ok(false)
+        })
+        .map_err(archive_error)?;
+    rom.unwrap_or_else(|| Err(missing(entry)))
+}
+
+// Whether the file called `name` is the one to load
+fn wanted(name: &str, entry: Option<&str>) -> bool {
+    match entry {
+        Some(entry) => name == entry,
+        None => Path::new(name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                ROM_EXTENSIONS
+                    .iter()
+                    .any(|rom| extension.eq_ignore_ascii_case(rom))
+            }),
+    }
+}
+
+// Stops a bad archive from decompressing to something huge. Anything that
+// goes past the limit gets turned down as too large later on.
+fn read_limited(reader: impl Read) -> Result<Vec<u8>, LoadError> {
+    let mut rom = Vec::new();
+    reader
+        .take(MAX_ROM_SIZE as u64 + 1)
+        .read_to_end(&mut rom)
+        .map_err(archive_error)?;
+    Ok(rom)
+}
+
+fn missing(entry: Option<&str>) -> LoadError {
+    LoadError::NoRomInArchive(entry.map(str::to_string))
+}
+
+fn archive_error(e: impl std::fmt::Display) -> LoadError {
+    LoadError::Archive(e.to_string())
+}
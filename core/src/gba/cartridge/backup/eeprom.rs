@@ -0,0 +1,195 @@
+// EEPROM backup, see here:
+// https://problemkaputt.de/gbatek-gba-cart-backup-eeprom.htm
+//
+// The EEPROM talks one bit at a time over bit 0 of the data bus, which
+// games drive with DMA3. A request starts with two bits for the command,
+// then the address of the 8 byte block and, for writes, the 64 bits to be
+// written. It ends with a 0 bit. Reads then get 4 junk bits followed by
+// the 64 bits of the block.
+//
+// Nothing in the ROM says how wide the address is, so that's worked out
+// from the length of the first DMA that talks to the EEPROM.
+
+const BLOCK_SIZE: usize = 8;
+
+// 4 bits nobody cares about, then the block
+const READ_BITS: u32 = 4 + 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EepromSize {
+    // 512 bytes with a 6 bit address
+    Small,
+    // 8kb with a 14 bit address, of which only 10 bits are used
+    Large,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // Taking in a request, `bits` of which have arrived so far
+    Request { bits: u32 },
+    // Sending out the block at `offset`, `bits` of it already gone
+    Reading { offset: usize, bits: u32 },
+}
+
+pub struct Eeprom {
+    size: Option<EepromSize>,
+    data: Vec<u8>,
+    state: State,
+    // The request bits received so far, the latest one being bit 0
+    buffer: u128,
+}
+
+impl EepromSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            EepromSize::Small => 512,
+            EepromSize::Large => 8 * 1024,
+        }
+    }
+
+    fn address_bits(self) -> u32 {
+        match self {
+            EepromSize::Small => 6,
+            EepromSize::Large => 14,
+        }
+    }
+
+    // Requests are 2 command bits, the address and the stop bit, plus 64
+    // data bits for writes
+    fn from_dma_count(count: u32) -> Option<EepromSize> {
+        match count {
+            9 | 73 => Some(EepromSize::Small),
+            17 | 81 => Some(EepromSize::Large),
+            _ => None,
+        }
+    }
+}
+
+impl Eeprom {
+    pub fn new(size: Option<EepromSize>) -> Eeprom {
+        Eeprom {
+            size,
+            // Big enough for either size until we know which one it is
+            data: vec![0xFF; EepromSize::Large.bytes()],
+            state: State::Request { bits: 0 },
+            buffer: 0,
+        }
+    }
+
+    pub fn size(&self) -> Option<EepromSize> {
+        self.size
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.size.unwrap_or(EepromSize::Large).bytes()]
+    }
+
+    /// Fills the EEPROM from a save, which also tells us how big it is
+    pub fn load(&mut self, data: &[u8]) {
+        if self.size.is_none() {
+            self.size = Some(if data.len() <= EepromSize::Small.bytes() {
+                EepromSize::Small
+            } else {
+                EepromSize::Large
+            });
+        }
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Called when DMA3 starts writing `count` units to the EEPROM, which
+    /// is how we find out the size of it.
+    pub fn detect_size(&mut self, count: u32) {
+        if self.size.is_some() {
+            return;
+        }
+        self.size = EepromSize::from_dma_count(count);
+        if let Some(size) = self.size {
+            log::info!("Detected {} byte EEPROM", size.bytes());
+        }
+    }
+
+    pub fn read(&mut self) -> u16 {
+        match self.state {
+            State::Reading { offset, bits } => {
+                let bit = if bits < 4 {
+                    0
+                } else {
+                    let index = (bits - 4) as usize;
+                    let byte = self.data[offset + index / 8];
+                    (byte >> (7 - index % 8)) & 0x01
+                };
+
+                self.state = if bits + 1 == READ_BITS {
+                    State::Request { bits: 0 }
+                } else {
+                    State::Reading {
+                        offset,
+                        bits: bits + 1,
+                    }
+                };
+                u16::from(bit)
+            }
+            // Writes finish straight away, so we're always ready
+            State::Request { .. } => 1,
+        }
+    }
+
+    /// Returns whether the bit finished a write request
+    pub fn write(&mut self, value: u16) -> bool {
+        // A new request cancels a read that's in progress
+        let bits = match self.state {
+            State::Request { bits } => bits,
+            State::Reading { .. } => 0,
+        };
+        if bits == 0 {
+            self.buffer = 0;
+        }
+        self.buffer = (self.buffer << 1) | u128::from(value & 0x01);
+        let bits = bits + 1;
+        self.state = State::Request { bits };
+
+        if bits < 2 {
+            return false;
+        }
+
+        let size = self.size.unwrap_or_else(|| {
+            log::warn!("EEPROM size isn't known yet, assuming 8kb");
+            self.size = Some(EepromSize::Large);
+            EepromSize::Large
+        });
+        let address_bits = size.address_bits();
+        let command = (self.buffer >> (bits - 2)) & 0b11;
+        match command {
+            // Read request
+            0b11 if bits == 2 + address_bits + 1 => {
+                let block = (self.buffer >> 1) as usize;
+                self.state = State::Reading {
+                    offset: block_offset(block, size),
+                    bits: 0,
+                };
+            }
+            // Write request
+            0b10 if bits == 2 + address_bits + 64 + 1 => {
+                let block = (self.buffer >> 65) as usize;
+                let offset = block_offset(block, size);
+                let value = (self.buffer >> 1) as u64;
+                self.data[offset..offset + BLOCK_SIZE].copy_from_slice(&value.to_be_bytes());
+                self.state = State::Request { bits: 0 };
+                return true;
+            }
+            0b11 | 0b10 if bits < 2 + address_bits + 64 + 1 => {}
+            _ => {
+                log::debug!("Unexpected EEPROM request {:#b}", self.buffer);
+                self.state = State::Request { bits: 0 };
+            }
+        }
+        false
+    }
+}
+
+// The address also has the command bits above it, which get masked off here
+fn block_offset(block: usize, size: EepromSize) -> usize {
+    let blocks = size.bytes() / BLOCK_SIZE;
+    (block & (blocks - 1)) * BLOCK_SIZE
+}
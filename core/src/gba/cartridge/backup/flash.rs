@@ -0,0 +1,161 @@
+// Flash backup, see here:
+// https://problemkaputt.de/gbatek-gba-cart-backup-flash-rom.htm
+//
+// Every command is prefixed with 0xAA to 0x5555 and 0x55 to 0x2AAA. Writes
+// and erases finish straight away, so games polling for them to be done
+// see them done on the first read.
+
+const BANK_SIZE: usize = 64 * 1024;
+const SECTOR_SIZE: usize = 4 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashChip {
+    // 64kb chips
+    Macronix64K,
+    Panasonic,
+    Sst,
+    // 128kb chips, which have two banks
+    Macronix128K,
+    Sanyo,
+}
+
+impl FlashChip {
+    /// The chips games get when the override table doesn't name one
+    pub const DEFAULT_64K: FlashChip = FlashChip::Panasonic;
+    pub const DEFAULT_128K: FlashChip = FlashChip::Sanyo;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ready,
+    // Got 0xAA at 0x5555
+    Unlock1,
+    // Got 0x55 at 0x2AAA, waiting for the command
+    Unlock2,
+    // Erases need a second unlock sequence before saying what to erase
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+    // The next write is the byte to program
+    Write,
+    // The next write to 0x0000 selects the bank
+    Bank,
+}
+
+pub struct Flash {
+    chip: FlashChip,
+    data: Vec<u8>,
+    bank: usize,
+    state: State,
+    // While set the first two bytes read back as the chip ID
+    id_mode: bool,
+}
+
+impl FlashChip {
+    /// The manufacturer and device ID the chip identifies itself with
+    pub fn id(self) -> (u8, u8) {
+        match self {
+            FlashChip::Macronix64K => (0xC2, 0x1C),
+            FlashChip::Panasonic => (0x32, 0x1B),
+            FlashChip::Sst => (0xBF, 0xD4),
+            FlashChip::Macronix128K => (0xC2, 0x09),
+            FlashChip::Sanyo => (0x62, 0x13),
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            FlashChip::Macronix64K | FlashChip::Panasonic | FlashChip::Sst => BANK_SIZE,
+            FlashChip::Macronix128K | FlashChip::Sanyo => 2 * BANK_SIZE,
+        }
+    }
+}
+
+impl Flash {
+    pub fn new(chip: FlashChip) -> Flash {
+        Flash {
+            chip,
+            data: vec![0xFF; chip.size()],
+            bank: 0,
+            state: State::Ready,
+            id_mode: false,
+        }
+    }
+
+    pub fn chip(&self) -> FlashChip {
+        self.chip
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn read(&self, address: u32) -> u8 {
+        let offset = address as usize & (BANK_SIZE - 1);
+        if self.id_mode && offset < 2 {
+            let (manufacturer, device) = self.chip.id();
+            return if offset == 0 { manufacturer } else { device };
+        }
+        self.data[self.bank * BANK_SIZE + offset]
+    }
+
+    /// Returns whether the write programmed or erased anything
+    pub fn write(&mut self, address: u32, value: u8) -> bool {
+        let offset = address as usize & (BANK_SIZE - 1);
+        let mut changed = false;
+        self.state = match (self.state, offset, value) {
+            // Programming can only clear bits, setting them takes an erase
+            (State::Write, _, _) => {
+                self.data[self.bank * BANK_SIZE + offset] &= value;
+                changed = true;
+                State::Ready
+            }
+            (State::Bank, 0x0000, _) => {
+                self.bank = usize::from(value & 0x01);
+                State::Ready
+            }
+            (State::Ready, 0x5555, 0xAA) => State::Unlock1,
+            (State::Unlock1, 0x2AAA, 0x55) => State::Unlock2,
+            (State::Unlock2, 0x5555, command) => self.command(command),
+            (State::Erase, 0x5555, 0xAA) => State::EraseUnlock1,
+            (State::EraseUnlock1, 0x2AAA, 0x55) => State::EraseUnlock2,
+            (State::EraseUnlock2, 0x5555, 0x10) => {
+                log::debug!("Erasing flash");
+                self.data.fill(0xFF);
+                changed = true;
+                State::Ready
+            }
+            (State::EraseUnlock2, _, 0x30) => {
+                let start = self.bank * BANK_SIZE + (offset & !(SECTOR_SIZE - 1));
+                self.data[start..start + SECTOR_SIZE].fill(0xFF);
+                changed = true;
+                State::Ready
+            }
+            // Macronix chips can be told to give up on a command halfway
+            (_, _, 0xF0) => State::Ready,
+            _ => {
+                log::debug!("Unexpected flash write {:#2X} to {:#2X}", value, address);
+                State::Ready
+            }
+        };
+        changed
+    }
+
+    fn command(&mut self, command: u8) -> State {
+        match command {
+            0x90 => self.id_mode = true,
+            0xF0 => self.id_mode = false,
+            0x80 => return State::Erase,
+            0xA0 => return State::Write,
+            // Only the 128kb chips have more than one bank
+            0xB0 if self.chip.size() > BANK_SIZE => return State::Bank,
+            _ => log::debug!("Unknown flash command {:#2X}", command),
+        }
+        State::Ready
+    }
+}
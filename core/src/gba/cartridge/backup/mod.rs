@@ -0,0 +1,128 @@
+// Backup media on the cartridge, see here:
+// https://problemkaputt.de/gbatek-gba-cart-backup-ids.htm
+
+mod eeprom;
+mod flash;
+
+pub use eeprom::Eeprom;
+pub use flash::{Flash, FlashChip};
+
+// Battery backed SRAM at 0xE000000, mirrored across 0xE000000 - 0xFFFFFFF
+pub const SRAM_SIZE: usize = 32 * 1024;
+
+// The save libraries Nintendo handed out leave their name and version in
+// the ROM, which is the only way to tell what kind of backup is fitted.
+// SRAM_F is FRAM, which works the same as SRAM.
+const ID_STRINGS: [(&[u8], BackupType); 6] = [
+    (b"EEPROM_V", BackupType::Eeprom),
+    (b"SRAM_V", BackupType::Sram),
+    (b"SRAM_F_V", BackupType::Sram),
+    (b"FLASH_V", BackupType::Flash(FlashChip::DEFAULT_64K)),
+    (b"FLASH512_V", BackupType::Flash(FlashChip::DEFAULT_64K)),
+    (b"FLASH1M_V", BackupType::Flash(FlashChip::DEFAULT_128K)),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackupType {
+    None,
+    Sram,
+    // The chip decides the size, and the ID the game sees
+    Flash(FlashChip),
+    // The size is worked out once the game starts talking to it
+    Eeprom,
+}
+
+pub enum Backup {
+    None,
+    Sram(Vec<u8>),
+    Flash(Flash),
+    Eeprom(Eeprom),
+}
+
+impl BackupType {
+    /// Looks for the ID string of a save library in `rom`. They're always
+    /// word aligned.
+    pub fn detect(rom: &[u8]) -> Option<BackupType> {
+        (0..rom.len()).step_by(4).find_map(|offset| {
+            ID_STRINGS
+                .iter()
+                .find(|(id, _)| rom[offset..].starts_with(id))
+                .map(|&(_, kind)| kind)
+        })
+    }
+}
+
+impl Backup {
+    pub fn new(kind: BackupType) -> Backup {
+        match kind {
+            BackupType::None => Backup::None,
+            BackupType::Sram => Backup::Sram(vec![0xFF; SRAM_SIZE]),
+            BackupType::Flash(chip) => Backup::Flash(Flash::new(chip)),
+            BackupType::Eeprom => Backup::Eeprom(Eeprom::new(None)),
+        }
+    }
+
+    pub fn kind(&self) -> BackupType {
+        match self {
+            Backup::None => BackupType::None,
+            Backup::Sram(_) => BackupType::Sram,
+            Backup::Flash(flash) => BackupType::Flash(flash.chip()),
+            Backup::Eeprom(_) => BackupType::Eeprom,
+        }
+    }
+
+    /// Everything that's on the backup, as it's stored in a save file
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Backup::None => &[],
+            Backup::Sram(data) => data,
+            Backup::Flash(flash) => flash.data(),
+            Backup::Eeprom(eeprom) => eeprom.data(),
+        }
+    }
+
+    /// Fills the backup from a save file. Other emulators don't always
+    /// agree on the size, so anything past the end of it is dropped.
+    pub fn load(&mut self, data: &[u8]) {
+        match self {
+            Backup::None => log::warn!("Ignoring save file for a cartridge without backup"),
+            Backup::Sram(sram) => {
+                let len = data.len().min(SRAM_SIZE);
+                sram[..len].copy_from_slice(&data[..len]);
+            }
+            Backup::Flash(flash) => {
+                // A 128kb save means the game found a 128kb chip last time
+                if data.len() > flash.chip().size() {
+                    *flash = Flash::new(FlashChip::DEFAULT_128K);
+                }
+                flash.load(data);
+            }
+            Backup::Eeprom(eeprom) => eeprom.load(data),
+        }
+    }
+
+    /// Reads from the SRAM region, where SRAM and Flash sit
+    pub fn read(&self, address: u32) -> u8 {
+        match self {
+            Backup::Sram(data) => data[address as usize & (SRAM_SIZE - 1)],
+            Backup::Flash(flash) => flash.read(address),
+            // Nothing drives the data lines
+            Backup::None | Backup::Eeprom(_) => 0xFF,
+        }
+    }
+
+    /// Writes to the SRAM region, returning whether the backup took it
+    pub fn write(&mut self, address: u32, value: u8) -> bool {
+        match self {
+            Backup::Sram(data) => {
+                data[address as usize & (SRAM_SIZE - 1)] = value;
+                true
+            }
+            Backup::Flash(flash) => flash.write(address, value),
+            Backup::None | Backup::Eeprom(_) => {
+                log::debug!("Ignoring write to {:#2X} without SRAM", address);
+                false
+            }
+        }
+    }
+}
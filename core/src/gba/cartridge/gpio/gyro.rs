@@ -0,0 +1,58 @@
+// WarioWare: Twisted!'s gyro sensor, see here:
+// https://problemkaputt.de/gbatek-gba-cart-gyro-sensor.htm
+//
+// Pin 0 takes a sample of how fast the cartridge is turning, which is
+// then clocked out MSB first on pin 2, a bit on each falling edge of
+// pin 1. Only 12 bits of the 16 mean anything.
+
+const SAMPLE: u8 = 0x01;
+const CLOCK: u8 = 0x02;
+const DATA: u8 = 0x04;
+
+// What the sensor reads while it's held still
+const CENTRE: i32 = 0x6C0;
+
+pub struct Gyro {
+    rate: i16,
+    // The sample being sent out, the next bit being bit 15
+    sample: u16,
+    clock: bool,
+    output: bool,
+}
+
+impl Gyro {
+    pub fn new() -> Gyro {
+        Gyro {
+            rate: 0,
+            sample: 0,
+            clock: false,
+            output: false,
+        }
+    }
+
+    /// Positive is clockwise. The full range covers everything the sensor
+    /// can see either side of still.
+    pub fn set_rate(&mut self, rate: i16) {
+        self.rate = rate;
+    }
+
+    pub fn read_pins(&self) -> u8 {
+        if self.output {
+            DATA
+        } else {
+            0
+        }
+    }
+
+    pub fn write_pins(&mut self, pins: u8) {
+        if pins & SAMPLE != 0 {
+            self.sample = (CENTRE + (i32::from(self.rate) >> 5)).clamp(0, 0xFFF) as u16;
+        }
+        let clock = pins & CLOCK != 0;
+        if self.clock && !clock {
+            self.output = self.sample & 0x80_00 != 0;
+            self.sample <<= 1;
+        }
+        self.clock = clock;
+    }
+}
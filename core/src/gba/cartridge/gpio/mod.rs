@@ -0,0 +1,137 @@
+// The GPIO port some cartridges have for extra hardware, see here:
+// https://problemkaputt.de/gbatek-gba-cart-i-o-port-gpio.htm
+//
+// It's four pins with three registers in the ROM region. The game chooses
+// which pins it drives and which ones the device drives, and whether the
+// registers can be read at all. While they can't the ROM is read there as
+// normal.
+
+mod gyro;
+mod rtc;
+mod solar;
+
+pub use rtc::RtcClock;
+
+use super::Hardware;
+
+// Offsets into the ROM of the registers
+const DATA: u32 = 0xC4;
+const DIRECTION: u32 = 0xC6;
+const CONTROL: u32 = 0xC8;
+
+const PIN_MASK: u8 = 0x0F;
+
+// The RTC library leaves its name in the ROM like the save libraries do
+const RTC_ID_STRING: &[u8] = b"SIIRTC_V";
+
+// The rumble motor is on pin 3 for both games that have one
+const RUMBLE: u8 = 0x08;
+
+pub struct Gpio {
+    // The pins as the GBA last drove them
+    data: u8,
+    // Set bits are pins the GBA drives, clear bits are ones it reads
+    direction: u8,
+    readable: bool,
+    rtc: Option<rtc::Rtc>,
+    solar: Option<solar::Solar>,
+    gyro: Option<gyro::Gyro>,
+    rumble: bool,
+}
+
+/// Looks for devices that can be found from the ROM alone
+pub fn detect(rom: &[u8]) -> Vec<Hardware> {
+    let has_rtc = (0..rom.len())
+        .step_by(4)
+        .any(|offset| rom[offset..].starts_with(RTC_ID_STRING));
+    if has_rtc {
+        vec![Hardware::Rtc]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Whether `address` is one of the GPIO registers
+pub fn is_gpio(address: u32) -> bool {
+    (DATA..CONTROL + 2).contains(&(address & 0x01_FF_FF_FF))
+}
+
+impl Gpio {
+    pub fn new(hardware: &[Hardware]) -> Gpio {
+        Gpio {
+            data: 0,
+            direction: 0,
+            readable: false,
+            rtc: hardware.contains(&Hardware::Rtc).then(rtc::Rtc::new),
+            solar: hardware.contains(&Hardware::Solar).then(solar::Solar::new),
+            gyro: hardware.contains(&Hardware::Gyro).then(gyro::Gyro::new),
+            rumble: hardware.contains(&Hardware::Rumble),
+        }
+    }
+
+    /// Whether reads from the registers see the registers rather than ROM
+    pub fn readable(&self) -> bool {
+        self.readable
+    }
+
+    pub fn read(&self, address: u32) -> u8 {
+        match address & 0x01_FF_FF_FF {
+            DATA => {
+                let input = self.rtc.as_ref().map_or(0, rtc::Rtc::read_pins)
+                    | self.solar.as_ref().map_or(0, solar::Solar::read_pins)
+                    | self.gyro.as_ref().map_or(0, gyro::Gyro::read_pins);
+                (self.data & self.direction) | (input & !self.direction & PIN_MASK)
+            }
+            DIRECTION => self.direction,
+            CONTROL => u8::from(self.readable),
+            _ => 0,
+        }
+    }
+
+    /// Writes a byte to the registers. Returns whether something that goes
+    /// in the save file has changed.
+    pub fn write(&mut self, address: u32, value: u8) -> bool {
+        match address & 0x01_FF_FF_FF {
+            DATA => {
+                self.data = value & PIN_MASK;
+                // Pins the GBA isn't driving are pulled low
+                let pins = self.data & self.direction;
+                if let Some(solar) = &mut self.solar {
+                    solar.write_pins(pins);
+                }
+                if let Some(gyro) = &mut self.gyro {
+                    gyro.write_pins(pins);
+                }
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_pins(pins);
+                    return rtc.take_changed();
+                }
+            }
+            DIRECTION => self.direction = value & PIN_MASK,
+            CONTROL => self.readable = value & 0x01 != 0,
+            _ => {}
+        }
+        false
+    }
+
+    /// Whether the game has the rumble motor running
+    pub fn rumble(&self) -> bool {
+        self.rumble && self.data & self.direction & RUMBLE != 0
+    }
+
+    pub fn solar_mut(&mut self) -> Option<&mut solar::Solar> {
+        self.solar.as_mut()
+    }
+
+    pub fn gyro_mut(&mut self) -> Option<&mut gyro::Gyro> {
+        self.gyro.as_mut()
+    }
+
+    pub fn rtc(&self) -> Option<&rtc::Rtc> {
+        self.rtc.as_ref()
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut rtc::Rtc> {
+        self.rtc.as_mut()
+    }
+}
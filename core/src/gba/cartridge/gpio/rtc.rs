@@ -0,0 +1,336 @@
+// Seiko S-3511 real-time clock, see here:
+// https://problemkaputt.de/gbatek-gba-cart-real-time-clock-rtc.htm
+//
+// It's wired to the GPIO port with SCK on pin 0, SIO on pin 1 and CS on
+// pin 2. A transfer starts when CS goes high, after which a bit is moved
+// on every rising edge of SCK. The command byte comes first, MSB first,
+// then the parameter bytes, LSB first, in whichever direction the command
+// says.
+//
+// We don't keep time ourselves. The clock is whatever the host says it is
+// plus however far the game has set it away from that.
+
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, Timelike};
+
+pub const SCK: u8 = 0x01;
+pub const SIO: u8 = 0x02;
+pub const CS: u8 = 0x04;
+
+// The upper nibble of the command byte, which has to be there for the chip
+// to take any notice
+const COMMAND_CODE: u8 = 0b0110;
+
+// Control register bits
+const CONTROL_24_HOUR: u8 = 0x40;
+const CONTROL_POWER_FAIL: u8 = 0x80;
+
+/// Where the RTC gets the time from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtcClock {
+    /// The host's local time
+    Host,
+    /// Always the same time, so runs can be repeated exactly
+    Fixed(NaiveDateTime),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    Reset,
+    Control,
+    DateTime,
+    Time,
+    Irq,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // CS is low
+    Idle,
+    // Taking in the command byte
+    Command,
+    // Taking in the parameters for `command`
+    Writing(Command),
+    // Sending out the parameters that were put in the buffer
+    Reading,
+}
+
+pub struct Rtc {
+    clock: RtcClock,
+    // How far the game has set the clock away from `clock`
+    offset: Duration,
+    control: u8,
+    state: State,
+    // The last value of each pin
+    sck: bool,
+    sio: bool,
+    // The byte being moved and how many bits of it have been
+    byte: u8,
+    bits: u8,
+    // The parameters of the current command
+    buffer: [u8; 7],
+    index: usize,
+    len: usize,
+    // Set when something that belongs in the save file changes
+    changed: bool,
+}
+
+impl Command {
+    fn decode(byte: u8) -> Option<(Command, bool)> {
+        if byte >> 4 != COMMAND_CODE {
+            return None;
+        }
+        let read = byte & 0x01 != 0;
+        let command = match (byte >> 1) & 0b111 {
+            0 => Command::Reset,
+            1 => Command::Control,
+            2 => Command::DateTime,
+            3 => Command::Time,
+            6 => Command::Irq,
+            _ => return None,
+        };
+        Some((command, read))
+    }
+
+    fn len(self) -> usize {
+        match self {
+            Command::Reset | Command::Irq => 0,
+            Command::Control => 1,
+            Command::DateTime => 7,
+            Command::Time => 3,
+        }
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            clock: RtcClock::Host,
+            offset: Duration::zero(),
+            control: CONTROL_24_HOUR,
+            state: State::Idle,
+            sck: false,
+            sio: false,
+            byte: 0,
+            bits: 0,
+            buffer: [0; 7],
+            index: 0,
+            len: 0,
+            changed: false,
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.clock = clock;
+    }
+
+    /// The time the game sees
+    pub fn now(&self) -> NaiveDateTime {
+        let base = match self.clock {
+            RtcClock::Host => Local::now().naive_local(),
+            RtcClock::Fixed(time) => time,
+        };
+        base + self.offset
+    }
+
+    /// The SIO pin as driven by the RTC
+    pub fn read_pins(&self) -> u8 {
+        if self.sio {
+            SIO
+        } else {
+            0
+        }
+    }
+
+    /// Takes the pins the GBA is driving
+    pub fn write_pins(&mut self, pins: u8) {
+        let sck = pins & SCK != 0;
+        let rising = sck && !self.sck;
+        self.sck = sck;
+
+        if pins & CS == 0 {
+            self.state = State::Idle;
+            return;
+        }
+        if self.state == State::Idle {
+            self.state = State::Command;
+            self.byte = 0;
+            self.bits = 0;
+        }
+        if !rising {
+            return;
+        }
+
+        match self.state {
+            State::Command => {
+                // The command byte is the only thing that comes MSB first
+                self.byte = (self.byte << 1) | ((pins & SIO) >> 1);
+                self.bits += 1;
+                if self.bits == 8 {
+                    self.command(self.byte);
+                }
+            }
+            State::Writing(command) => {
+                self.byte |= ((pins & SIO) >> 1) << self.bits;
+                self.bits += 1;
+                if self.bits == 8 && self.index < self.len {
+                    self.buffer[self.index] = self.byte;
+                    self.index += 1;
+                    self.byte = 0;
+                    self.bits = 0;
+                    if self.index == self.len {
+                        self.finish_write(command);
+                    }
+                }
+            }
+            State::Reading => {
+                let byte = self.buffer.get(self.index).copied().unwrap_or(0);
+                self.sio = (byte >> self.bits) & 0x01 != 0;
+                self.bits += 1;
+                if self.bits == 8 {
+                    self.index += 1;
+                    self.bits = 0;
+                }
+            }
+            State::Idle => unreachable!(),
+        }
+    }
+
+    fn command(&mut self, byte: u8) {
+        self.byte = 0;
+        self.bits = 0;
+        self.index = 0;
+
+        let Some((command, read)) = Command::decode(byte) else {
+            log::debug!("Unknown RTC command {:#2X}", byte);
+            self.state = State::Idle;
+            return;
+        };
+        self.len = command.len();
+
+        if read {
+            self.fill_buffer(command);
+            self.state = State::Reading;
+        } else if self.len == 0 {
+            self.finish_write(command);
+        } else {
+            self.state = State::Writing(command);
+        }
+    }
+
+    fn fill_buffer(&mut self, command: Command) {
+        let now = self.now();
+        let time = [
+            self.hour_bcd(now.hour()),
+            bcd(now.minute()),
+            bcd(now.second()),
+        ];
+        match command {
+            Command::Control => self.buffer[0] = self.control,
+            Command::DateTime => {
+                self.buffer = [
+                    bcd(now.year().rem_euclid(100) as u32),
+                    bcd(now.month()),
+                    bcd(now.day()),
+                    now.weekday().num_days_from_sunday() as u8,
+                    time[0],
+                    time[1],
+                    time[2],
+                ];
+            }
+            Command::Time => self.buffer[..3].copy_from_slice(&time),
+            Command::Reset | Command::Irq => {}
+        }
+    }
+
+    fn finish_write(&mut self, command: Command) {
+        match command {
+            Command::Reset => {
+                // Back to midnight on the 1st of January 2000
+                let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+                    .unwrap();
+                self.set_time(epoch);
+                self.control = 0;
+            }
+            Command::Control => self.control = self.buffer[0] & !CONTROL_POWER_FAIL,
+            Command::DateTime => {
+                let [year, month, day, _, hour, minute, second] = self.buffer;
+                let date = NaiveDate::from_ymd_opt(
+                    2000 + from_bcd(year) as i32,
+                    from_bcd(month),
+                    from_bcd(day),
+                );
+                let time = date.and_then(|date| {
+                    date.and_hms_opt(self.hour(hour), from_bcd(minute), from_bcd(second))
+                });
+                match time {
+                    Some(time) => self.set_time(time),
+                    None => log::warn!("RTC set to an invalid date {:X?}", self.buffer),
+                }
+            }
+            Command::Time => {
+                let [hour, minute, second, ..] = self.buffer;
+                let time = self.now().date().and_hms_opt(
+                    self.hour(hour),
+                    from_bcd(minute),
+                    from_bcd(second),
+                );
+                match time {
+                    Some(time) => self.set_time(time),
+                    None => log::warn!("RTC set to an invalid time {:X?}", &self.buffer[..3]),
+                }
+            }
+            Command::Irq => log::debug!("Ignoring RTC interrupt request"),
+        }
+        self.changed = true;
+        self.state = State::Idle;
+    }
+
+    fn set_time(&mut self, time: NaiveDateTime) {
+        self.offset += time - self.now();
+    }
+
+    // Bit 7 is the PM flag, which is set in 24 hour mode too
+    fn hour_bcd(&self, hour: u32) -> u8 {
+        let pm = if hour >= 12 { 0x80 } else { 0 };
+        if self.control & CONTROL_24_HOUR != 0 {
+            bcd(hour) | pm
+        } else {
+            bcd(hour % 12) | pm
+        }
+    }
+
+    fn hour(&self, value: u8) -> u32 {
+        let hour = from_bcd(value & 0x3F);
+        if self.control & CONTROL_24_HOUR == 0 && value & 0x80 != 0 {
+            hour % 12 + 12
+        } else {
+            hour
+        }
+    }
+
+    /// Whether anything that goes in the save file has changed since this
+    /// was last called
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// The offset from the host clock and the control register, for the
+    /// save file
+    pub fn state(&self) -> (i64, u8) {
+        (self.offset.num_seconds(), self.control)
+    }
+
+    pub fn load_state(&mut self, offset: i64, control: u8) {
+        self.offset = Duration::seconds(offset);
+        self.control = control;
+    }
+}
+
+fn bcd(value: u32) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+fn from_bcd(value: u8) -> u32 {
+    u32::from(value >> 4) * 10 + u32::from(value & 0x0F)
+}
@@ -0,0 +1,53 @@
+// Boktai's solar sensor, see here:
+// https://problemkaputt.de/gbatek-gba-cart-solar-sensor.htm
+//
+// The game resets a counter with pin 1, then clocks it up with pin 0 until
+// pin 3 says it's gone past the light level. The brighter it is the sooner
+// that happens. Pin 2 has to be low for any of this.
+
+const CLOCK: u8 = 0x01;
+const RESET: u8 = 0x02;
+const SELECT: u8 = 0x04;
+const FLAG: u8 = 0x08;
+
+pub struct Solar {
+    // 0 is pitch black, 0xFF is the brightest the sensor can see
+    level: u8,
+    counter: u8,
+    clock: bool,
+}
+
+impl Solar {
+    pub fn new() -> Solar {
+        Solar {
+            level: 0,
+            counter: 0,
+            clock: false,
+        }
+    }
+
+    pub fn set_level(&mut self, level: u8) {
+        self.level = level;
+    }
+
+    pub fn read_pins(&self) -> u8 {
+        if self.counter >= 0xFF - self.level {
+            FLAG
+        } else {
+            0
+        }
+    }
+
+    pub fn write_pins(&mut self, pins: u8) {
+        if pins & SELECT != 0 {
+            return;
+        }
+        let clock = pins & CLOCK != 0;
+        if pins & RESET != 0 {
+            self.counter = 0;
+        } else if clock && !self.clock {
+            self.counter = self.counter.saturating_add(1);
+        }
+        self.clock = clock;
+    }
+}
@@ -0,0 +1,81 @@
+use super::LoadError;
+
+// The cartridge header at the start of every ROM, see here:
+// https://problemkaputt.de/gbatek-gba-cartridge-header.htm
+
+pub const HEADER_SIZE: usize = 0xC0;
+pub const LOGO_SIZE: usize = 156;
+
+// Always 0x96 at 0xB2, which is the easiest way to tell a GBA ROM apart
+// from anything else.
+const FIXED_VALUE: u8 = 0x96;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    /// The ARM branch at 0x00 that jumps over the header
+    pub entry_point: u32,
+    /// The Nintendo logo the BIOS compares against its own copy at boot
+    pub logo: [u8; LOGO_SIZE],
+    /// Up to 12 uppercase ASCII characters
+    pub title: String,
+    /// 4 characters, like AGBE. The last one is the region.
+    pub game_code: String,
+    /// 2 characters identifying the publisher, like 01 for Nintendo
+    pub maker_code: String,
+    /// 0x00 for the GBA
+    pub unit_code: u8,
+    pub device_type: u8,
+    pub version: u8,
+    /// The complement check over 0xA0 - 0xBC stored in the header
+    pub checksum: u8,
+    // What the checksum works out to be for this header
+    computed_checksum: u8,
+}
+
+impl CartridgeHeader {
+    pub fn parse(data: &[u8]) -> Result<CartridgeHeader, LoadError> {
+        if data.len() < HEADER_SIZE {
+            return Err(LoadError::Truncated(data.len()));
+        }
+        if data[0xB2] != FIXED_VALUE {
+            return Err(LoadError::NotGba);
+        }
+
+        let mut logo = [0; LOGO_SIZE];
+        logo.copy_from_slice(&data[0x04..0x04 + LOGO_SIZE]);
+
+        Ok(CartridgeHeader {
+            entry_point: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            logo,
+            title: ascii(&data[0xA0..0xAC]),
+            game_code: ascii(&data[0xAC..0xB0]),
+            maker_code: ascii(&data[0xB0..0xB2]),
+            unit_code: data[0xB3],
+            device_type: data[0xB4],
+            version: data[0xBC],
+            checksum: data[0xBD],
+            computed_checksum: Self::compute_checksum(data),
+        })
+    }
+
+    // The complement check over 0xA0 - 0xBC
+    fn compute_checksum(data: &[u8]) -> u8 {
+        let sum = data[0xA0..0xBD]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte));
+        sum.wrapping_sub(0x19)
+    }
+
+    /// The real BIOS refuses to boot a cartridge with a bad checksum
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+}
+
+// Text fields are padded with zeroes (or spaces, in some homebrew)
+fn ascii(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end])
+        .trim_end()
+        .to_string()
+}
@@ -1,5 +1,71 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+mod archive;
+pub mod backup;
+pub mod gpio;
+pub mod header;
+mod overrides;
+mod patch;
+pub mod prefetch;
+mod save;
+mod tilt;
+
+pub use backup::{BackupType, FlashChip};
+pub use gpio::RtcClock;
+pub use header::CartridgeHeader;
+
+// The largest ROM that fits in the 32mb Game Pak address space
+pub const MAX_ROM_SIZE: usize = 32 * 1024 * 1024;
+
+// ROMs bigger than this leave only the last 256 bytes of 0xD000000 -
+// 0xDFFFFFF for the EEPROM, the rest being the top of the ROM
+const EEPROM_FULL_REGION_MAX_ROM: usize = 16 * 1024 * 1024;
+
+/// Extra hardware on the cartridge. Everything but the tilt sensor is on
+/// the GPIO port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hardware {
+    Rtc,
+    Solar,
+    Tilt,
+    Gyro,
+    Rumble,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    // Too small to even hold the header
+    Truncated(usize),
+    // Doesn't have the fixed 0x96 in the header
+    NotGba,
+    TooLarge(usize),
+    // The archive couldn't be read
+    Archive(String),
+    // Nothing in the archive looked like a ROM, or had the name asked for
+    NoRomInArchive(Option<String>),
+    InvalidPatch(String),
+    // The patch is for a different ROM, or one of them is corrupt. Says
+    // which checksum didn't match.
+    PatchChecksum(&'static str),
+    // Too big to fit in EWRAM
+    MultibootTooLarge(usize),
+}
+
 pub struct Cartridge {
     rom: Rom,
+    header: Option<CartridgeHeader>,
+    backup: backup::Backup,
+    hardware: Vec<Hardware>,
+    gpio: gpio::Gpio,
+    tilt: Option<tilt::Tilt>,
+    // Kept here so it outlives the cartridge that's inserted
+    rtc_clock: RtcClock,
+    // Only there for ROMs loaded from a file
+    save_file: Option<save::SaveFile>,
+    pub prefetch: prefetch::Prefetch,
 }
 
 struct Rom {
@@ -8,29 +74,384 @@ struct Rom {
 
 impl Cartridge {
     pub fn new() -> Cartridge {
-        Cartridge { rom: Rom::new() }
+        Cartridge {
+            rom: Rom::new(),
+            header: None,
+            backup: backup::Backup::new(BackupType::Sram),
+            hardware: Vec::new(),
+            gpio: gpio::Gpio::new(&[]),
+            tilt: None,
+            rtc_clock: RtcClock::Host,
+            save_file: None,
+            prefetch: prefetch::Prefetch::new(),
+        }
     }
 
-    pub fn load(&mut self, file_name: &str) {
-        self.rom.data = std::fs::read(file_name).expect("Could not read ROM!");
+    /// Inserts the ROM at `file_name` along with the .sav file next to it.
+    /// The ROM can be in a zip, 7z or gzip archive.
+    pub fn load(&mut self, file_name: &str) -> Result<&CartridgeHeader, LoadError> {
+        self.load_entry(file_name, None)
     }
 
+    /// Like `load`, but `entry` names the file to use in an archive that
+    /// has more than one ROM in it
+    pub fn load_entry(
+        &mut self,
+        file_name: &str,
+        entry: Option<&str>,
+    ) -> Result<&CartridgeHeader, LoadError> {
+        self.load_patched(file_name, entry, &[])
+    }
+
+    /// Like `load_entry`, applying the IPS, UPS or BPS files in `patches`
+    /// one after the other. Without any, a patch with the same name as the
+    /// ROM is applied if there is one. Hacks often ship in more than one
+    /// format, so only the first of BPS, UPS and IPS that's found is used.
+    pub fn load_patched(
+        &mut self,
+        file_name: &str,
+        entry: Option<&str>,
+        patches: &[&str],
+    ) -> Result<&CartridgeHeader, LoadError> {
+        let mut data = archive::extract(std::fs::read(file_name)?, entry)?;
+
+        let found: Vec<PathBuf>;
+        let patches: Vec<&Path> = if patches.is_empty() {
+            found = patch::EXTENSIONS
+                .iter()
+                .map(|extension| Path::new(file_name).with_extension(extension))
+                .filter(|path| path.is_file())
+                .collect();
+            for path in found.iter().skip(1) {
+                log::warn!("Ignoring patch {} as there's another one", path.display());
+            }
+            found.iter().take(1).map(PathBuf::as_path).collect()
+        } else {
+            patches.iter().map(Path::new).collect()
+        };
+        for path in patches {
+            log::info!("Applying patch {}", path.display());
+            data = patch::apply(data, &std::fs::read(path)?)?;
+        }
+
+        self.insert(data)?;
+
+        let mut save_file = save::SaveFile::for_rom(Path::new(file_name));
+        if let Some(save) = save_file.load()? {
+            log::info!("Loaded save from {}", save_file.path().display());
+            let (backup, rtc_state) = save::split_rtc(&save);
+            self.backup.load(backup);
+            if let (Some(rtc), Some((offset, control))) = (self.gpio.rtc_mut(), rtc_state) {
+                rtc.load_state(offset, control);
+            }
+        }
+        self.save_file = Some(save_file);
+
+        Ok(self.header.as_ref().expect("A cartridge was just inserted"))
+    }
+
+    /// Inserts a ROM image that's already in memory, which can be an
+    /// archive too. Nothing it saves is written anywhere.
+    pub fn load_data(&mut self, data: Vec<u8>) -> Result<&CartridgeHeader, LoadError> {
+        self.insert(archive::extract(data, None)?)?;
+        Ok(self.header.as_ref().expect("A cartridge was just inserted"))
+    }
+
+    fn insert(&mut self, mut data: Vec<u8>) -> Result<(), LoadError> {
+        if data.len() > MAX_ROM_SIZE {
+            return Err(LoadError::TooLarge(data.len()));
+        }
+
+        let header = CartridgeHeader::parse(&data)?;
+        log::info!(
+            "Loaded {} ({}-{}) version {}",
+            header.title,
+            header.game_code,
+            header.maker_code,
+            header.version
+        );
+        if !header.checksum_valid() {
+            // The real BIOS would lock up, but plenty of homebrew never
+            // bothered to fix the header so we run it anyway.
+            log::warn!("Header checksum {:#04X} doesn't match", header.checksum);
+        }
+
+        // Homebrew doesn't use the save libraries, so it gets SRAM as
+        // that's the easiest to use.
+        let game_override = overrides::find(&header.game_code);
+        let backup = game_override
+            .map(|o| o.backup)
+            .or_else(|| BackupType::detect(&data))
+            .unwrap_or(BackupType::Sram);
+        log::info!("Using {:?} for backup", backup);
+        let hardware = game_override
+            .map(|o| o.hardware.to_vec())
+            .unwrap_or_else(|| gpio::detect(&data));
+        if !hardware.is_empty() {
+            log::info!("Cartridge has {:?}", hardware);
+        }
+
+        // Don't lose what the last cartridge had left to save
+        if let Err(e) = self.flush_save() {
+            log::error!("Could not save: {}", e);
+        }
+        self.save_file = None;
+        self.backup = backup::Backup::new(backup);
+        self.gpio = gpio::Gpio::new(&hardware);
+        if let Some(rtc) = self.gpio.rtc_mut() {
+            rtc.set_clock(self.rtc_clock);
+        }
+        self.tilt = hardware.contains(&Hardware::Tilt).then(tilt::Tilt::new);
+        self.hardware = hardware;
+
+        // Cartridges are built out of power of two sized chips, so room is
+        // made for the whole chip. Only the image itself is readable, past
+        // its end `read_rom` gives back open bus.
+        data.reserve_exact(data.len().next_power_of_two() - data.len());
+        self.rom.data = data;
+        self.header = Some(header);
+        Ok(())
+    }
+
+    /// Writes the backup to the save file if the game has written to it
+    /// since the file was last written
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        if !self
+            .save_file
+            .as_ref()
+            .is_some_and(save::SaveFile::is_dirty)
+        {
+            return Ok(());
+        }
+        let mut data = self.backup.data().to_vec();
+        if let Some(rtc) = self.gpio.rtc() {
+            save::append_rtc(&mut data, rtc.state());
+        }
+        match &mut self.save_file {
+            Some(save_file) => save_file.write(&data),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the save file once the game has stopped writing to the
+    /// backup for a while.
+    pub fn end_frame(&mut self) {
+        let due = self
+            .save_file
+            .as_mut()
+            .is_some_and(|save_file| save_file.end_frame());
+        if due {
+            if let Err(e) = self.flush_save() {
+                log::error!("Could not save: {}", e);
+            }
+        }
+    }
+
+    fn mark_backup_written(&mut self) {
+        if let Some(save_file) = &mut self.save_file {
+            save_file.mark_written();
+        }
+    }
+
+    pub fn header(&self) -> Option<&CartridgeHeader> {
+        self.header.as_ref()
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom.data
+    }
+
+    /// Whether reads from `address` have to go through `read_rom`, rather
+    /// than straight to the ROM
+    pub fn is_gpio(&self, address: u32) -> bool {
+        self.gpio.readable() && gpio::is_gpio(address)
+    }
+
+    /// Reads from the ROM, which is the same in all three waitstate
+    /// regions
     pub fn read_rom(&self, address: u32) -> u8 {
+        if self.is_gpio(address) {
+            return self.gpio.read(address);
+        }
         let index = (address & 0x1_FF_FF_FF) as usize;
-        // Do I need to check for overflow here?
-        if index < self.rom.data.len() {
-            self.rom.data[index]
+        if let Some(&value) = self.rom.data.get(index) {
+            value
         } else {
-            0x0
+            // Nothing drives the bus past the end of the ROM, so we read back
+            // the lower bits of the address that was put on it. The Game Pak
+            // bus is 16 bits wide and addressed in halfwords.
+            let half = (index >> 1) as u16;
+            (half >> ((index & 1) * 8)) as u8
+        }
+    }
+
+    /// Writes to the ROM region, where only the GPIO port takes any notice
+    pub fn write_rom(&mut self, address: u32, value: u8) {
+        if !gpio::is_gpio(address) {
+            log::debug!("Ignoring write to ROM at {:#2X}", address);
+            return;
+        }
+        if self.gpio.write(address, value) {
+            self.mark_backup_written();
+        }
+    }
+
+    /// Where the RTC gets the time from, for this and any later cartridge
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc_clock = clock;
+        if let Some(rtc) = self.gpio.rtc_mut() {
+            rtc.set_clock(clock);
+        }
+    }
+
+    /// The extra hardware the cartridge has
+    pub fn hardware(&self) -> &[Hardware] {
+        &self.hardware
+    }
+
+    /// Takes the cartridge out, saving anything it had left to save
+    pub fn eject(&mut self) {
+        if let Err(e) = self.flush_save() {
+            log::error!("Could not save: {}", e);
+        }
+        *self = Cartridge {
+            rtc_clock: self.rtc_clock,
+            ..Cartridge::new()
+        };
+    }
+
+    /// Sets how bright it is for the solar sensor, 0 being pitch black
+    pub fn set_solar_level(&mut self, level: u8) {
+        if let Some(solar) = self.gpio.solar_mut() {
+            solar.set_level(level);
+        }
+    }
+
+    pub fn set_tilt(&mut self, x: i16, y: i16) {
+        if let Some(tilt) = &mut self.tilt {
+            tilt.set(x, y);
+        }
+    }
+
+    pub fn set_gyro_rate(&mut self, rate: i16) {
+        if let Some(gyro) = self.gpio.gyro_mut() {
+            gyro.set_rate(rate);
+        }
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.gpio.rumble()
+    }
+
+    pub fn backup_type(&self) -> BackupType {
+        self.backup.kind()
+    }
+
+    /// Reads from the SRAM region at 0xE000000, which is where SRAM and
+    /// Flash are.
+    pub fn read_sram(&self, address: u32) -> u8 {
+        if let Some(tilt) = self.tilt.as_ref().filter(|_| tilt::is_tilt(address)) {
+            return tilt.read(address);
+        }
+        self.backup.read(address)
+    }
+
+    pub fn write_sram(&mut self, address: u32, value: u8) {
+        if let Some(tilt) = self.tilt.as_mut().filter(|_| tilt::is_tilt(address)) {
+            tilt.write(address, value);
+            return;
+        }
+        if self.backup.write(address, value) {
+            self.mark_backup_written();
+        }
+    }
+
+    /// Whether `address` talks to the EEPROM rather than the ROM
+    pub fn is_eeprom(&self, address: u32) -> bool {
+        if !matches!(self.backup, backup::Backup::Eeprom(_)) || address >> 24 != 0x0D {
+            return false;
+        }
+        self.rom.data.len() <= EEPROM_FULL_REGION_MAX_ROM || address & 0xFF_FF_00 == 0xFF_FF_00
+    }
+
+    pub fn read_eeprom(&mut self) -> u16 {
+        match &mut self.backup {
+            backup::Backup::Eeprom(eeprom) => eeprom.read(),
+            _ => unreachable!("No EEPROM fitted"),
+        }
+    }
+
+    pub fn write_eeprom(&mut self, value: u16) {
+        if let backup::Backup::Eeprom(eeprom) = &mut self.backup {
+            if eeprom.write(value) {
+                self.mark_backup_written();
+            }
+        }
+    }
+
+    /// Lets the EEPROM know DMA3 is about to send it `count` bits
+    pub fn eeprom_dma(&mut self, count: u32) {
+        if let backup::Backup::Eeprom(eeprom) = &mut self.backup {
+            eeprom.detect_size(count);
         }
     }
 }
 
 impl Rom {
     fn new() -> Rom {
-        // I don't believe the ROM is zero initialized?
-        Rom {
-            data: vec![0; 32 * 1024 * 1024],
+        // Without a cartridge every read is open bus
+        Rom { data: Vec::new() }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "Could not read ROM: {}", e),
+            LoadError::Truncated(len) => write!(
+                f,
+                "ROM is only {} bytes, too small to hold a cartridge header",
+                len
+            ),
+            LoadError::NotGba => write!(f, "Not a GBA ROM, the header is missing its fixed value"),
+            LoadError::TooLarge(len) => write!(
+                f,
+                "ROM is {} bytes, more than the {} that can be mapped",
+                len, MAX_ROM_SIZE
+            ),
+            LoadError::Archive(e) => write!(f, "Could not read archive: {}", e),
+            LoadError::NoRomInArchive(Some(entry)) => {
+                write!(f, "Archive doesn't have a file called {}", entry)
+            }
+            LoadError::NoRomInArchive(None) => {
+                write!(f, "Archive doesn't have a .gba, .agb or .bin file in it")
+            }
+            LoadError::MultibootTooLarge(len) => write!(
+                f,
+                "Multiboot image is {} bytes, more than fits in the 256kb of EWRAM",
+                len
+            ),
+            LoadError::InvalidPatch(reason) => write!(f, "Could not apply patch, it {}", reason),
+            LoadError::PatchChecksum(which) => write!(
+                f,
+                "Could not apply patch, the {} checksum doesn't match",
+                which
+            ),
         }
     }
 }
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
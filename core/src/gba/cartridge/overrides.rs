@@ -0,0 +1,117 @@
+use super::backup::{BackupType, FlashChip};
+use super::Hardware;
+
+// Settings for games that can't be worked out from the ROM alone, either
+// because the save library ID is missing or misleading, or because the
+// cartridge has extra hardware. Flash games also get the chip their
+// cartridge was built with, as some of them check its ID. Matched on the
+// first three characters of the game code, the last one being the region.
+
+pub struct Override {
+    pub game_code: &'static str,
+    pub backup: BackupType,
+    pub hardware: &'static [Hardware],
+}
+
+const OVERRIDES: &[Override] = &[
+    // Advance Wars
+    Override {
+        game_code: "AWR",
+        backup: BackupType::Flash(FlashChip::Sst),
+        hardware: &[],
+    },
+    // Pokemon Ruby, Sapphire, Emerald, FireRed and LeafGreen
+    Override {
+        game_code: "AXV",
+        backup: BackupType::Flash(FlashChip::Sanyo),
+        hardware: &[Hardware::Rtc],
+    },
+    Override {
+        game_code: "AXP",
+        backup: BackupType::Flash(FlashChip::Sanyo),
+        hardware: &[Hardware::Rtc],
+    },
+    Override {
+        game_code: "BPE",
+        backup: BackupType::Flash(FlashChip::Macronix128K),
+        hardware: &[Hardware::Rtc],
+    },
+    Override {
+        game_code: "BPR",
+        backup: BackupType::Flash(FlashChip::Macronix128K),
+        hardware: &[],
+    },
+    Override {
+        game_code: "BPG",
+        backup: BackupType::Flash(FlashChip::Macronix128K),
+        hardware: &[],
+    },
+    // Super Mario Advance 4
+    Override {
+        game_code: "AX4",
+        backup: BackupType::Flash(FlashChip::Macronix128K),
+        hardware: &[],
+    },
+    // F-Zero Climax
+    Override {
+        game_code: "BFT",
+        backup: BackupType::Flash(FlashChip::DEFAULT_128K),
+        hardware: &[],
+    },
+    // Sennen Kazoku
+    Override {
+        game_code: "BKA",
+        backup: BackupType::Flash(FlashChip::DEFAULT_128K),
+        hardware: &[Hardware::Rtc],
+    },
+    // Mega Man Battle Network
+    Override {
+        game_code: "ARE",
+        backup: BackupType::Sram,
+        hardware: &[],
+    },
+    // Boktai 1 and 2, and Shin Bokura no Taiyou
+    Override {
+        game_code: "U3I",
+        backup: BackupType::Eeprom,
+        hardware: &[Hardware::Rtc, Hardware::Solar],
+    },
+    Override {
+        game_code: "U32",
+        backup: BackupType::Eeprom,
+        hardware: &[Hardware::Rtc, Hardware::Solar],
+    },
+    Override {
+        game_code: "U33",
+        backup: BackupType::Eeprom,
+        hardware: &[Hardware::Rtc, Hardware::Solar],
+    },
+    // Koro Koro Puzzle and Yoshi's Universal Gravitation
+    Override {
+        game_code: "KHP",
+        backup: BackupType::Eeprom,
+        hardware: &[Hardware::Tilt],
+    },
+    Override {
+        game_code: "KYG",
+        backup: BackupType::Eeprom,
+        hardware: &[Hardware::Tilt],
+    },
+    // WarioWare: Twisted! and Drill Dozer
+    Override {
+        game_code: "RZW",
+        backup: BackupType::Sram,
+        hardware: &[Hardware::Gyro, Hardware::Rumble],
+    },
+    Override {
+        game_code: "V49",
+        backup: BackupType::Sram,
+        hardware: &[Hardware::Rumble],
+    },
+];
+
+pub fn find(game_code: &str) -> Option<&'static Override> {
+    OVERRIDES
+        .iter()
+        .find(|o| game_code.get(..3) == Some(o.game_code))
+}
@@ -0,0 +1,276 @@
+use super::{LoadError, MAX_ROM_SIZE};
+
+// Soft patches for ROM hacks and translations, applied as the ROM is
+// loaded. See here for the formats:
+// IPS: https://zerosoft.zophar.net/ips.php
+// UPS: https://www.romhacking.net/documents/392/
+// BPS: https://www.romhacking.net/documents/746/
+//
+// UPS and BPS end with the CRC32 of the ROM they're for, the ROM they make
+// and the patch itself, which all get checked. IPS has nothing to check.
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: usize = 0x45_4F_46;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// Source, target and patch CRC32s
+const FOOTER_SIZE: usize = 12;
+
+// The file extensions we look for next to a ROM, in the order they're
+// preferred when there's more than one. BPS and UPS come first as they
+// check they're being applied to the right ROM.
+pub const EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+
+// Reads through a patch, turning running off the end into an error
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// Applies `patch` to `rom`, working out the format from the patch
+pub fn apply(rom: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, LoadError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(&rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(&rom, patch)
+    } else {
+        Err(invalid("isn't an IPS, UPS or BPS patch"))
+    }
+}
+
+fn apply_ips(mut rom: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    loop {
+        let offset = reader.be(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let size = reader.be(2)?;
+        // A size of 0 means a run of the same byte
+        let (size, run) = if size == 0 {
+            (reader.be(2)?, Some(reader.byte()?))
+        } else {
+            (size, None)
+        };
+
+        let end = offset + size;
+        if end > MAX_ROM_SIZE {
+            return Err(LoadError::TooLarge(end));
+        }
+        if end > rom.len() {
+            rom.resize(end, 0);
+        }
+        match run {
+            Some(value) => rom[offset..end].fill(value),
+            None => rom[offset..end].copy_from_slice(reader.bytes(size)?),
+        }
+    }
+
+    // Some patches cut the ROM down afterwards
+    if let Ok(len) = reader.be(3) {
+        rom.truncate(len);
+    }
+    Ok(rom)
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let (body, target_crc) = check_footer(rom, patch)?;
+    let mut reader = Reader::new(body, UPS_MAGIC.len());
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    check_sizes(rom, source_size, target_size)?;
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+
+    // Runs of bytes to XOR with the source, each one ending in a 0
+    let mut position = 0;
+    while !reader.done() {
+        position += reader.varint()?;
+        loop {
+            let value = reader.byte()?;
+            if value == 0 {
+                position += 1;
+                break;
+            }
+            if let Some(byte) = target.get_mut(position) {
+                *byte ^= value;
+            }
+            position += 1;
+        }
+    }
+
+    check_target(&target, target_crc)?;
+    Ok(target)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let (body, target_crc) = check_footer(rom, patch)?;
+    let mut reader = Reader::new(body, BPS_MAGIC.len());
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    check_sizes(rom, source_size, target_size)?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    while !reader.done() {
+        let action = reader.varint()?;
+        let len = (action >> 2) + 1;
+        if target.len() + len > target_size {
+            return Err(invalid("writes past the end of the ROM"));
+        }
+        match action & 0b11 {
+            // The same bytes as in the source
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(slice(rom, start, len)?);
+            }
+            // New bytes from the patch
+            1 => target.extend_from_slice(reader.bytes(len)?),
+            // Bytes from elsewhere in the source
+            2 => {
+                source_offset = reader.relative(source_offset)?;
+                target.extend_from_slice(slice(rom, source_offset, len)?);
+                source_offset += len;
+            }
+            // Bytes from earlier in the target, which can overlap with the
+            // ones being written to repeat a pattern
+            _ => {
+                target_offset = reader.relative(target_offset)?;
+                for _ in 0..len {
+                    let value = *target
+                        .get(target_offset)
+                        .ok_or_else(|| invalid("copies from past the end of the ROM"))?;
+                    target.push(value);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(invalid("makes a ROM of the wrong size"));
+    }
+    check_target(&target, target_crc)?;
+    Ok(target)
+}
+
+// Checks the patch and the ROM it's being applied to against the footer.
+// Returns the rest of the patch and the CRC32 of the patched ROM.
+fn check_footer<'a>(rom: &[u8], patch: &'a [u8]) -> Result<(&'a [u8], u32), LoadError> {
+    let body_len = patch
+        .len()
+        .checked_sub(FOOTER_SIZE)
+        .ok_or_else(|| invalid("too short"))?;
+    let (body, footer) = patch.split_at(body_len);
+    let crc = |offset: usize| u32::from_le_bytes(footer[offset..offset + 4].try_into().unwrap());
+    let (source_crc, target_crc, patch_crc) = (crc(0), crc(4), crc(8));
+
+    if crc32fast::hash(&patch[..patch.len() - 4]) != patch_crc {
+        return Err(LoadError::PatchChecksum("patch"));
+    }
+    if crc32fast::hash(rom) != source_crc {
+        return Err(LoadError::PatchChecksum("source ROM"));
+    }
+    Ok((body, target_crc))
+}
+
+fn check_sizes(rom: &[u8], source_size: usize, target_size: usize) -> Result<(), LoadError> {
+    if source_size != rom.len() {
+        return Err(LoadError::PatchChecksum("source ROM"));
+    }
+    if target_size > MAX_ROM_SIZE {
+        return Err(LoadError::TooLarge(target_size));
+    }
+    Ok(())
+}
+
+fn check_target(target: &[u8], target_crc: u32) -> Result<(), LoadError> {
+    if crc32fast::hash(target) != target_crc {
+        return Err(LoadError::PatchChecksum("patched ROM"));
+    }
+    Ok(())
+}
+
+fn slice(data: &[u8], start: usize, len: usize) -> Result<&[u8], LoadError> {
+    data.get(start..start + len)
+        .ok_or_else(|| invalid("copies from past the end of the ROM"))
+}
+
+fn invalid(reason: &str) -> LoadError {
+    LoadError::InvalidPatch(reason.to_string())
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Reader<'a> {
+        Reader { data, position }
+    }
+
+    fn done(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| invalid("ends too early"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    // IPS numbers are big endian
+    fn be(&mut self, len: usize) -> Result<usize, LoadError> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | usize::from(byte)))
+    }
+
+    // UPS and BPS numbers are 7 bits a byte, least significant first, with
+    // the top bit set on the last byte. Each byte after the first also
+    // adds one to the byte before it, so no number has two encodings.
+    fn varint(&mut self) -> Result<usize, LoadError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            value = usize::from(byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|add| value.checked_add(add))
+                .ok_or_else(|| invalid("has a number that's too large"))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_shl(7)
+                .filter(|&shift| shift <= 1 << 49)
+                .ok_or_else(|| invalid("has a number that's too large"))?;
+            value = value
+                .checked_add(shift)
+                .ok_or_else(|| invalid("has a number that's too large"))?;
+        }
+    }
+
+    // BPS copy offsets are relative to the last one, with the sign in bit 0
+    fn relative(&mut self, offset: usize) -> Result<usize, LoadError> {
+        let value = self.varint()?;
+        let delta = value >> 1;
+        let offset = if value & 1 != 0 {
+            offset.checked_sub(delta)
+        } else {
+            offset.checked_add(delta)
+        };
+        offset.ok_or_else(|| invalid("copies from before the start of the ROM"))
+    }
+}
@@ -0,0 +1,92 @@
+// Game Pak prefetch buffer, see here:
+// https://problemkaputt.de/gbatek-gba-system-control.htm
+//
+// While the CPU is busy with anything other than the Game Pak, the prefetch
+// unit keeps reading the halfwords that follow the last opcode fetch into an
+// 8 halfword FIFO. Opcode fetches that hit the FIFO only take a single cycle.
+
+const CAPACITY: u32 = 8;
+
+pub struct Prefetch {
+    active: bool,
+    // Address of the oldest halfword in the buffer, which is
+    // the one the CPU is expected to fetch next.
+    head: u32,
+    // Halfwords ready in the buffer
+    count: u32,
+    // Cycles left until the halfword being read arrives
+    countdown: u32,
+    // Cycles a sequential halfword read takes from the current region
+    seq_cycles: u32,
+}
+
+impl Prefetch {
+    pub fn new() -> Prefetch {
+        Prefetch {
+            active: false,
+            head: 0x0,
+            count: 0,
+            countdown: 0,
+            seq_cycles: 0,
+        }
+    }
+
+    /// Throws the buffer away, e.g. when the Game Pak is accessed for data.
+    pub fn flush(&mut self) {
+        self.active = false;
+        self.count = 0;
+    }
+
+    /// Starts prefetching from `address`, which follows the opcode that
+    /// was just fetched.
+    pub fn start(&mut self, address: u32, seq_cycles: u32) {
+        self.active = true;
+        self.head = address;
+        self.count = 0;
+        self.countdown = seq_cycles;
+        self.seq_cycles = seq_cycles;
+    }
+
+    /// Lets the prefetcher use `cycles` during which the CPU isn't
+    /// accessing the Game Pak.
+    pub fn run(&mut self, cycles: u32) {
+        if !self.active {
+            return;
+        }
+
+        let mut cycles = cycles;
+        while cycles > 0 && self.count < CAPACITY {
+            if cycles >= self.countdown {
+                cycles -= self.countdown;
+                self.count += 1;
+                self.countdown = self.seq_cycles;
+            } else {
+                self.countdown -= cycles;
+                cycles = 0;
+            }
+        }
+    }
+
+    /// Tries to serve an opcode fetch of `width` bytes at `address` from the
+    /// buffer. Returns the cycles it took, or None if it missed.
+    pub fn fetch(&mut self, address: u32, width: u32) -> Option<u32> {
+        if !self.active || address != self.head {
+            return None;
+        }
+
+        let mut cycles = 0;
+        for _ in 0..width / 2 {
+            if self.count == 0 {
+                // Wait for the halfword that's already on its way
+                cycles += self.countdown;
+                self.countdown = self.seq_cycles;
+            } else {
+                self.count -= 1;
+                cycles += 1;
+                self.run(1);
+            }
+            self.head = self.head.wrapping_add(2);
+        }
+        Some(cycles)
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// The .sav file next to the ROM that the backup media is kept in. It's a
+// raw dump of the backup, the same as what other emulators and flashcarts
+// use.
+
+// Cartridges with an RTC get its state added after the backup, in a
+// block that other emulators ignore along with anything else past the
+// size they expect:
+//   0x00  "RTC\0"
+//   0x04  Control register
+//   0x05  Unused
+//   0x08  Seconds the clock is ahead of the host, i64 little endian
+const RTC_MAGIC: &[u8; 4] = b"RTC\0";
+const RTC_BLOCK_SIZE: usize = 16;
+
+// Frames to wait after the last write to the backup before writing the
+// file, as games write their saves a byte or a block at a time.
+const WRITE_BACK_DELAY: u32 = 60;
+
+/// Splits the RTC block off the end of a save file, if it has one
+pub fn split_rtc(data: &[u8]) -> (&[u8], Option<(i64, u8)>) {
+    let Some(start) = data.len().checked_sub(RTC_BLOCK_SIZE) else {
+        return (data, None);
+    };
+    let (backup, block) = data.split_at(start);
+    if !block.starts_with(RTC_MAGIC) {
+        return (data, None);
+    }
+    let offset = i64::from_le_bytes(block[8..16].try_into().unwrap());
+    (backup, Some((offset, block[4])))
+}
+
+/// Adds an RTC block to the end of `data`
+pub fn append_rtc(data: &mut Vec<u8>, (offset, control): (i64, u8)) {
+    data.extend_from_slice(RTC_MAGIC);
+    data.extend_from_slice(&[control, 0, 0, 0]);
+    data.extend_from_slice(&offset.to_le_bytes());
+}
+
+pub struct SaveFile {
+    path: PathBuf,
+    // What's in the file, so we don't write it again for nothing
+    saved: Vec<u8>,
+    // Frames since the backup was last written to, while there are
+    // changes that haven't made it to the file yet.
+    frames_since_write: Option<u32>,
+}
+
+impl SaveFile {
+    /// The save file for the ROM at `rom_path`
+    pub fn for_rom(rom_path: &Path) -> SaveFile {
+        SaveFile {
+            path: rom_path.with_extension("sav"),
+            saved: Vec::new(),
+            frames_since_write: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the file, which is fine to not exist yet
+    pub fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                self.saved = data.clone();
+                Ok(Some(data))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Called whenever the game writes to the backup
+    pub fn mark_written(&mut self) {
+        self.frames_since_write = Some(0);
+    }
+
+    /// Whether the game has written to the backup since the file was
+    /// last written. Games that never save never get a file.
+    pub fn is_dirty(&self) -> bool {
+        self.frames_since_write.is_some()
+    }
+
+    /// Called once per frame. Returns whether it's been long enough since
+    /// the last write that the file should be written.
+    pub fn end_frame(&mut self) -> bool {
+        match &mut self.frames_since_write {
+            Some(frames) => {
+                *frames += 1;
+                *frames >= WRITE_BACK_DELAY
+            }
+            None => false,
+        }
+    }
+
+    /// Writes `data` to a temporary file first and moves it over the old
+    /// one, so a crash halfway through can't leave a broken save behind.
+    /// If that fails the changes are still pending, and get another go
+    /// once the write-back delay has passed again.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if !self.is_dirty() {
+            return Ok(());
+        }
+        if data.is_empty() || data == self.saved.as_slice() {
+            self.frames_since_write = None;
+            return Ok(());
+        }
+
+        if let Err(e) = self.write_file(data) {
+            self.frames_since_write = Some(0);
+            return Err(e);
+        }
+
+        log::info!("Saved to {}", self.path.display());
+        self.saved = data.to_vec();
+        self.frames_since_write = None;
+        Ok(())
+    }
+
+    fn write_file(&self, data: &[u8]) -> io::Result<()> {
+        let temp_path = self.path.with_extension("sav.tmp");
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
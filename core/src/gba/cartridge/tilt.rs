@@ -0,0 +1,70 @@
+// The tilt sensor in Koro Koro Puzzle and Yoshi's Universal Gravitation,
+// see here:
+// https://problemkaputt.de/gbatek-gba-cart-tilt-sensor.htm
+//
+// It sits on the SRAM bus, as those games save to EEPROM. Writing 0x55
+// then 0xAA starts a sample, after which both axes can be read back 12
+// bits at a time.
+
+// What the sensor reads while it's held flat. It doesn't go much further
+// than 0x100 either side of that.
+const CENTRE: i32 = 0x3A0;
+
+pub struct Tilt {
+    x: i16,
+    y: i16,
+    // The last sample taken
+    sample: (u16, u16),
+    // Got the 0x55, waiting for the 0xAA
+    armed: bool,
+}
+
+/// Whether `address` is one of the sensor's registers
+pub fn is_tilt(address: u32) -> bool {
+    (0x0E_00_80_00..0x0E_00_86_00).contains(&address)
+}
+
+impl Tilt {
+    pub fn new() -> Tilt {
+        Tilt {
+            x: 0,
+            y: 0,
+            sample: (CENTRE as u16, CENTRE as u16),
+            armed: false,
+        }
+    }
+
+    /// Positive x is tilted right and positive y is tilted towards the
+    /// player. The full range covers about as far as it's ever tilted.
+    pub fn set(&mut self, x: i16, y: i16) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn read(&self, address: u32) -> u8 {
+        let (x, y) = self.sample;
+        match address & 0xFF_00 {
+            0x82_00 => x as u8,
+            // Bit 7 says the sample is ready, which it always is
+            0x83_00 => (x >> 8) as u8 | 0x80,
+            0x84_00 => y as u8,
+            0x85_00 => (y >> 8) as u8,
+            _ => 0x00,
+        }
+    }
+
+    pub fn write(&mut self, address: u32, value: u8) {
+        match (address & 0xFF_00, value) {
+            (0x80_00, 0x55) => self.armed = true,
+            (0x81_00, 0xAA) if self.armed => {
+                self.sample = (axis(self.x), axis(self.y));
+                self.armed = false;
+            }
+            _ => log::debug!("Unexpected tilt write {:#2X} to {:#2X}", value, address),
+        }
+    }
+}
+
+fn axis(value: i16) -> u16 {
+    (CENTRE - (i32::from(value) >> 7)).clamp(0, 0xFFF) as u16
+}
@@ -0,0 +1,174 @@
+use crate::gba::cpu::arm7tdmi::{PSRFlags, Processor};
+
+// Barrel shifter and flag helpers shared by the ARM and Thumb handlers.
+// The shift semantics follow GBATek's description of the ALU shifter:
+// https://problemkaputt.de/gbatek-arm-opcodes-data-processing-alu.htm
+
+pub const SHIFT_LSL: u32 = 0;
+pub const SHIFT_LSR: u32 = 1;
+pub const SHIFT_ASR: u32 = 2;
+pub const SHIFT_ROR: u32 = 3;
+
+/// Runs `value` through the barrel shifter. `carry` should hold the current
+/// carry flag and is updated with the shifter carry out. `immediate` tells us
+/// whether the amount came from the instruction itself, in which case an
+/// amount of 0 means LSR #32, ASR #32 or RRX instead of "no shift".
+pub fn barrel_shift(
+    shift_type: u32,
+    value: u32,
+    amount: u32,
+    immediate: bool,
+    carry: &mut bool,
+) -> u32 {
+    match shift_type {
+        SHIFT_LSL => lsl(value, amount, carry),
+        SHIFT_LSR => lsr(value, amount, immediate, carry),
+        SHIFT_ASR => asr(value, amount, immediate, carry),
+        SHIFT_ROR => ror(value, amount, immediate, carry),
+        _ => panic!("Incorrect shift type got {}", shift_type),
+    }
+}
+
+pub fn lsl(value: u32, amount: u32, carry: &mut bool) -> u32 {
+    match amount {
+        0 => value,
+        1..=31 => {
+            *carry = (value >> (32 - amount)) & 0x01 == 1;
+            value << amount
+        }
+        32 => {
+            *carry = value & 0x01 == 1;
+            0
+        }
+        _ => {
+            *carry = false;
+            0
+        }
+    }
+}
+
+pub fn lsr(value: u32, amount: u32, immediate: bool, carry: &mut bool) -> u32 {
+    // LSR #0 is encoded as LSR #32 for immediates
+    let amount = if amount == 0 && immediate { 32 } else { amount };
+    match amount {
+        0 => value,
+        1..=31 => {
+            *carry = (value >> (amount - 1)) & 0x01 == 1;
+            value >> amount
+        }
+        32 => {
+            *carry = (value >> 31) == 1;
+            0
+        }
+        _ => {
+            *carry = false;
+            0
+        }
+    }
+}
+
+pub fn asr(value: u32, amount: u32, immediate: bool, carry: &mut bool) -> u32 {
+    // ASR #0 is encoded as ASR #32 for immediates
+    let amount = if amount == 0 && immediate { 32 } else { amount };
+    match amount {
+        0 => value,
+        1..=31 => {
+            *carry = (value >> (amount - 1)) & 0x01 == 1;
+            ((value as i32) >> amount) as u32
+        }
+        _ => {
+            // Everything gets filled with the sign bit
+            *carry = (value >> 31) == 1;
+            ((value as i32) >> 31) as u32
+        }
+    }
+}
+
+pub fn ror(value: u32, amount: u32, immediate: bool, carry: &mut bool) -> u32 {
+    if amount == 0 {
+        if immediate {
+            // ROR #0 is RRX, which shifts the carry into bit 31
+            let res = (u32::from(*carry) << 31) | (value >> 1);
+            *carry = value & 0x01 == 1;
+            return res;
+        }
+        return value;
+    }
+
+    let amount = amount & 0x1F;
+    if amount == 0 {
+        // Rotating by a multiple of 32 leaves the value as is
+        *carry = (value >> 31) == 1;
+        value
+    } else {
+        *carry = (value >> (amount - 1)) & 0x01 == 1;
+        value.rotate_right(amount)
+    }
+}
+
+/// Internal cycles a multiply takes, which depends on how many of the top
+/// bytes of the multiplier are all zeroes, or all ones if it's signed.
+pub fn multiply_cycles(multiplier: u32, signed: bool) -> u32 {
+    let leading = if signed && (multiplier >> 31) == 1 {
+        multiplier.leading_ones()
+    } else {
+        multiplier.leading_zeros()
+    };
+
+    match leading {
+        24..=32 => 1,
+        16..=23 => 2,
+        8..=15 => 3,
+        _ => 4,
+    }
+}
+
+impl Processor {
+    pub fn add(&mut self, op1: u32, op2: u32, set_flags: bool) -> u32 {
+        self.add_with_carry(op1, op2, false, set_flags)
+    }
+
+    pub fn adc(&mut self, op1: u32, op2: u32, set_flags: bool) -> u32 {
+        let carry = self.regs.get_cpsr(PSRFlags::Carry);
+        self.add_with_carry(op1, op2, carry, set_flags)
+    }
+
+    pub fn sub(&mut self, op1: u32, op2: u32, set_flags: bool) -> u32 {
+        // op1 - op2 is the same as op1 + !op2 + 1
+        self.add_with_carry(op1, !op2, true, set_flags)
+    }
+
+    pub fn sbc(&mut self, op1: u32, op2: u32, set_flags: bool) -> u32 {
+        // The carry flag is an inverted borrow for subtraction
+        let carry = self.regs.get_cpsr(PSRFlags::Carry);
+        self.add_with_carry(op1, !op2, carry, set_flags)
+    }
+
+    fn add_with_carry(&mut self, op1: u32, op2: u32, carry: bool, set_flags: bool) -> u32 {
+        let wide = u64::from(op1) + u64::from(op2) + u64::from(carry);
+        let res = wide as u32;
+
+        if set_flags {
+            self.set_nz_flags(res);
+            self.regs.set_cpsr(PSRFlags::Carry, (wide >> 32) == 1);
+            // Overflow happens when both operands have the same sign
+            // but the result has a different one.
+            self.regs.set_cpsr(
+                PSRFlags::Overflow,
+                ((!(op1 ^ op2) & (op1 ^ res)) >> 31) == 1,
+            );
+        }
+        res
+    }
+
+    pub fn set_nz_flags(&mut self, res: u32) {
+        self.regs.set_cpsr(PSRFlags::Negative, (res >> 31) == 1);
+        self.regs.set_cpsr(PSRFlags::Zero, res == 0);
+    }
+
+    /// Logical operations set N and Z from the result and C from the shifter.
+    pub fn set_logical_flags(&mut self, res: u32, carry: bool) {
+        self.set_nz_flags(res);
+        self.regs.set_cpsr(PSRFlags::Carry, carry);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::gba::bus;
+use crate::gba::cpu::arm7tdmi::{Mode, PSRFlags, Processor};
+
+// Exception vectors and return addresses as described here:
+// https://problemkaputt.de/gbatek-arm-cpu-exceptions.htm
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    Reset,
+    Undefined,
+    SoftwareInterrupt,
+    PrefetchAbort,
+    DataAbort,
+    Irq,
+    Fiq,
+}
+
+impl Exception {
+    pub const fn vector(self) -> u32 {
+        match self {
+            Exception::Reset => 0x00,
+            Exception::Undefined => 0x04,
+            Exception::SoftwareInterrupt => 0x08,
+            Exception::PrefetchAbort => 0x0C,
+            Exception::DataAbort => 0x10,
+            Exception::Irq => 0x18,
+            Exception::Fiq => 0x1C,
+        }
+    }
+
+    pub const fn mode(self) -> Mode {
+        match self {
+            Exception::Reset | Exception::SoftwareInterrupt => Mode::Supervisor,
+            Exception::Undefined => Mode::Undefined,
+            Exception::PrefetchAbort | Exception::DataAbort => Mode::Abort,
+            Exception::Irq => Mode::Irq,
+            Exception::Fiq => Mode::Fiq,
+        }
+    }
+}
+
+impl Processor {
+    /// Saves the CPSR into the SPSR of the exception mode, stores the return
+    /// address in the banked LR and jumps to the vector in ARM state.
+    ///
+    /// SWI, undefined and abort exceptions are raised while their instruction
+    /// is executing, whereas IRQ and FIQ are taken between instructions. The
+    /// PC is at a different point of the pipeline in both cases, which is what
+    /// the return address calculation accounts for.
+    pub fn enter_exception(&mut self, bus: &mut bus::Bus, exception: Exception) {
+        log::info!("Entering exception {:?}", exception);
+
+        let thumb = self.regs.get_cpsr(PSRFlags::Thumb);
+        let pc = self.regs.r15_pc;
+        let return_addr = match exception {
+            Exception::Reset => 0,
+            Exception::Undefined | Exception::SoftwareInterrupt | Exception::PrefetchAbort => {
+                // The instruction after the one being executed
+                if thumb {
+                    pc.wrapping_sub(2)
+                } else {
+                    pc.wrapping_sub(4)
+                }
+            }
+            Exception::DataAbort => {
+                // The aborted instruction + 8
+                if thumb {
+                    pc.wrapping_add(4)
+                } else {
+                    pc
+                }
+            }
+            Exception::Irq | Exception::Fiq => {
+                // The next instruction to execute + 4, so that
+                // SUBS PC, LR, #4 resumes right where we were.
+                if thumb {
+                    pc
+                } else {
+                    pc.wrapping_sub(4)
+                }
+            }
+        };
+
+        let cpsr = self.regs.cpsr;
+        self.regs.set_mode(exception.mode());
+        self.regs.set_spsr(cpsr);
+        self.regs.set_reg(14, return_addr);
+
+        self.regs.set_cpsr(PSRFlags::Thumb, false);
+        self.regs.set_cpsr(PSRFlags::IRQOff, true);
+        if matches!(exception, Exception::Reset | Exception::Fiq) {
+            self.regs.set_cpsr(PSRFlags::FIQOff, true);
+        }
+
+        self.regs.r15_pc = exception.vector();
+        self.reload_arm_pipeline(bus);
+    }
+}
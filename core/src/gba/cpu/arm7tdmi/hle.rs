@@ -0,0 +1,616 @@
+use std::f64::consts::PI;
+
+use crate::gba::bus::{self, Access};
+use crate::gba::cpu::arm7tdmi::{PSRFlags, Processor, Registers};
+use crate::gba::system::PowerState;
+
+// High-level emulation of the BIOS functions, used whenever no BIOS file
+// has been loaded. Arguments and results follow the register conventions
+// described here:
+// https://problemkaputt.de/gbatek-bios-functions.htm
+
+// Where the user IRQ handler acknowledges interrupts for IntrWait
+const INTR_CHECK_FLAGS: u32 = 0x03_00_7F_F8;
+// Non-zero if SoftReset should return to EWRAM instead of the cartridge
+const RESET_RETURN_FLAG: u32 = 0x03_00_7F_FA;
+
+const SWI_SOFT_RESET: u32 = 0x00;
+const SWI_REGISTER_RAM_RESET: u32 = 0x01;
+const SWI_HALT: u32 = 0x02;
+const SWI_STOP: u32 = 0x03;
+const SWI_INTR_WAIT: u32 = 0x04;
+const SWI_VBLANK_INTR_WAIT: u32 = 0x05;
+const SWI_DIV: u32 = 0x06;
+const SWI_DIV_ARM: u32 = 0x07;
+const SWI_SQRT: u32 = 0x08;
+const SWI_ARCTAN: u32 = 0x09;
+const SWI_ARCTAN2: u32 = 0x0A;
+const SWI_CPU_SET: u32 = 0x0B;
+const SWI_CPU_FAST_SET: u32 = 0x0C;
+const SWI_GET_BIOS_CHECKSUM: u32 = 0x0D;
+const SWI_BG_AFFINE_SET: u32 = 0x0E;
+const SWI_OBJ_AFFINE_SET: u32 = 0x0F;
+const SWI_BIT_UNPACK: u32 = 0x10;
+const SWI_LZ77_WRAM: u32 = 0x11;
+const SWI_LZ77_VRAM: u32 = 0x12;
+const SWI_HUFFMAN: u32 = 0x13;
+const SWI_RLE_WRAM: u32 = 0x14;
+const SWI_RLE_VRAM: u32 = 0x15;
+const SWI_DIFF8_WRAM: u32 = 0x16;
+const SWI_DIFF8_VRAM: u32 = 0x17;
+const SWI_DIFF16: u32 = 0x18;
+const SWI_SOUND_BIAS: u32 = 0x19;
+const SWI_MIDI_KEY_2_FREQ: u32 = 0x1F;
+
+// What GetBiosChecksum returns on a GBA
+const BIOS_CHECKSUM: u32 = 0xBA_AE_18_7F;
+
+impl Processor {
+    /// Runs the BIOS function `number` right away instead of jumping to
+    /// the SWI vector.
+    pub fn hle_swi(&mut self, bus: &mut bus::Bus, number: u32) {
+        log::debug!("HLE SWI {:#2X}", number);
+
+        match number {
+            SWI_SOFT_RESET => self.soft_reset(bus),
+            SWI_REGISTER_RAM_RESET => register_ram_reset(bus, self.regs.r[0]),
+            SWI_HALT => bus.system.power_state = PowerState::Halted,
+            SWI_STOP => bus.system.power_state = PowerState::Stopped,
+            SWI_INTR_WAIT => self.intr_wait(bus),
+            SWI_VBLANK_INTR_WAIT => {
+                self.regs.r[0] = 1;
+                self.regs.r[1] = 1;
+                self.intr_wait(bus);
+            }
+            SWI_DIV => self.div(self.regs.r[0] as i32, self.regs.r[1] as i32),
+            SWI_DIV_ARM => self.div(self.regs.r[1] as i32, self.regs.r[0] as i32),
+            SWI_SQRT => self.regs.r[0] = f64::from(self.regs.r[0]).sqrt() as u32,
+            SWI_ARCTAN => {
+                let (result, a, b) = arctan(self.regs.r[0] as i32);
+                self.regs.r[0] = result as i16 as u32;
+                self.regs.r[1] = a as u32;
+                self.regs.r[3] = b as u32;
+            }
+            SWI_ARCTAN2 => {
+                let result = arctan2(self.regs.r[0] as i32, self.regs.r[1] as i32);
+                self.regs.r[0] = u32::from(result);
+            }
+            SWI_CPU_SET => cpu_set(bus, self.regs.r[0], self.regs.r[1], self.regs.r[2]),
+            SWI_CPU_FAST_SET => cpu_fast_set(bus, self.regs.r[0], self.regs.r[1], self.regs.r[2]),
+            SWI_GET_BIOS_CHECKSUM => self.regs.r[0] = BIOS_CHECKSUM,
+            SWI_BG_AFFINE_SET => bg_affine_set(bus, self.regs.r[0], self.regs.r[1], self.regs.r[2]),
+            SWI_OBJ_AFFINE_SET => obj_affine_set(
+                bus,
+                self.regs.r[0],
+                self.regs.r[1],
+                self.regs.r[2],
+                self.regs.r[3],
+            ),
+            SWI_BIT_UNPACK => bit_unpack(bus, self.regs.r[0], self.regs.r[1], self.regs.r[2]),
+            SWI_LZ77_WRAM | SWI_LZ77_VRAM => {
+                let data = lz77_decompress(bus, self.regs.r[0]);
+                let unit = OutputUnit::for_vram(number == SWI_LZ77_VRAM);
+                write_output(bus, self.regs.r[1], &data, unit);
+            }
+            SWI_HUFFMAN => {
+                let data = huffman_decompress(bus, self.regs.r[0]);
+                write_output(bus, self.regs.r[1], &data, OutputUnit::Word);
+            }
+            SWI_RLE_WRAM | SWI_RLE_VRAM => {
+                let data = rle_decompress(bus, self.regs.r[0]);
+                let unit = OutputUnit::for_vram(number == SWI_RLE_VRAM);
+                write_output(bus, self.regs.r[1], &data, unit);
+            }
+            SWI_DIFF8_WRAM | SWI_DIFF8_VRAM => {
+                let data = diff8_unfilter(bus, self.regs.r[0]);
+                let unit = OutputUnit::for_vram(number == SWI_DIFF8_VRAM);
+                write_output(bus, self.regs.r[1], &data, unit);
+            }
+            SWI_DIFF16 => {
+                let data = diff16_unfilter(bus, self.regs.r[0]);
+                write_output(bus, self.regs.r[1], &data, OutputUnit::Half);
+            }
+            SWI_SOUND_BIAS => {
+                // The BIOS slowly ramps the level, we just set it
+                let level = if self.regs.r[0] == 0 { 0x000 } else { 0x200 };
+                let bias = bus.read_half(0x04_00_00_88, Access::NonSequential);
+                bus.write_half(
+                    0x04_00_00_88,
+                    (bias & !0x3FF) | level,
+                    Access::NonSequential,
+                );
+            }
+            SWI_MIDI_KEY_2_FREQ => {
+                let freq = bus.read_word(self.regs.r[0].wrapping_add(4), Access::NonSequential);
+                let key = f64::from(self.regs.r[1]) + f64::from(self.regs.r[2]) / 256.0;
+                self.regs.r[0] = (f64::from(freq) / 2f64.powf((180.0 - key) / 12.0)) as u32;
+            }
+            _ => log::warn!("HLE SWI {:#2X} is not implemented", number),
+        }
+    }
+
+    // Clears the top of IWRAM and starts over from the cartridge (or EWRAM)
+    // with the registers the BIOS sets up when booting.
+    fn soft_reset(&mut self, bus: &mut bus::Bus) {
+        let to_ewram = bus.read_byte(RESET_RETURN_FLAG, Access::NonSequential) != 0;
+        fill_zero(bus, 0x03_00_7E_00, 0x200);
+
+        self.regs = Registers::after_boot();
+        self.regs.r15_pc = if to_ewram {
+            0x02_00_00_00
+        } else {
+            0x08_00_00_00
+        };
+        self.reload_arm_pipeline(bus);
+    }
+
+    // The interrupts have to be acknowledged by the user IRQ handler in
+    // the flags at 0x3007FFF8. Until one of the ones we're waiting for
+    // shows up there we halt and run the SWI again once we wake up.
+    fn intr_wait(&mut self, bus: &mut bus::Bus) {
+        bus.write_half(0x04_00_02_08, 1, Access::NonSequential);
+
+        let wanted = self.regs.r[1] & 0x3F_FF;
+        let mut flags = bus.read_half(INTR_CHECK_FLAGS, Access::NonSequential);
+        if self.regs.r[0] == 1 && !self.intr_waiting {
+            // Only interrupts that happen from now on count
+            flags &= !wanted;
+        }
+
+        if flags & wanted != 0 {
+            bus.write_half(INTR_CHECK_FLAGS, flags & !wanted, Access::NonSequential);
+            self.intr_waiting = false;
+        } else {
+            bus.write_half(INTR_CHECK_FLAGS, flags, Access::NonSequential);
+            bus.system.power_state = PowerState::Halted;
+            self.intr_waiting = true;
+            self.repeat_instruction(bus);
+        }
+    }
+
+    // Points the PC back at the instruction being executed, so that it
+    // runs again after the step advances the PC.
+    fn repeat_instruction(&mut self, bus: &mut bus::Bus) {
+        if self.regs.get_cpsr(PSRFlags::Thumb) {
+            self.regs.r15_pc = self.regs.r15_pc.wrapping_sub(4);
+            self.reload_thumb_pipeline(bus);
+        } else {
+            self.regs.r15_pc = self.regs.r15_pc.wrapping_sub(8);
+            self.reload_arm_pipeline(bus);
+        }
+    }
+
+    fn div(&mut self, numerator: i32, denominator: i32) {
+        if denominator == 0 {
+            // The BIOS gets stuck here, so just return something sensible
+            log::warn!("Division of {} by zero", numerator);
+            self.regs.r[0] = if numerator < 0 { -1i32 as u32 } else { 1 };
+            self.regs.r[1] = numerator as u32;
+            self.regs.r[3] = 1;
+            return;
+        }
+
+        let quotient = numerator.wrapping_div(denominator);
+        self.regs.r[0] = quotient as u32;
+        self.regs.r[1] = numerator.wrapping_rem(denominator) as u32;
+        self.regs.r[3] = quotient.unsigned_abs();
+    }
+}
+
+// Only the user mode registers and the last 0x200 bytes of IWRAM survive
+fn register_ram_reset(bus: &mut bus::Bus, flags: u32) {
+    // The screen is always forced blank
+    bus.write_half(0x04_00_00_00, 0x80, Access::NonSequential);
+
+    if flags & 0x01 != 0 {
+        fill_zero(bus, 0x02_00_00_00, 0x4_00_00);
+    }
+    if flags & 0x02 != 0 {
+        fill_zero(bus, 0x03_00_00_00, 0x7E_00);
+    }
+    if flags & 0x04 != 0 {
+        fill_zero(bus, 0x05_00_00_00, 0x4_00);
+    }
+    if flags & 0x08 != 0 {
+        fill_zero(bus, 0x06_00_00_00, 0x1_80_00);
+    }
+    if flags & 0x10 != 0 {
+        fill_zero(bus, 0x07_00_00_00, 0x4_00);
+    }
+    if flags & 0x20 != 0 {
+        fill_zero(bus, 0x04_00_01_20, 0x10);
+        bus.write_half(0x04_00_01_34, 0x80_00, Access::NonSequential);
+        fill_zero(bus, 0x04_00_01_40, 0x04);
+        fill_zero(bus, 0x04_00_01_50, 0x10);
+    }
+    if flags & 0x40 != 0 {
+        fill_zero(bus, 0x04_00_00_60, 0x28);
+        bus.write_half(0x04_00_00_88, 0x2_00, Access::NonSequential);
+        fill_zero(bus, 0x04_00_00_90, 0x18);
+    }
+    if flags & 0x80 != 0 {
+        fill_zero(bus, 0x04_00_00_02, 0x5E);
+        fill_zero(bus, 0x04_00_00_B0, 0x30);
+        fill_zero(bus, 0x04_00_01_00, 0x10);
+        bus.write_half(0x04_00_02_00, 0x0, Access::NonSequential);
+        bus.write_half(0x04_00_02_02, 0xFF_FF, Access::NonSequential);
+        bus.write_half(0x04_00_02_04, 0x0, Access::NonSequential);
+        bus.write_half(0x04_00_02_08, 0x0, Access::NonSequential);
+    }
+}
+
+fn fill_zero(bus: &mut bus::Bus, start: u32, len: u32) {
+    for offset in (0..len).step_by(2) {
+        bus.write_half(start + offset, 0x0, Access::Sequential);
+    }
+}
+
+// How wide the writes of decompressed data are, in bytes
+#[derive(Clone, Copy)]
+enum OutputUnit {
+    Byte = 1,
+    Half = 2,
+    Word = 4,
+}
+
+impl OutputUnit {
+    fn for_vram(vram: bool) -> OutputUnit {
+        if vram {
+            OutputUnit::Half
+        } else {
+            OutputUnit::Byte
+        }
+    }
+}
+
+// Polynomial approximation the BIOS uses, with the input and output in
+// 1.14 fixed point. Also returns the intermediate values it leaves in
+// r1 and r3.
+fn arctan(i: i32) -> (u16, i32, i32) {
+    let a = -(i.wrapping_mul(i) >> 14);
+    let mut b = (0xA9i32.wrapping_mul(a) >> 14) + 0x3_90;
+    for c in [0x9_1C, 0xF_B6, 0x16_AA, 0x20_81, 0x36_51, 0xA2_F9] {
+        b = (b.wrapping_mul(a) >> 14) + c;
+    }
+    ((i.wrapping_mul(b) >> 16) as u16, a, b)
+}
+
+// The full range version where 0x10000 is a full turn
+fn arctan2(x: i32, y: i32) -> u16 {
+    let atan = |i: i32| i32::from(arctan(i).0 as i16);
+    if y == 0 {
+        return if x >= 0 { 0x0 } else { 0x80_00 };
+    }
+    if x == 0 {
+        return if y >= 0 { 0x40_00 } else { 0xC0_00 };
+    }
+
+    let result = if y >= 0 {
+        if x >= 0 && x >= y {
+            atan((y << 14).wrapping_div(x))
+        } else if x < 0 && x.wrapping_neg() >= y {
+            atan((y << 14).wrapping_div(x)) + 0x80_00
+        } else {
+            0x40_00 - atan((x << 14).wrapping_div(y))
+        }
+    } else if x <= 0 && x.wrapping_neg() > y.wrapping_neg() {
+        atan((y << 14).wrapping_div(x)) + 0x80_00
+    } else if x > 0 && x >= y.wrapping_neg() {
+        atan((y << 14).wrapping_div(x)) + 0x1_00_00
+    } else {
+        0xC0_00 - atan((x << 14).wrapping_div(y))
+    };
+    result as u16
+}
+
+// r2 bits 0-20 hold the amount of units, bit 24 fills the destination
+// with the first unit and bit 26 copies words instead of halfwords.
+fn cpu_set(bus: &mut bus::Bus, mut src: u32, mut dst: u32, control: u32) {
+    let count = control & 0x1F_FF_FF;
+    let fill = (control >> 24) & 0x01 == 1;
+    let word = (control >> 26) & 0x01 == 1;
+
+    if word {
+        src &= !3;
+        dst &= !3;
+        let value = bus.read_word(src, Access::NonSequential);
+        for _ in 0..count {
+            let value = if fill {
+                value
+            } else {
+                bus.read_word(src, Access::Sequential)
+            };
+            bus.write_word(dst, value, Access::Sequential);
+            src = src.wrapping_add(if fill { 0 } else { 4 });
+            dst = dst.wrapping_add(4);
+        }
+    } else {
+        src &= !1;
+        dst &= !1;
+        let value = bus.read_half(src, Access::NonSequential);
+        for _ in 0..count {
+            let value = if fill {
+                value
+            } else {
+                bus.read_half(src, Access::Sequential)
+            };
+            bus.write_half(dst, value, Access::Sequential);
+            src = src.wrapping_add(if fill { 0 } else { 2 });
+            dst = dst.wrapping_add(2);
+        }
+    }
+}
+
+// Always copies words, in blocks of 8
+fn cpu_fast_set(bus: &mut bus::Bus, src: u32, dst: u32, control: u32) {
+    let count = ((control & 0x1F_FF_FF) + 7) & !7;
+    cpu_set(bus, src, dst, (control & !0x1F_FF_FF) | (1 << 26) | count);
+}
+
+// Each source entry is 20 bytes: the center of rotation in texture and
+// screen coordinates, the scaling ratios and the angle. The result is a
+// set of BG rotation/scaling parameters along with the reference point.
+fn bg_affine_set(bus: &mut bus::Bus, mut src: u32, mut dst: u32, count: u32) {
+    for _ in 0..count {
+        let ox = f64::from(bus.read_word(src, Access::NonSequential) as i32) / 256.0;
+        let oy = f64::from(bus.read_word(src.wrapping_add(4), Access::Sequential) as i32) / 256.0;
+        let cx = f64::from(bus.read_half(src.wrapping_add(8), Access::Sequential) as i16);
+        let cy = f64::from(bus.read_half(src.wrapping_add(10), Access::Sequential) as i16);
+        let sx = f64::from(bus.read_half(src.wrapping_add(12), Access::Sequential) as i16) / 256.0;
+        let sy = f64::from(bus.read_half(src.wrapping_add(14), Access::Sequential) as i16) / 256.0;
+        let theta =
+            f64::from(bus.read_half(src.wrapping_add(16), Access::Sequential) >> 8) / 128.0 * PI;
+
+        let (sin, cos) = theta.sin_cos();
+        let (pa, pb, pc, pd) = (cos * sx, -sin * sx, sin * sy, cos * sy);
+        let x = ox - (pa * cx + pb * cy);
+        let y = oy - (pc * cx + pd * cy);
+
+        for (i, p) in [pa, pb, pc, pd].into_iter().enumerate() {
+            bus.write_half(
+                dst.wrapping_add(i as u32 * 2),
+                (p * 256.0) as i32 as u32,
+                Access::Sequential,
+            );
+        }
+        bus.write_word(
+            dst.wrapping_add(8),
+            (x * 256.0) as i32 as u32,
+            Access::Sequential,
+        );
+        bus.write_word(
+            dst.wrapping_add(12),
+            (y * 256.0) as i32 as u32,
+            Access::Sequential,
+        );
+
+        src = src.wrapping_add(20);
+        dst = dst.wrapping_add(16);
+    }
+}
+
+// Source entries are 8 bytes of scaling ratios and angle. The four
+// parameters are written `stride` bytes apart so they can go straight
+// into OAM.
+fn obj_affine_set(bus: &mut bus::Bus, mut src: u32, mut dst: u32, count: u32, stride: u32) {
+    for _ in 0..count {
+        let sx = f64::from(bus.read_half(src, Access::NonSequential) as i16) / 256.0;
+        let sy = f64::from(bus.read_half(src.wrapping_add(2), Access::Sequential) as i16) / 256.0;
+        let theta =
+            f64::from(bus.read_half(src.wrapping_add(4), Access::Sequential) >> 8) / 128.0 * PI;
+
+        let (sin, cos) = theta.sin_cos();
+        for p in [cos * sx, -sin * sx, sin * sy, cos * sy] {
+            bus.write_half(dst, (p * 256.0) as i32 as u32, Access::NonSequential);
+            dst = dst.wrapping_add(stride);
+        }
+        src = src.wrapping_add(8);
+    }
+}
+
+// Expands units of 1, 2, 4 or 8 bits into units of up to 32 bits. The
+// info struct holds the source length, both widths and an offset that
+// gets added to every unit (or only the non-zero ones unless bit 31 is set).
+fn bit_unpack(bus: &mut bus::Bus, mut src: u32, mut dst: u32, info: u32) {
+    let len = bus.read_half(info, Access::NonSequential);
+    let src_width = u32::from(bus.read_byte(info.wrapping_add(2), Access::Sequential));
+    let dst_width = u32::from(bus.read_byte(info.wrapping_add(3), Access::Sequential));
+    let offset = bus.read_word(info.wrapping_add(4), Access::Sequential);
+    let zero_offset = (offset >> 31) & 0x01 == 1;
+    let offset = offset & 0x7F_FF_FF_FF;
+
+    if !matches!(src_width, 1 | 2 | 4 | 8) || !matches!(dst_width, 1 | 2 | 4 | 8 | 16 | 32) {
+        log::warn!("Invalid BitUnPack widths {} -> {}", src_width, dst_width);
+        return;
+    }
+
+    let src_mask = (1 << src_width) - 1;
+    let mut out = 0u32;
+    let mut out_bits = 0;
+    for _ in 0..len {
+        let byte = u32::from(bus.read_byte(src, Access::Sequential));
+        src = src.wrapping_add(1);
+
+        for shift in (0..8).step_by(src_width as usize) {
+            let mut unit = (byte >> shift) & src_mask;
+            if unit != 0 || zero_offset {
+                unit = unit.wrapping_add(offset);
+            }
+            out |= unit.checked_shl(out_bits).unwrap_or(0);
+            out_bits += dst_width;
+            if out_bits == 32 {
+                bus.write_word(dst, out, Access::Sequential);
+                dst = dst.wrapping_add(4);
+                out = 0;
+                out_bits = 0;
+            }
+        }
+    }
+}
+
+// Every compressed stream starts off with the type in bits 4-7 and the
+// decompressed size in bits 8-31.
+fn read_header(bus: &mut bus::Bus, src: u32) -> (u32, usize) {
+    let header = bus.read_word(src, Access::NonSequential);
+    (header & 0xFF, (header >> 8) as usize)
+}
+
+fn lz77_decompress(bus: &mut bus::Bus, src: u32) -> Vec<u8> {
+    let (_, size) = read_header(bus, src);
+    let mut data = Vec::with_capacity(size);
+    let mut src = src.wrapping_add(4);
+
+    while data.len() < size {
+        let flags = bus.read_byte(src, Access::Sequential);
+        src = src.wrapping_add(1);
+
+        for block in (0..8).rev() {
+            if data.len() >= size {
+                break;
+            }
+
+            if (flags >> block) & 0x01 == 0 {
+                data.push(bus.read_byte(src, Access::Sequential));
+                src = src.wrapping_add(1);
+                continue;
+            }
+
+            // Copies 3-18 bytes from 1-4096 bytes back
+            let hi = usize::from(bus.read_byte(src, Access::Sequential));
+            let lo = usize::from(bus.read_byte(src.wrapping_add(1), Access::Sequential));
+            src = src.wrapping_add(2);
+            let len = (hi >> 4) + 3;
+            let disp = (((hi & 0x0F) << 8) | lo) + 1;
+            for _ in 0..len {
+                let byte = data.len().checked_sub(disp).map_or(0, |i| data[i]);
+                data.push(byte);
+            }
+        }
+    }
+    data.truncate(size);
+    data
+}
+
+// The tree comes right after the header, and the bitstream after the
+// tree in words that are read from the top bit down.
+fn huffman_decompress(bus: &mut bus::Bus, src: u32) -> Vec<u8> {
+    let (header, size) = read_header(bus, src);
+    let unit_bits = header & 0x0F;
+    if unit_bits != 4 && unit_bits != 8 {
+        log::warn!("Invalid Huffman data size {}", unit_bits);
+        return Vec::new();
+    }
+
+    let tree_size = u32::from(bus.read_byte(src.wrapping_add(4), Access::Sequential));
+    let root = src.wrapping_add(5);
+    let mut stream = src.wrapping_add(4 + (tree_size + 1) * 2);
+
+    let mut data = Vec::with_capacity(size);
+    let mut node_addr = root;
+    let mut node = bus.read_byte(root, Access::Sequential);
+    let mut out = 0u32;
+    let mut out_bits = 0;
+
+    while data.len() < size {
+        let bits = bus.read_word(stream, Access::Sequential);
+        stream = stream.wrapping_add(4);
+
+        for bit in (0..32).rev() {
+            let right = (bits >> bit) & 0x01;
+            let child = (node_addr & !1).wrapping_add(u32::from(node & 0x3F) * 2 + 2 + right);
+            let is_data = (node >> (7 - right)) & 0x01 == 1;
+
+            if is_data {
+                let value = u32::from(bus.read_byte(child, Access::Sequential));
+                out |= value << out_bits;
+                out_bits += unit_bits;
+                if out_bits == 32 {
+                    data.extend_from_slice(&out.to_le_bytes());
+                    out = 0;
+                    out_bits = 0;
+                }
+                node_addr = root;
+            } else {
+                node_addr = child;
+            }
+            node = bus.read_byte(node_addr, Access::Sequential);
+
+            if data.len() >= size {
+                break;
+            }
+        }
+    }
+    data.truncate(size);
+    data
+}
+
+fn rle_decompress(bus: &mut bus::Bus, src: u32) -> Vec<u8> {
+    let (_, size) = read_header(bus, src);
+    let mut data = Vec::with_capacity(size);
+    let mut src = src.wrapping_add(4);
+
+    while data.len() < size {
+        let flag = bus.read_byte(src, Access::Sequential);
+        src = src.wrapping_add(1);
+
+        if (flag >> 7) & 0x01 == 1 {
+            // A run of 3-130 copies of the next byte
+            let len = usize::from(flag & 0x7F) + 3;
+            let byte = bus.read_byte(src, Access::Sequential);
+            src = src.wrapping_add(1);
+            data.resize(data.len() + len, byte);
+        } else {
+            // 1-128 bytes that are stored as they are
+            for _ in 0..usize::from(flag & 0x7F) + 1 {
+                data.push(bus.read_byte(src, Access::Sequential));
+                src = src.wrapping_add(1);
+            }
+        }
+    }
+    data.truncate(size);
+    data
+}
+
+fn diff8_unfilter(bus: &mut bus::Bus, src: u32) -> Vec<u8> {
+    let (_, size) = read_header(bus, src);
+    let mut data = Vec::with_capacity(size);
+    let mut value = 0u8;
+
+    for i in 0..size as u32 {
+        value = value.wrapping_add(bus.read_byte(src.wrapping_add(4 + i), Access::Sequential));
+        data.push(value);
+    }
+    data
+}
+
+fn diff16_unfilter(bus: &mut bus::Bus, src: u32) -> Vec<u8> {
+    let (_, size) = read_header(bus, src);
+    let mut data = Vec::with_capacity(size);
+    let mut value = 0u16;
+
+    for i in (0..size as u32).step_by(2) {
+        value =
+            value.wrapping_add(bus.read_half(src.wrapping_add(4 + i), Access::Sequential) as u16);
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.truncate(size);
+    data
+}
+
+// VRAM can't be written a byte at a time, so the "Vram" variants of the
+// functions write halfwords instead. Huffman always writes words.
+fn write_output(bus: &mut bus::Bus, dst: u32, data: &[u8], unit: OutputUnit) {
+    let size = unit as usize;
+    for (i, chunk) in data.chunks(size).enumerate() {
+        let address = dst.wrapping_add((i * size) as u32);
+        let value = chunk
+            .iter()
+            .rev()
+            .fold(0u32, |value, &byte| (value << 8) | u32::from(byte));
+        match unit {
+            OutputUnit::Byte => bus.write_byte(address, value as u8, Access::Sequential),
+            OutputUnit::Half => bus.write_half(address, value, Access::Sequential),
+            OutputUnit::Word => bus.write_word(address, value, Access::Sequential),
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use crate::gba::bus::{self};
-use crate::gba::cpu::arm7tdmi::{self, PSRFlags};
+use crate::gba::bus::{self, Access};
+use crate::gba::cpu::arm7tdmi::{self, alu, Exception, Mode, PSRFlags};
 
 #[derive(Clone, Copy)]
 pub struct ArmInstruction {
@@ -21,6 +21,7 @@ pub enum Instruction {
     BlockDataTransfer,
     SingleDataSwap,
     CoprocessorInstructions,
+    Undefined,
     Unknown,
 }
 
@@ -41,6 +42,16 @@ pub const ARM_UNKNOWN: ArmInstruction = ArmInstruction {
     handler: ArmInstruction::unknown_instruction,
 };
 
+pub const ARM_UNDEFINED: ArmInstruction = ArmInstruction {
+    name: Instruction::Undefined,
+    handler: ArmInstruction::undefined_instruction,
+};
+
+pub const ARM_SOFTWARE_INTERRUPT: ArmInstruction = ArmInstruction {
+    name: Instruction::SoftwareInterrupt,
+    handler: ArmInstruction::software_interrupt,
+};
+
 pub const ARM_DATA_PROC: ArmInstruction = ArmInstruction {
     name: Instruction::DataProcessing,
     handler: ArmInstruction::data_processing,
@@ -71,6 +82,11 @@ pub const ARM_HALF_SIGNED_TRANSFER: ArmInstruction = ArmInstruction {
     handler: ArmInstruction::halfword_signed_transfer,
 };
 
+pub const ARM_SINGLE_DATA_SWAP: ArmInstruction = ArmInstruction {
+    name: Instruction::SingleDataSwap,
+    handler: ArmInstruction::single_data_swap,
+};
+
 pub const ARM_BLOCK_DATA_TRANSFER: ArmInstruction = ArmInstruction {
     name: Instruction::BlockDataTransfer,
     handler: ArmInstruction::block_data_transfer,
@@ -91,11 +107,24 @@ impl ArmInstruction {
         panic!("Error:Unknown instruction! Got {:#2X}\n", opcode);
     }
 
+    pub fn undefined_instruction(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, opcode: u32) {
+        log::warn!("Undefined instruction {:#2X}", opcode);
+        cpu.enter_exception(bus, Exception::Undefined);
+    }
+
+    pub fn software_interrupt(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
+        log::info!("SWI {:#2X}", (instr >> 16) & 0xFF);
+        if bus.bios.loaded() {
+            cpu.enter_exception(bus, Exception::SoftwareInterrupt);
+        } else {
+            cpu.hle_swi(bus, (instr >> 16) & 0xFF);
+        }
+    }
+
     pub fn data_processing(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
         log::info!("Data Processing Instruction");
 
         let opcode: u32 = (instr >> 21) & 0x0F;
-        // TODO: Check set flags
         let set_flags = (instr >> 20) & 0x01 == 1;
         let is_imm = (instr >> 25) & 0x01 == 1;
 
@@ -104,112 +133,208 @@ impl ArmInstruction {
 
         log::debug!("Registers: Rn {}, Rd {}", reg_op1, reg_dest);
 
-        // 1 means shift by register, 0 means immediate
-        let shift_type = (instr >> 4) == 1;
-        let op1: u32;
-        let op2: u32;
-        let mut carry = (cpu.regs.cpsr >> 29) & 0x01;
+        let mut op1 = cpu.regs.get_reg(reg_op1);
+        let mut carry = cpu.regs.get_cpsr(PSRFlags::Carry);
 
-        if is_imm {
+        let op2 = if is_imm {
             log::info!("Immediate as 2nd operand");
 
+            // The carry only changes if the immediate is actually rotated
             let imm = instr & 0xFF;
-            let shift = ((instr >> 8) & 0xF) * 2;
-            if shift != 0 {
-                // Need to get carry from here?
-                carry = (imm >> (shift - 1)) & 0x01;
-                op2 = imm.rotate_right(shift);
-            } else {
-                op2 = imm;
-            }
-
-            op1 = cpu.regs.get_reg(reg_op1);
-            log::debug!("Op1 is reg {} with value {:#2X}", reg_op1, op1);
-            log::debug!("Op2 is immediate shift: {:#2X}", op2);
+            let rotate = ((instr >> 8) & 0xF) * 2;
+            alu::ror(imm, rotate, false, &mut carry)
         } else {
             log::info!("Register as 2nd operand");
 
-            if shift_type {
-                // Register
-                panic!("TODO");
+            let shift_type = (instr >> 5) & 0b11;
+            let rm = instr & 0xF;
+            let mut value = cpu.regs.get_reg(rm);
+
+            // 1 means shift by register, 0 means immediate
+            if (instr >> 4) & 0x01 == 1 {
+                log::info!("Shifting by register");
+                let amount = cpu.regs.get_reg((instr >> 8) & 0xF) & 0xFF;
+
+                // The register shift takes an extra cycle, so the PC
+                // has moved along by then and reads as PC + 12.
+                bus.idle(1);
+                if rm == 15 {
+                    value = value.wrapping_add(4);
+                }
+                if reg_op1 == 15 {
+                    op1 = op1.wrapping_add(4);
+                }
+                alu::barrel_shift(shift_type, value, amount, false, &mut carry)
             } else {
-                // Immediate
                 log::info!("Shifting by immediate");
-                let imm = (instr >> 7) & 0x1F;
-                let rm = (instr) & 0xF;
+                let amount = (instr >> 7) & 0x1F;
+                alu::barrel_shift(shift_type, value, amount, true, &mut carry)
+            }
+        };
 
-                log::debug!("Imm {:#2X}, Rm register {}", imm, rm);
+        log::debug!("Op1 is {:#2X}, Op2 is {:#2X}", op1, op2);
+
+        // With Rd = R15 the S bit means restoring the CPSR from the SPSR
+        // instead of touching the flags.
+        let restore_psr = set_flags && reg_dest == 15;
+        let set_flags = set_flags && !restore_psr;
+
+        let res = match opcode {
+            // AND, EOR, ORR, BIC and the test variants
+            0x0 | 0x8 => op1 & op2,
+            0x1 | 0x9 => op1 ^ op2,
+            0xC => op1 | op2,
+            0xE => op1 & !op2,
+            // MOV, MVN
+            0xD => op2,
+            0xF => !op2,
+            // SUB, RSB, ADD, ADC, SBC, RSC and CMP, CMN
+            0x2 | 0xA => cpu.sub(op1, op2, set_flags),
+            0x3 => cpu.sub(op2, op1, set_flags),
+            0x4 | 0xB => cpu.add(op1, op2, set_flags),
+            0x5 => cpu.adc(op1, op2, set_flags),
+            0x6 => cpu.sbc(op1, op2, set_flags),
+            0x7 => cpu.sbc(op2, op1, set_flags),
+            _ => unreachable!(),
+        };
 
-                op1 = cpu.regs.get_reg(reg_op1);
-                op2 = cpu.regs.get_reg(rm) << imm;
-            }
+        // Logical operations take their carry from the shifter
+        let logical = matches!(opcode, 0x0 | 0x1 | 0x8 | 0x9 | 0xC..=0xF);
+        if logical && set_flags {
+            cpu.set_logical_flags(res, carry);
         }
 
-        match opcode {
-            // ADD
-            0x04 => cpu.regs.set_reg(reg_dest, op1 + op2),
-            // TST
-            0x08 => {
-                let res: u32 = op1 & op2;
-                log::debug!("TST: Result is {} with hex {:#2X}", res, res);
-                cpu.regs.set_cpsr(PSRFlags::Negative, (res >> 31) == 1);
-                cpu.regs.set_cpsr(PSRFlags::Zero, res == 0);
-                // Carry bit is the carry flag of the shift operation
-                // in the TST operation.
-                cpu.regs.set_cpsr(PSRFlags::Carry, carry == 1);
-                // Don't ask me how this works please :D
-                cpu.regs
-                    .set_cpsr(PSRFlags::Overflow, (((op1 ^ op2) & (op1 ^ res)) >> 31) == 1);
-            }
-            // CMP
-            0x0A => {
-                let res = op1.wrapping_sub(op2);
-                log::debug!("CMP: Result is {} with hex {:#2X}", res, res);
-
-                cpu.regs.set_cpsr(PSRFlags::Negative, (res >> 31) == 1);
-                cpu.regs.set_cpsr(PSRFlags::Zero, res == 0);
-                cpu.regs.set_cpsr(PSRFlags::Carry, op1 >= op2);
-                // Don't ask me how this works please :D
-                cpu.regs
-                    .set_cpsr(PSRFlags::Overflow, (((op1 ^ op2) & (op1 ^ res)) >> 31) == 1);
-            }
-            // MOV
-            0x0D => {
-                cpu.regs.set_reg(reg_dest, op2);
-                log::debug!(
-                    "MOV: Setting reg {} to result {} with hex {:#2X}",
-                    reg_dest,
-                    op2,
-                    op2
-                );
-            }
-            _ => panic!("Opcode {:#2X} not implemented for data proc!", opcode),
+        // TST, TEQ, CMP and CMN only set flags
+        if (0x8..=0xB).contains(&opcode) {
+            log::debug!("Test opcode {:#2X}: result {:#2X}", opcode, res);
+            return;
         }
 
+        cpu.regs.set_reg(reg_dest, res);
+        log::debug!("Dest reg is {} with {:#2X}", reg_dest, res);
+
         if reg_dest == 15 {
+            if restore_psr {
+                log::debug!("Restoring CPSR from SPSR");
+                cpu.regs.cpsr = cpu.regs.get_spsr();
+            }
+
             log::debug!("Reloading pipeline in Data Processing!");
-            cpu.reload_arm_pipeline(bus);
+            if cpu.regs.get_cpsr(PSRFlags::Thumb) {
+                cpu.regs.r15_pc &= !1;
+                cpu.reload_thumb_pipeline(bus);
+            } else {
+                cpu.regs.r15_pc &= !3;
+                cpu.reload_arm_pipeline(bus);
+            }
         }
-
-        log::debug!(
-            "Dest reg is {} with {:#2X}",
-            reg_dest,
-            cpu.regs.get_reg(reg_dest)
-        );
     }
 
-    pub fn multiply(_cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, _instr: u32) {
-        panic!("MULTIPLY: TODO!");
+    pub fn multiply(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
+        let accumulate = (instr >> 21) & 0x01 == 1;
+        let set_flags = (instr >> 20) & 0x01 == 1;
+
+        let reg_dest = (instr >> 16) & 0x0F;
+        let rn = cpu.regs.get_reg((instr >> 12) & 0x0F);
+        let rs = cpu.regs.get_reg((instr >> 8) & 0x0F);
+        let rm = cpu.regs.get_reg(instr & 0x0F);
+
+        let mut res = rm.wrapping_mul(rs);
+        let mut cycles = alu::multiply_cycles(rs, true);
+        if accumulate {
+            res = res.wrapping_add(rn);
+            cycles += 1;
+        }
+        bus.idle(cycles);
+
+        // The carry flag is garbage after a multiply, so we leave it alone
+        if set_flags {
+            cpu.set_nz_flags(res);
+        }
+        cpu.regs.set_reg(reg_dest, res);
     }
 
-    pub fn multiply_long(_cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, _instr: u32) {
-        panic!("MULTIPLY LONG: TODO!");
+    pub fn multiply_long(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
+        let signed = (instr >> 22) & 0x01 == 1;
+        let accumulate = (instr >> 21) & 0x01 == 1;
+        let set_flags = (instr >> 20) & 0x01 == 1;
+
+        let reg_hi = (instr >> 16) & 0x0F;
+        let reg_lo = (instr >> 12) & 0x0F;
+        let rs = cpu.regs.get_reg((instr >> 8) & 0x0F);
+        let rm = cpu.regs.get_reg(instr & 0x0F);
+
+        let mut res = if signed {
+            (i64::from(rm as i32) * i64::from(rs as i32)) as u64
+        } else {
+            u64::from(rm) * u64::from(rs)
+        };
+
+        let mut cycles = alu::multiply_cycles(rs, signed) + 1;
+        if accumulate {
+            let acc =
+                (u64::from(cpu.regs.get_reg(reg_hi)) << 32) | u64::from(cpu.regs.get_reg(reg_lo));
+            res = res.wrapping_add(acc);
+            cycles += 1;
+        }
+        bus.idle(cycles);
+
+        if set_flags {
+            cpu.regs.set_cpsr(PSRFlags::Negative, (res >> 63) == 1);
+            cpu.regs.set_cpsr(PSRFlags::Zero, res == 0);
+        }
+        cpu.regs.set_reg(reg_lo, res as u32);
+        cpu.regs.set_reg(reg_hi, (res >> 32) as u32);
     }
 
-    pub fn status_transfer(_cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, instr: u32) {
+    pub fn status_transfer(cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, instr: u32) {
         let is_imm = (instr >> 25) & 0x01 == 1;
         // 0=CPSR, 1=SPSR_<current mode>
         let psr = (instr >> 22) & 0x01 == 1;
+        // 0=MRS, 1=MSR
+        let write = (instr >> 21) & 0x01 == 1;
+
+        if !write {
+            let reg_dest = (instr >> 12) & 0x0F;
+            let value = if psr {
+                cpu.regs.get_spsr()
+            } else {
+                cpu.regs.cpsr
+            };
+            log::debug!("MRS: Setting reg {} to {:#2X}", reg_dest, value);
+            cpu.regs.set_reg(reg_dest, value);
+            return;
+        }
+
+        let value = if is_imm {
+            let imm = instr & 0xFF;
+            let rotate = ((instr >> 8) & 0xF) * 2;
+            imm.rotate_right(rotate)
+        } else {
+            cpu.regs.get_reg(instr & 0x0F)
+        };
+
+        // Bits 16 - 19 select which bytes of the PSR get written
+        let mut mask = 0;
+        for field in 0..4 {
+            if (instr >> (16 + field)) & 0x01 == 1 {
+                mask |= 0xFF << (field * 8);
+            }
+        }
+
+        log::debug!("MSR: Writing {:#2X} with mask {:#2X}", value, mask);
+        if psr {
+            let spsr = cpu.regs.get_spsr();
+            cpu.regs.set_spsr((spsr & !mask) | (value & mask));
+        } else {
+            // User mode can only change the flags. The T bit can't be
+            // changed through MSR either, only through BX.
+            if cpu.regs.mode() == Mode::User {
+                mask &= 0xFF_00_00_00;
+            }
+            mask &= !(1 << 5);
+            cpu.regs.cpsr = (cpu.regs.cpsr & !mask) | (value & mask);
+        }
     }
 
     pub fn single_data_transfer(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
@@ -217,77 +342,84 @@ impl ArmInstruction {
         let pre = (instr >> 24) & 0x01 == 1;
         let add = (instr >> 23) & 0x01 == 1;
         let byte = (instr >> 22) & 0x01 == 1;
+        // Post indexing always writes back
         let writeback = (instr >> 21) & 0x01 == 1 || !pre;
         let load = (instr >> 20) & 0x01 == 1;
 
         let reg_base = (instr >> 16) & 0x0F;
         let reg_dest = (instr >> 12) & 0x0F;
 
-        let mut address = cpu.regs.r[reg_base as usize];
+        let base = cpu.regs.get_reg(reg_base);
 
         let offset = if is_imm {
             log::info!("Immediate offset!");
-            //println!("IMM");
             instr & 0xFFF
         } else {
             log::info!("Register offset!");
-            //println!("REG");
             let shift_amt = (instr >> 7) & 0x1F;
             let shift_type = (instr >> 5) & 0b11;
             let reg_offset = instr & 0xF;
-            //log::debug!("Shift is {:#2X}", offset);
-            //log::debug!("Reg is {:#2X}", offset);
-            //log::debug!("Offset is {:#2X}", offset);
-
-            // Might need to abstract this out later!
-            match shift_type {
-                // LSL
-                0 => cpu.regs.get_reg(reg_offset) << shift_amt,
-                // LSR
-                1 => panic!("LSR NOT DONE"),
-                // ASR
-                2 => panic!("ASR NOT DONE"),
-                // ROR
-                3 => panic!("ROR NOT DONE"),
-                _ => panic!("Incorrect shift type got {}", shift_type),
-            }
+
+            // The shifter carry is thrown away for address calculations
+            let mut carry = cpu.regs.get_cpsr(PSRFlags::Carry);
+            alu::barrel_shift(
+                shift_type,
+                cpu.regs.get_reg(reg_offset),
+                shift_amt,
+                true,
+                &mut carry,
+            )
         };
 
         log::debug!("Offset is {:#2X}", offset);
 
-        if pre {
-            log::info!("Pre indexing detected");
-            if add {
-                address += offset;
-            } else {
-                address -= offset;
-            }
-            log::debug!("Address is {:#2X}", address);
-        }
+        let base_new = if add {
+            base.wrapping_add(offset)
+        } else {
+            base.wrapping_sub(offset)
+        };
+        let address = if pre { base_new } else { base };
+        log::debug!("Address is {:#2X}", address);
 
         if load {
-            // LDR
             log::info!("LDR operation");
-            if byte {
-                let value = bus.read_byte(address);
-                cpu.regs.set_reg(reg_dest, u32::from(value));
-                log::info!("Setting {} to {}", reg_dest, value);
+            let value = if byte {
+                u32::from(bus.read_byte(address, Access::NonSequential))
             } else {
-                panic!("LDR not implemented!");
+                bus.read_word(address, Access::NonSequential)
+            };
+            // An extra internal cycle to write the register
+            bus.idle(1);
+
+            // The loaded value wins if the base is also the destination
+            if writeback && reg_base != reg_dest {
+                cpu.regs.set_reg(reg_base, base_new);
+            }
+            cpu.regs.set_reg(reg_dest, value);
+            log::info!("Setting {} to {:#2X}", reg_dest, value);
+
+            if reg_dest == 15 {
+                cpu.regs.r15_pc &= !3;
+                cpu.reload_arm_pipeline(bus);
             }
         } else {
             log::info!("STR operation");
+            // Storing R15 stores PC + 12
+            let mut value = cpu.regs.get_reg(reg_dest);
+            if reg_dest == 15 {
+                value = value.wrapping_add(4);
+            }
+
             if byte {
-                panic!("STRB not implemented!");
+                bus.write_byte(address, value as u8, Access::NonSequential);
             } else {
-                bus.write_word(address, cpu.regs.get_reg(reg_dest));
+                bus.write_word(address, value, Access::NonSequential);
             }
-        }
 
-        if writeback || !pre {
-            let base = cpu.regs.get_reg(reg_base);
-            cpu.regs.set_reg(reg_base, base + offset);
-            log::debug!("Setting reg {} to {:#2X}", reg_base, base + offset);
+            if writeback {
+                cpu.regs.set_reg(reg_base, base_new);
+                log::debug!("Setting reg {} to {:#2X}", reg_base, base_new);
+            }
         }
     }
 
@@ -296,98 +428,127 @@ impl ArmInstruction {
         let pre = (instr >> 24 & 0x01) == 1;
         let add = (instr >> 23 & 0x01) == 1;
         let is_imm = (instr >> 22 & 0x01) == 1;
-        let writeback = (instr >> 21 & 0x01) == 1;
+        // Post indexing always writes back
+        let writeback = (instr >> 21 & 0x01) == 1 || !pre;
         let load = (instr >> 20 & 0x01) == 1;
 
         let reg_base = (instr >> 16) & 0x0F;
         let reg_dest = (instr >> 12) & 0x0F;
 
-        let mut address = cpu.regs.r[reg_base as usize];
+        let base = cpu.regs.get_reg(reg_base);
 
         let offset = if is_imm {
             log::info!("Offset is immediate");
-            let res = (instr & 0x0F) | ((instr >> 4) & 0xF0);
-            log::debug!("Offset is {}", res);
-            res
+            (instr & 0x0F) | ((instr >> 4) & 0xF0)
         } else {
             log::info!("Offset is register");
-            let res = cpu.regs.get_reg(instr & 0x0F);
-            log::debug!("Offset is {}", res);
-            res
+            cpu.regs.get_reg(instr & 0x0F)
         };
+        log::debug!("Offset is {}", offset);
 
-        if pre {
-            log::info!("Pre indexing detected");
-            if add {
-                address += offset;
-            } else {
-                address -= offset;
-            }
-            log::debug!("Address is {:#2X}", address);
-        }
+        let base_new = if add {
+            base.wrapping_add(offset)
+        } else {
+            base.wrapping_sub(offset)
+        };
+        let address = if pre { base_new } else { base };
+        log::debug!("Address is {:#2X}", address);
 
         let opcode = (instr >> 5) & 0b11;
-        match opcode {
-            0 => panic!("Should not have happened! Reserved for SWP"),
-            1 => {
-                if load {
-                    let value = bus.read_half(address);
-                    cpu.regs.set_reg(reg_dest, value);
-                    log::debug!("Setting reg {} to {:#2X}", reg_dest, value);
-                } else {
-                    let value = cpu.regs.get_reg(reg_dest);
-                    bus.write_half(address, value);
-                    log::debug!("Writing to addr {:#2X} with value {:#2X}", address, value);
+        if load {
+            let value = match opcode {
+                // LDRH
+                1 => bus.read_half(address, Access::NonSequential),
+                // LDRSB
+                2 => bus.read_byte(address, Access::NonSequential) as i8 as i32 as u32,
+                // LDRSH, misaligned addresses only sign extend the byte
+                3 => {
+                    if address & 0x01 == 1 {
+                        bus.read_byte(address, Access::NonSequential) as i8 as i32 as u32
+                    } else {
+                        bus.read_half(address, Access::NonSequential) as u16 as i16 as i32 as u32
+                    }
                 }
+                _ => panic!("Should not have happened! Reserved for SWP"),
+            };
+            bus.idle(1);
+
+            // The loaded value wins if the base is also the destination
+            if writeback && reg_base != reg_dest {
+                cpu.regs.set_reg(reg_base, base_new);
             }
-            _ => panic!("Unimplemented got {}", opcode),
-        }
+            cpu.regs.set_reg(reg_dest, value);
+            log::debug!("Setting reg {} to {:#2X}", reg_dest, value);
 
-        if writeback || !pre {
-            let base = cpu.regs.get_reg(reg_base);
-            cpu.regs.set_reg(reg_base, base + offset);
-            log::debug!(
-                "Writing to reg {} with value {:#2X}",
-                reg_base,
-                base + offset
-            );
+            if reg_dest == 15 {
+                cpu.regs.r15_pc &= !3;
+                cpu.reload_arm_pipeline(bus);
+            }
+        } else {
+            // Signed stores don't exist on the ARM7TDMI, so STRH is all we have
+            let mut value = cpu.regs.get_reg(reg_dest);
+            if reg_dest == 15 {
+                value = value.wrapping_add(4);
+            }
+            bus.write_half(address, value, Access::NonSequential);
+            log::debug!("Writing to addr {:#2X} with value {:#2X}", address, value);
+
+            if writeback {
+                cpu.regs.set_reg(reg_base, base_new);
+            }
         }
     }
 
+    pub fn single_data_swap(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
+        let byte = (instr >> 22) & 0x01 == 1;
+        let address = cpu.regs.get_reg((instr >> 16) & 0x0F);
+        let reg_dest = (instr >> 12) & 0x0F;
+        let source = cpu.regs.get_reg(instr & 0x0F);
+        log::info!("SWP at {:#2X}", address);
+
+        // The read and the write are locked together, so the source
+        // is read before the destination gets written.
+        let value = if byte {
+            let value = u32::from(bus.read_byte(address, Access::NonSequential));
+            bus.write_byte(address, source as u8, Access::NonSequential);
+            value
+        } else {
+            let value = bus.read_word(address, Access::NonSequential);
+            bus.write_word(address, source, Access::NonSequential);
+            value
+        };
+        bus.idle(1);
+        cpu.regs.set_reg(reg_dest, value);
+    }
+
     pub fn block_data_transfer(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
         let mut pre = (instr >> 24) & 0x01 == 1;
         let add = (instr >> 23) & 0x01 == 1;
-        // What does this do?
+        // With R15 in a LDM list this restores the CPSR from the SPSR,
+        // otherwise the User mode registers are transferred.
         let psr = (instr >> 22) & 0x01 == 1;
         let writeback = (instr >> 21) & 0x01 == 1;
         let load = (instr >> 20) & 0x01 == 1;
 
         let reg_base = (instr >> 16) & 0x0F;
-        let reg_list = instr & 0xFF_FF;
+        let mut reg_list = instr & 0xFF_FF;
 
         let mut address = cpu.regs.get_reg(reg_base);
-        let mut offset = 0;
 
-        // I think I have to do some special stuff for PC here too...
         // Offset is 4 * the number of registers per https://datasheets.chipdb.org/ARM/arm.pdf
-        if reg_list == 0 {
-            // This means we are modifying the PC R15?
-            panic!("TODO: Block data transfer for when reg list is empty");
+        // An empty list transfers R15 but still moves the base by 0x40.
+        let offset = if reg_list == 0 {
+            reg_list = 1 << 15;
+            0x40
         } else {
-            let i = 0b1;
-            for r in 0..15 {
-                // Get flipped reg list and test if bit r is 1
-                // If so, that means that reg r was not specified in list.
-                if (!reg_list & (i << r)) >> r == 1 {
-                    continue;
-                }
-                offset += 4;
-            }
-        }
+            reg_list.count_ones() * 4
+        };
+
+        let load_pc = load && (reg_list >> 15) & 0x01 == 1;
+        let user_bank = psr && !load_pc;
 
-        // Note that stack grows up?
         let base_new = if add {
-            address + offset
+            address.wrapping_add(offset)
         } else {
             // If we are decrementing, we need to account
             // for the fact that the stack grows up from
@@ -400,33 +561,70 @@ impl ArmInstruction {
             address
         };
 
-        let i = 0b1;
-        for r in 0..15 {
-            // Get flipped reg list and test if bit r is 1
-            // If so, that means that reg r was not specified in list.
-            if (!reg_list & (i << r)) >> r == 1 {
+        // When storing the base, the old value is written if it's the first
+        // register in the list. Otherwise, the written back value is stored.
+        let base_first = reg_list & ((1 << reg_base) - 1) == 0;
+
+        // The first transfer is non sequential, the rest follow on from it
+        let mut access = Access::NonSequential;
+        for r in 0..16 {
+            if (reg_list >> r) & 0x01 == 0 {
                 continue;
             }
             if pre {
-                address += 4;
+                address = address.wrapping_add(4);
             }
 
             if load {
-                let value = bus.read_word(address);
-                cpu.regs.set_reg(r, value);
+                let value = bus.read_word(address, access);
+                if user_bank {
+                    cpu.regs.set_user_reg(r, value);
+                } else {
+                    cpu.regs.set_reg(r, value);
+                }
             } else {
-                bus.write_word(address, cpu.regs.get_reg(r));
+                let value = if r == reg_base && writeback && !base_first {
+                    base_new
+                } else if r == 15 {
+                    // Storing R15 stores PC + 12
+                    cpu.regs.r15_pc.wrapping_add(4)
+                } else if user_bank {
+                    cpu.regs.get_user_reg(r)
+                } else {
+                    cpu.regs.get_reg(r)
+                };
+                bus.write_word(address, value, access);
             }
+            access = Access::Sequential;
 
             if !pre {
-                address += 4;
+                address = address.wrapping_add(4);
             }
         }
 
-        // TODO: Do I need to check the base register?
-        if writeback {
+        if load {
+            bus.idle(1);
+        }
+
+        // Loading the base register overrides the writeback
+        if writeback && !(load && (reg_list >> reg_base) & 0x01 == 1) {
             cpu.regs.set_reg(reg_base, base_new);
         }
+
+        if load_pc {
+            if psr {
+                log::debug!("Restoring CPSR from SPSR");
+                cpu.regs.cpsr = cpu.regs.get_spsr();
+            }
+
+            if cpu.regs.get_cpsr(PSRFlags::Thumb) {
+                cpu.regs.r15_pc &= !1;
+                cpu.reload_thumb_pipeline(bus);
+            } else {
+                cpu.regs.r15_pc &= !3;
+                cpu.reload_arm_pipeline(bus);
+            }
+        }
     }
 
     pub fn branch_and_link(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
@@ -444,29 +642,18 @@ impl ArmInstruction {
             cpu.regs.set_reg(14, cpu.regs.r15_pc - 4);
         }
 
+        // A branch to itself can only be left through an interrupt
+        cpu.idle_loop = imm == -8 && !link;
+
         // Do not add +8 because of the prefetch operation?
         // imm += 4;
         cpu.regs.r15_pc = cpu.regs.r15_pc.wrapping_add(imm as u32);
         cpu.reload_arm_pipeline(bus);
     }
 
-    pub fn branch_and_exchange(_cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, _instr: u32) {
-        panic!("BRANCH AND EXCHANGE TODO!");
-    }
-
-    // TODO: Figure out a better way to possibly separate out
-    // this logic with setting the status flags.
-    fn add(op1: u32, op2: u32, set_flags: bool) -> u32 {
-        if set_flags {
-            panic!("set flags not implemented");
-        }
-        op1 + op2
-    }
-
-    fn cmp(op1: u32, op2: u32, set_flags: bool) -> u32 {
-        if set_flags {
-            panic!("set flags not implemented");
-        }
-        op1 + op2
+    pub fn branch_and_exchange(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u32) {
+        let address = cpu.regs.get_reg(instr & 0x0F);
+        log::info!("Branch and Exchange to {:#2X}", address);
+        cpu.branch_exchange(bus, address);
     }
 }
@@ -1,29 +1,48 @@
 use crate::gba::bus;
 
+mod alu;
+mod exception;
+mod hle;
 mod instructions;
+mod thumb_instructions;
 
+pub use exception::Exception;
 use instructions::*;
+use thumb_instructions::*;
+
+static EXEC_ARM: [ArmInstruction; 4096] = Processor::gen_arm_table();
+static EXEC_THUMB: [ThumbInstruction; 1024] = Processor::gen_thumb_table();
 
 pub struct Processor {
     regs: Registers,
     pipe: [u32; 2],
     exec_arm: [ArmInstruction; 4096],
+    exec_thumb: [ThumbInstruction; 1024],
+    // Set when the last instruction was a branch to itself, which
+    // nothing but an interrupt can get us out of.
+    idle_loop: bool,
+    // Set while an HLE IntrWait is halted, as the SWI runs again every
+    // time the CPU wakes up.
+    intr_waiting: bool,
 }
 
 #[derive(Default)]
 struct Registers {
-    r: [u32; 13], // General registers
+    r: [u32; 13],    // General registers
+    r_fiq: [u32; 5], // R8 - R12 are banked in FIQ mode
 
     r13_sp: u32,
     r13_fiq: u32,
     r13_svc: u32,
     r13_abt: u32,
+    r13_irq: u32,
     r13_und: u32,
 
     r14: u32,
     r14_fiq: u32,
     r14_svc: u32,
     r14_abt: u32,
+    r14_irq: u32,
     r14_und: u32,
 
     r15_pc: u32,
@@ -37,6 +56,18 @@ struct Registers {
     spsr_und: u32,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    User,
+    Fiq,
+    Irq,
+    Supervisor,
+    Abort,
+    Undefined,
+    System,
+}
+
+#[derive(Clone, Copy)]
 pub enum PSRFlags {
     Negative,
     Zero,
@@ -51,46 +82,172 @@ pub enum PSRFlags {
 
 impl Processor {
     pub fn new() -> Processor {
-        const EXEC_ARM: [ArmInstruction; 4096] = Processor::gen_arm_table();
         Processor {
-            regs: Registers {
-                r15_pc: 0x08_00_00_00,
-                cpsr: 0x00,
-                ..Registers::default()
-            },
+            regs: Registers::after_boot(),
             pipe: [0xF0_00_00_00; 2],
             exec_arm: EXEC_ARM,
+            exec_thumb: EXEC_THUMB,
+            idle_loop: false,
+            intr_waiting: false,
         }
     }
 
+    /// Puts the CPU in the state it powers on in and starts executing
+    /// the BIOS from the reset vector.
+    pub fn reset(&mut self, bus: &mut bus::Bus) {
+        self.regs = Registers {
+            cpsr: Mode::Supervisor.bits(),
+            ..Registers::default()
+        };
+        self.regs.set_cpsr(PSRFlags::IRQOff, true);
+        self.regs.set_cpsr(PSRFlags::FIQOff, true);
+        self.idle_loop = false;
+        self.intr_waiting = false;
+
+        self.regs.r15_pc = Exception::Reset.vector();
+        self.reload_arm_pipeline(bus);
+        self.advance_pc();
+    }
+
+    /// Starts off with the state the BIOS leaves behind before
+    /// jumping to `entry`, which is the cartridge or a multiboot image.
+    pub fn skip_bios(&mut self, entry: u32) {
+        self.regs = Registers::after_boot();
+        self.regs.r15_pc = entry;
+        self.pipe = [0xF0_00_00_00; 2];
+        self.idle_loop = false;
+        self.intr_waiting = false;
+    }
+
     pub fn step(&mut self, clocks: u32, bus: &mut bus::Bus) {
         let mut c = 0;
         while c < clocks {
-            let instr = self.pipe[0];
-            self.pipe[0] = self.pipe[1];
-            self.pipe[1] = bus.read_word(self.regs.r15_pc);
+            // DMA stalls the CPU until every active channel is done,
+            // and it runs even while the CPU is halted.
+            if bus.dma.active() {
+                let cycles = bus.run_dma();
+                bus.tick(cycles);
+                c += cycles;
+                continue;
+            }
+
+            // Nothing happens until the next interrupt while halted,
+            // so we can skip straight to the next event.
+            if !bus.system.wake_up(&bus.interrupt) {
+                c += Self::skip_to_next_event(clocks.saturating_sub(c), bus);
+                continue;
+            }
 
-            println!("Running addr {:#2X}", self.regs.r15_pc - 8);
-            //println!("Regs {:#2X?}, {:#2X}", self.regs.r, self.regs.r13_sp);
-            println!("INSTR IS {:#2X}", instr);
-            //println!("CLOCKS {}", c);
+            // Interrupts are only taken between instructions
+            if self.irq_pending(bus) {
+                self.enter_exception(bus, Exception::Irq);
+                self.advance_pc();
+            }
 
-            if self.regs.check_cond((instr >> 28) & 0x0F) {
-                let hash = ((instr & 0x0F_F0_00_00) >> 16) | ((instr & 0x00_00_00_F0) >> 4);
+            if self.regs.get_cpsr(PSRFlags::Thumb) {
+                self.step_thumb(bus);
+            } else {
+                self.step_arm(bus);
+            }
+            // Every access and internal cycle has been added up on the bus
+            let cycles = bus.take_cycles();
+            bus.tick(cycles);
+            c += cycles;
 
-                let ArmInstruction { name: _, handler } = self.exec_arm[hash as usize];
-                handler(self, bus, instr);
+            // Same goes for an idle loop, unless an IRQ is about to be taken
+            if self.idle_loop {
+                self.idle_loop = false;
+                if !self.irq_pending(bus) {
+                    log::trace!("Skipping idle loop at {:#2X}", self.regs.r15_pc);
+                    c += Self::skip_to_next_event(clocks.saturating_sub(c), bus);
+                }
             }
-            self.regs.r15_pc += 4;
-            c += 1;
+        }
+    }
+
+    // Fast forwards to whichever comes first, the next scheduled event
+    // or the end of this step.
+    fn skip_to_next_event(remaining: u32, bus: &mut bus::Bus) -> u32 {
+        let cycles = match bus.scheduler.cycles_until_next() {
+            Some(next) => next.min(u64::from(remaining)) as u32,
+            None => remaining,
+        };
+        bus.tick(cycles);
+        cycles
+    }
+
+    fn irq_pending(&self, bus: &bus::Bus) -> bool {
+        bus.interrupt.pending() && !self.regs.get_cpsr(PSRFlags::IRQOff)
+    }
+
+    fn step_arm(&mut self, bus: &mut bus::Bus) {
+        let instr = self.pipe[0];
+        self.pipe[0] = self.pipe[1];
+        self.pipe[1] = bus.fetch_word(self.regs.r15_pc);
+
+        log::trace!("Running addr {:#2X}", self.regs.r15_pc.wrapping_sub(8));
+        log::trace!("INSTR IS {:#2X}", instr);
+
+        if self.regs.check_cond((instr >> 28) & 0x0F) {
+            let hash = ((instr & 0x0F_F0_00_00) >> 16) | ((instr & 0x00_00_00_F0) >> 4);
+
+            let ArmInstruction { name: _, handler } = self.exec_arm[hash as usize];
+            handler(self, bus, instr);
+        }
+        self.advance_pc();
+    }
+
+    fn step_thumb(&mut self, bus: &mut bus::Bus) {
+        let instr = self.pipe[0] as u16;
+        self.pipe[0] = self.pipe[1];
+        self.pipe[1] = bus.fetch_half(self.regs.r15_pc);
+
+        log::trace!(
+            "Running thumb addr {:#2X}",
+            self.regs.r15_pc.wrapping_sub(4)
+        );
+        log::trace!("THUMB INSTR IS {:#2X}", instr);
+
+        // The top 10 bits are enough to tell every Thumb format apart
+        let ThumbInstruction { name: _, handler } = self.exec_thumb[(instr >> 6) as usize];
+        handler(self, bus, instr);
+        self.advance_pc();
+    }
+
+    // The handler might have switched states with BX, so we need to check
+    // the T bit again to know how far to move along.
+    fn advance_pc(&mut self) {
+        if self.regs.get_cpsr(PSRFlags::Thumb) {
+            self.regs.r15_pc = self.regs.r15_pc.wrapping_add(2);
+        } else {
+            self.regs.r15_pc = self.regs.r15_pc.wrapping_add(4);
         }
     }
 
     pub fn reload_arm_pipeline(&mut self, bus: &mut bus::Bus) {
-        // println!("PC is {:#2X}", self.regs.r15_pc);
-        self.pipe[0] = bus.read_word(self.regs.r15_pc);
-        self.pipe[1] = bus.read_word(self.regs.r15_pc + 4);
-        self.regs.r15_pc += 4;
+        self.pipe[0] = bus.fetch_word(self.regs.r15_pc);
+        self.pipe[1] = bus.fetch_word(self.regs.r15_pc.wrapping_add(4));
+        self.regs.r15_pc = self.regs.r15_pc.wrapping_add(4);
+    }
+
+    pub fn reload_thumb_pipeline(&mut self, bus: &mut bus::Bus) {
+        self.pipe[0] = bus.fetch_half(self.regs.r15_pc);
+        self.pipe[1] = bus.fetch_half(self.regs.r15_pc.wrapping_add(2));
+        self.regs.r15_pc = self.regs.r15_pc.wrapping_add(2);
+    }
+
+    /// Jumps to `address`, switching to Thumb state if bit 0 is set
+    /// and to ARM state otherwise.
+    pub fn branch_exchange(&mut self, bus: &mut bus::Bus, address: u32) {
+        if address & 0x01 == 1 {
+            self.regs.set_cpsr(PSRFlags::Thumb, true);
+            self.regs.r15_pc = address & !1;
+            self.reload_thumb_pipeline(bus);
+        } else {
+            self.regs.set_cpsr(PSRFlags::Thumb, false);
+            self.regs.r15_pc = address & !3;
+            self.reload_arm_pipeline(bus);
+        }
     }
 
     const fn gen_arm_table() -> [ArmInstruction; 4096] {
@@ -104,6 +261,64 @@ impl Processor {
         arm_table
     }
 
+    const fn gen_thumb_table() -> [ThumbInstruction; 1024] {
+        let mut thumb_table = [THUMB_UNKNOWN; 1024];
+        let mut i = 0;
+        while i < 1024 {
+            thumb_table[i] = Processor::thumb_decode((i as u16) << 6);
+            i += 1;
+        }
+        thumb_table
+    }
+
+    const fn thumb_decode(instr: u16) -> ThumbInstruction {
+        if (instr & 0xF8_00) == 0x18_00 {
+            THUMB_ADD_SUB
+        } else if (instr & 0xE0_00) == 0x00_00 {
+            THUMB_MOVE_SHIFTED_REG
+        } else if (instr & 0xE0_00) == 0x20_00 {
+            THUMB_MOV_CMP_ADD_SUB
+        } else if (instr & 0xFC_00) == 0x40_00 {
+            THUMB_ALU
+        } else if (instr & 0xFC_00) == 0x44_00 {
+            THUMB_HI_REG_OPS
+        } else if (instr & 0xF8_00) == 0x48_00 {
+            THUMB_PC_RELATIVE_LOAD
+        } else if (instr & 0xF2_00) == 0x50_00 {
+            THUMB_LOAD_STORE_REG
+        } else if (instr & 0xF2_00) == 0x52_00 {
+            THUMB_LOAD_STORE_SIGNED
+        } else if (instr & 0xE0_00) == 0x60_00 {
+            THUMB_LOAD_STORE_IMM
+        } else if (instr & 0xF0_00) == 0x80_00 {
+            THUMB_LOAD_STORE_HALF
+        } else if (instr & 0xF0_00) == 0x90_00 {
+            THUMB_SP_RELATIVE_LOAD_STORE
+        } else if (instr & 0xF0_00) == 0xA0_00 {
+            THUMB_LOAD_ADDRESS
+        } else if (instr & 0xFF_00) == 0xB0_00 {
+            THUMB_ADD_OFFSET_SP
+        } else if (instr & 0xF6_00) == 0xB4_00 {
+            THUMB_PUSH_POP
+        } else if (instr & 0xF0_00) == 0xC0_00 {
+            THUMB_MULTIPLE_LOAD_STORE
+        } else if (instr & 0xFF_00) == 0xDF_00 {
+            THUMB_SOFTWARE_INTERRUPT
+        } else if (instr & 0xFF_00) == 0xDE_00 {
+            // Condition 0b1110 is undefined
+            THUMB_UNDEFINED
+        } else if (instr & 0xF0_00) == 0xD0_00 {
+            THUMB_CONDITIONAL_BRANCH
+        } else if (instr & 0xF8_00) == 0xE0_00 {
+            THUMB_UNCONDITIONAL_BRANCH
+        } else if (instr & 0xF0_00) == 0xF0_00 {
+            THUMB_LONG_BRANCH_LINK
+        } else {
+            // This only leaves 0xE800 which is BLX on later architectures
+            THUMB_UNDEFINED
+        }
+    }
+
     // The decoding logic closely models NanoBoyAdvance's logic
     // See here: https://github.com/nba-emu/NanoBoyAdvance/blob/master/src/nba/src/arm/tablegen/gen_arm.hpp
     const fn arm_decode(instr: u32) -> ArmInstruction {
@@ -113,8 +328,9 @@ impl Processor {
             0b00 => {
                 if (opcode >> 25) & 0x1 == 1 {
                     let opcode = (instr >> 21) & 0xF;
-                    // I might need to check this better?
-                    if opcode == 0b1001 || opcode == 1010 {
+                    let set_flags = (instr >> 20) & 0b1 == 1;
+                    // TST, TEQ, CMP and CMN without the S bit are MSR
+                    if !set_flags && opcode >= 0b1000 && opcode <= 0b1011 {
                         // Should be PSR
                         ARM_STATUS_TRANSFER
                     } else {
@@ -133,8 +349,8 @@ impl Processor {
                         ARM_MULTIPLY
                     }
                 } else if (opcode & 0x01_00_00_F0) == 0x01_00_00_90 {
-                    // Single Data Transfer which is TransSwp12 on GBATek?
-                    ARM_SINGLE_DATA_TRANSFER
+                    // SWP and SWPB, which is TransSwp12 on GBATek
+                    ARM_SINGLE_DATA_SWAP
                 } else if (opcode & 0xF0) == 0xB0 || (opcode & 0xD0) == 0xD0 {
                     // Halfword Data Transfer, register + immediate offset
                     // Signed Data Transfer as well?
@@ -156,9 +372,12 @@ impl Processor {
                 }
             }
             0b01 => {
-                // Might need to check for an undefined instruction here?
-                // For now, we'll just return the Single Data Transfer
-                ARM_SINGLE_DATA_TRANSFER
+                // A register offset with bit 4 set is undefined
+                if (opcode >> 25) & 0x01 == 1 && (opcode >> 4) & 0x01 == 1 {
+                    ARM_UNDEFINED
+                } else {
+                    ARM_SINGLE_DATA_TRANSFER
+                }
             }
             0b10 => {
                 if ((opcode >> 25) & 0x01) == 1 {
@@ -167,81 +386,209 @@ impl Processor {
                     ARM_BLOCK_DATA_TRANSFER
                 }
             }
-            0b11 => ARM_UNKNOWN, // Should do something about interrupts and coprocessor?
+            0b11 => {
+                if (opcode >> 24) & 0b11 == 0b11 {
+                    ARM_SOFTWARE_INTERRUPT
+                } else {
+                    // There are no coprocessors on the GBA, so these
+                    // raise the undefined instruction exception.
+                    ARM_UNDEFINED
+                }
+            }
             _ => panic!("Should not have happened!"),
         }
     }
 }
 
 impl Registers {
-    pub fn get_reg(&self, reg: u32) -> u32 {
-        match reg {
-            0..=12 => self.r[reg as usize],
-            13 => self.r13_sp,
-            14 => self.r14,
-            15 => self.r15_pc,
-            _ => panic!("TODO"),
+    fn after_boot() -> Registers {
+        Registers {
+            r13_sp: 0x03_00_7F_00,
+            r13_svc: 0x03_00_7F_E0,
+            r13_irq: 0x03_00_7F_A0,
+            r15_pc: 0x08_00_00_00,
+            cpsr: Mode::System.bits(),
+            ..Registers::default()
         }
     }
 
+    pub fn mode(&self) -> Mode {
+        Mode::from_bits(self.cpsr)
+    }
+
+    /// Switching modes only means updating the CPSR mode bits, as every
+    /// register access looks up the bank of the current mode.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.cpsr = (self.cpsr & !0x1F) | mode.bits();
+    }
+
+    pub fn get_reg(&self, reg: u32) -> u32 {
+        self.get_mode_reg(reg, self.mode())
+    }
+
     pub fn set_reg(&mut self, reg: u32, val: u32) {
-        match reg {
-            0..=12 => self.r[reg as usize] = val,
-            13 => self.r13_sp = val,
-            14 => self.r14 = val,
-            15 => self.r15_pc = val,
-            _ => panic!("TODO"),
+        self.set_mode_reg(reg, self.mode(), val);
+    }
+
+    /// LDM/STM with the S bit set transfer the User mode registers
+    /// no matter which mode we are currently in.
+    pub fn get_user_reg(&self, reg: u32) -> u32 {
+        self.get_mode_reg(reg, Mode::User)
+    }
+
+    pub fn set_user_reg(&mut self, reg: u32, val: u32) {
+        self.set_mode_reg(reg, Mode::User, val);
+    }
+
+    fn get_mode_reg(&self, reg: u32, mode: Mode) -> u32 {
+        match (reg, mode) {
+            (0..=7, _) => self.r[reg as usize],
+            (8..=12, Mode::Fiq) => self.r_fiq[reg as usize - 8],
+            (8..=12, _) => self.r[reg as usize],
+            (13, Mode::Fiq) => self.r13_fiq,
+            (13, Mode::Irq) => self.r13_irq,
+            (13, Mode::Supervisor) => self.r13_svc,
+            (13, Mode::Abort) => self.r13_abt,
+            (13, Mode::Undefined) => self.r13_und,
+            (13, _) => self.r13_sp,
+            (14, Mode::Fiq) => self.r14_fiq,
+            (14, Mode::Irq) => self.r14_irq,
+            (14, Mode::Supervisor) => self.r14_svc,
+            (14, Mode::Abort) => self.r14_abt,
+            (14, Mode::Undefined) => self.r14_und,
+            (14, _) => self.r14,
+            (15, _) => self.r15_pc,
+            _ => panic!("Invalid register {}!", reg),
+        }
+    }
+
+    fn set_mode_reg(&mut self, reg: u32, mode: Mode, val: u32) {
+        match (reg, mode) {
+            (0..=7, _) => self.r[reg as usize] = val,
+            (8..=12, Mode::Fiq) => self.r_fiq[reg as usize - 8] = val,
+            (8..=12, _) => self.r[reg as usize] = val,
+            (13, Mode::Fiq) => self.r13_fiq = val,
+            (13, Mode::Irq) => self.r13_irq = val,
+            (13, Mode::Supervisor) => self.r13_svc = val,
+            (13, Mode::Abort) => self.r13_abt = val,
+            (13, Mode::Undefined) => self.r13_und = val,
+            (13, _) => self.r13_sp = val,
+            (14, Mode::Fiq) => self.r14_fiq = val,
+            (14, Mode::Irq) => self.r14_irq = val,
+            (14, Mode::Supervisor) => self.r14_svc = val,
+            (14, Mode::Abort) => self.r14_abt = val,
+            (14, Mode::Undefined) => self.r14_und = val,
+            (14, _) => self.r14 = val,
+            (15, _) => self.r15_pc = val,
+            _ => panic!("Invalid register {}!", reg),
+        }
+    }
+
+    /// Returns the SPSR of the current mode. User and System mode
+    /// have no SPSR, so we just hand back the CPSR for those.
+    pub fn get_spsr(&self) -> u32 {
+        match self.mode() {
+            Mode::Fiq => self.spsr_fiq,
+            Mode::Irq => self.spsr_irq,
+            Mode::Supervisor => self.spsr_svc,
+            Mode::Abort => self.spsr_abt,
+            Mode::Undefined => self.spsr_und,
+            Mode::User | Mode::System => self.cpsr,
+        }
+    }
+
+    pub fn set_spsr(&mut self, val: u32) {
+        match self.mode() {
+            Mode::Fiq => self.spsr_fiq = val,
+            Mode::Irq => self.spsr_irq = val,
+            Mode::Supervisor => self.spsr_svc = val,
+            Mode::Abort => self.spsr_abt = val,
+            Mode::Undefined => self.spsr_und = val,
+            // Writes are ignored as there is no SPSR
+            Mode::User | Mode::System => {}
         }
     }
 
     pub fn set_cpsr(&mut self, flag: PSRFlags, set: bool) {
-        match flag {
-            PSRFlags::Negative => {
-                if set {
-                    self.cpsr |= 1 << 31;
-                } else {
-                    self.cpsr &= !(1 << 31);
-                }
-            }
-            PSRFlags::Zero => {
-                if set {
-                    self.cpsr |= 1 << 30;
-                } else {
-                    self.cpsr &= !(1 << 30);
-                }
-            }
-            PSRFlags::Carry => {
-                if set {
-                    self.cpsr |= 1 << 29;
-                } else {
-                    self.cpsr &= !(1 << 29);
-                }
-            }
-            PSRFlags::Overflow => {
-                if set {
-                    self.cpsr |= 1 << 28;
-                } else {
-                    self.cpsr &= !(1 << 28);
-                }
-            }
-            _ => panic!("TODO"),
+        let bit = PSRFlags::bit(flag);
+        if set {
+            self.cpsr |= 1 << bit;
+        } else {
+            self.cpsr &= !(1 << bit);
         }
     }
 
+    pub fn get_cpsr(&self, flag: PSRFlags) -> bool {
+        (self.cpsr >> PSRFlags::bit(flag)) & 0x01 == 1
+    }
+
     pub fn check_cond(&self, cond: u32) -> bool {
-        let n: bool = (self.cpsr >> 31) & 0x01 == 1;
-        let z: bool = (self.cpsr >> 30) & 0x01 == 1;
-        let _c: bool = (self.cpsr >> 29) & 0x01 == 1;
-        let v: bool = (self.cpsr >> 28) & 0x01 == 1;
+        let n = self.get_cpsr(PSRFlags::Negative);
+        let z = self.get_cpsr(PSRFlags::Zero);
+        let c = self.get_cpsr(PSRFlags::Carry);
+        let v = self.get_cpsr(PSRFlags::Overflow);
 
-        // println!("Got {}, {}, {}, {}", n, z, c, v);
         match cond {
             0x0 => z,
             0x1 => !z,
+            0x2 => c,
+            0x3 => !c,
+            0x4 => n,
+            0x5 => !n,
+            0x6 => v,
+            0x7 => !v,
+            0x8 => c && !z,
+            0x9 => !c || z,
+            0xA => n == v,
             0xB => n != v,
+            0xC => !z && n == v,
+            0xD => z || n != v,
             0xE => true,
             0xF => false,
             _ => panic!("Condition {:#2X} not implemented!", cond),
         }
     }
 }
+
+impl Mode {
+    pub const fn from_bits(bits: u32) -> Mode {
+        match bits & 0x1F {
+            0x11 => Mode::Fiq,
+            0x12 => Mode::Irq,
+            0x13 => Mode::Supervisor,
+            0x17 => Mode::Abort,
+            0x1B => Mode::Undefined,
+            0x1F => Mode::System,
+            // Invalid modes behave like User mode
+            _ => Mode::User,
+        }
+    }
+
+    pub const fn bits(self) -> u32 {
+        match self {
+            Mode::User => 0x10,
+            Mode::Fiq => 0x11,
+            Mode::Irq => 0x12,
+            Mode::Supervisor => 0x13,
+            Mode::Abort => 0x17,
+            Mode::Undefined => 0x1B,
+            Mode::System => 0x1F,
+        }
+    }
+}
+
+impl PSRFlags {
+    const fn bit(flag: PSRFlags) -> u32 {
+        match flag {
+            PSRFlags::Negative => 31,
+            PSRFlags::Zero => 30,
+            PSRFlags::Carry => 29,
+            PSRFlags::Overflow => 28,
+            PSRFlags::Saturation => 27,
+            PSRFlags::AbortOff => 8,
+            PSRFlags::IRQOff => 7,
+            PSRFlags::FIQOff => 6,
+            PSRFlags::Thumb => 5,
+        }
+    }
+}
@@ -0,0 +1,717 @@
+use crate::gba::bus::{self, Access};
+use crate::gba::cpu::arm7tdmi::{self, alu, Exception, PSRFlags};
+
+// Thumb formats are numbered as on GBATek, see here:
+// https://problemkaputt.de/gbatek-thumb-instruction-summary.htm
+
+#[derive(Clone, Copy)]
+pub struct ThumbInstruction {
+    pub name: ThumbFormat,
+    pub handler: fn(&mut arm7tdmi::Processor, &mut bus::Bus, u16),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ThumbFormat {
+    MoveShiftedRegister,
+    AddSubtract,
+    MoveCompareAddSubtract,
+    AluOperations,
+    HiRegisterOperations,
+    PcRelativeLoad,
+    LoadStoreRegisterOffset,
+    LoadStoreSignExtended,
+    LoadStoreImmediateOffset,
+    LoadStoreHalfword,
+    SpRelativeLoadStore,
+    LoadAddress,
+    AddOffsetToSp,
+    PushPop,
+    MultipleLoadStore,
+    ConditionalBranch,
+    SoftwareInterrupt,
+    UnconditionalBranch,
+    LongBranchWithLink,
+    Undefined,
+    Unknown,
+}
+
+pub const THUMB_UNKNOWN: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::Unknown,
+    handler: ThumbInstruction::unknown_instruction,
+};
+
+pub const THUMB_UNDEFINED: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::Undefined,
+    handler: ThumbInstruction::undefined_instruction,
+};
+
+pub const THUMB_MOVE_SHIFTED_REG: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::MoveShiftedRegister,
+    handler: ThumbInstruction::move_shifted_register,
+};
+
+pub const THUMB_ADD_SUB: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::AddSubtract,
+    handler: ThumbInstruction::add_subtract,
+};
+
+pub const THUMB_MOV_CMP_ADD_SUB: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::MoveCompareAddSubtract,
+    handler: ThumbInstruction::move_compare_add_subtract,
+};
+
+pub const THUMB_ALU: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::AluOperations,
+    handler: ThumbInstruction::alu_operations,
+};
+
+pub const THUMB_HI_REG_OPS: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::HiRegisterOperations,
+    handler: ThumbInstruction::hi_register_operations,
+};
+
+pub const THUMB_PC_RELATIVE_LOAD: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::PcRelativeLoad,
+    handler: ThumbInstruction::pc_relative_load,
+};
+
+pub const THUMB_LOAD_STORE_REG: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::LoadStoreRegisterOffset,
+    handler: ThumbInstruction::load_store_register_offset,
+};
+
+pub const THUMB_LOAD_STORE_SIGNED: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::LoadStoreSignExtended,
+    handler: ThumbInstruction::load_store_sign_extended,
+};
+
+pub const THUMB_LOAD_STORE_IMM: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::LoadStoreImmediateOffset,
+    handler: ThumbInstruction::load_store_immediate_offset,
+};
+
+pub const THUMB_LOAD_STORE_HALF: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::LoadStoreHalfword,
+    handler: ThumbInstruction::load_store_halfword,
+};
+
+pub const THUMB_SP_RELATIVE_LOAD_STORE: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::SpRelativeLoadStore,
+    handler: ThumbInstruction::sp_relative_load_store,
+};
+
+pub const THUMB_LOAD_ADDRESS: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::LoadAddress,
+    handler: ThumbInstruction::load_address,
+};
+
+pub const THUMB_ADD_OFFSET_SP: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::AddOffsetToSp,
+    handler: ThumbInstruction::add_offset_to_sp,
+};
+
+pub const THUMB_PUSH_POP: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::PushPop,
+    handler: ThumbInstruction::push_pop,
+};
+
+pub const THUMB_MULTIPLE_LOAD_STORE: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::MultipleLoadStore,
+    handler: ThumbInstruction::multiple_load_store,
+};
+
+pub const THUMB_CONDITIONAL_BRANCH: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::ConditionalBranch,
+    handler: ThumbInstruction::conditional_branch,
+};
+
+pub const THUMB_SOFTWARE_INTERRUPT: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::SoftwareInterrupt,
+    handler: ThumbInstruction::software_interrupt,
+};
+
+pub const THUMB_UNCONDITIONAL_BRANCH: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::UnconditionalBranch,
+    handler: ThumbInstruction::unconditional_branch,
+};
+
+pub const THUMB_LONG_BRANCH_LINK: ThumbInstruction = ThumbInstruction {
+    name: ThumbFormat::LongBranchWithLink,
+    handler: ThumbInstruction::long_branch_with_link,
+};
+
+impl ThumbInstruction {
+    pub fn unknown_instruction(_cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, opcode: u16) {
+        panic!("Error:Unknown thumb instruction! Got {:#2X}\n", opcode);
+    }
+
+    pub fn undefined_instruction(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, opcode: u16) {
+        log::warn!("Undefined thumb instruction {:#2X}", opcode);
+        cpu.enter_exception(bus, Exception::Undefined);
+    }
+
+    // Format 1
+    pub fn move_shifted_register(cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let opcode = (instr >> 11) & 0b11;
+        let offset = (instr >> 6) & 0x1F;
+        let reg_src = (instr >> 3) & 0x07;
+        let reg_dest = instr & 0x07;
+
+        let mut carry = cpu.regs.get_cpsr(PSRFlags::Carry);
+        let res = alu::barrel_shift(opcode, cpu.regs.get_reg(reg_src), offset, true, &mut carry);
+        log::debug!("Shift {} by {:#2X}: result {:#2X}", opcode, offset, res);
+
+        cpu.set_logical_flags(res, carry);
+        cpu.regs.set_reg(reg_dest, res);
+    }
+
+    // Format 2
+    pub fn add_subtract(cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let is_imm = (instr >> 10) & 0x01 == 1;
+        let subtract = (instr >> 9) & 0x01 == 1;
+        let operand = (instr >> 6) & 0x07;
+        let reg_src = (instr >> 3) & 0x07;
+        let reg_dest = instr & 0x07;
+
+        let op1 = cpu.regs.get_reg(reg_src);
+        let op2 = if is_imm {
+            operand
+        } else {
+            cpu.regs.get_reg(operand)
+        };
+
+        let res = if subtract {
+            cpu.sub(op1, op2, true)
+        } else {
+            cpu.add(op1, op2, true)
+        };
+        cpu.regs.set_reg(reg_dest, res);
+    }
+
+    // Format 3
+    pub fn move_compare_add_subtract(
+        cpu: &mut arm7tdmi::Processor,
+        _bus: &mut bus::Bus,
+        instr: u16,
+    ) {
+        let instr = u32::from(instr);
+        let opcode = (instr >> 11) & 0b11;
+        let reg_dest = (instr >> 8) & 0x07;
+        let imm = instr & 0xFF;
+
+        let op1 = cpu.regs.get_reg(reg_dest);
+        match opcode {
+            // MOV
+            0 => {
+                cpu.set_nz_flags(imm);
+                cpu.regs.set_reg(reg_dest, imm);
+            }
+            // CMP
+            1 => {
+                cpu.sub(op1, imm, true);
+            }
+            // ADD
+            2 => {
+                let res = cpu.add(op1, imm, true);
+                cpu.regs.set_reg(reg_dest, res);
+            }
+            // SUB
+            3 => {
+                let res = cpu.sub(op1, imm, true);
+                cpu.regs.set_reg(reg_dest, res);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Format 4
+    pub fn alu_operations(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let opcode = (instr >> 6) & 0x0F;
+        let reg_src = (instr >> 3) & 0x07;
+        let reg_dest = instr & 0x07;
+
+        let op1 = cpu.regs.get_reg(reg_dest);
+        let op2 = cpu.regs.get_reg(reg_src);
+        let mut carry = cpu.regs.get_cpsr(PSRFlags::Carry);
+
+        log::debug!("Thumb ALU opcode {:#2X}", opcode);
+        let res = match opcode {
+            // AND
+            0x0 => op1 & op2,
+            // EOR
+            0x1 => op1 ^ op2,
+            // LSL, LSR, ASR, shifting by a register takes an extra cycle
+            0x2 => {
+                bus.idle(1);
+                alu::lsl(op1, op2 & 0xFF, &mut carry)
+            }
+            0x3 => {
+                bus.idle(1);
+                alu::lsr(op1, op2 & 0xFF, false, &mut carry)
+            }
+            0x4 => {
+                bus.idle(1);
+                alu::asr(op1, op2 & 0xFF, false, &mut carry)
+            }
+            // ADC
+            0x5 => {
+                let res = cpu.adc(op1, op2, true);
+                cpu.regs.set_reg(reg_dest, res);
+                return;
+            }
+            // SBC
+            0x6 => {
+                let res = cpu.sbc(op1, op2, true);
+                cpu.regs.set_reg(reg_dest, res);
+                return;
+            }
+            // ROR
+            0x7 => {
+                bus.idle(1);
+                alu::ror(op1, op2 & 0xFF, false, &mut carry)
+            }
+            // TST
+            0x8 => {
+                cpu.set_nz_flags(op1 & op2);
+                return;
+            }
+            // NEG
+            0x9 => {
+                let res = cpu.sub(0, op2, true);
+                cpu.regs.set_reg(reg_dest, res);
+                return;
+            }
+            // CMP
+            0xA => {
+                cpu.sub(op1, op2, true);
+                return;
+            }
+            // CMN
+            0xB => {
+                cpu.add(op1, op2, true);
+                return;
+            }
+            // ORR
+            0xC => op1 | op2,
+            // MUL
+            0xD => {
+                bus.idle(alu::multiply_cycles(op1, true));
+                op1.wrapping_mul(op2)
+            }
+            // BIC
+            0xE => op1 & !op2,
+            // MVN
+            0xF => !op2,
+            _ => unreachable!(),
+        };
+
+        cpu.set_logical_flags(res, carry);
+        cpu.regs.set_reg(reg_dest, res);
+    }
+
+    // Format 5
+    pub fn hi_register_operations(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let opcode = (instr >> 8) & 0b11;
+        let reg_src = ((instr >> 3) & 0x07) | ((instr >> 3) & 0x08);
+        let reg_dest = (instr & 0x07) | ((instr >> 4) & 0x08);
+
+        let op1 = cpu.regs.get_reg(reg_dest);
+        let op2 = cpu.regs.get_reg(reg_src);
+
+        match opcode {
+            // ADD
+            0 => cpu.regs.set_reg(reg_dest, op1.wrapping_add(op2)),
+            // CMP
+            1 => {
+                cpu.sub(op1, op2, true);
+                return;
+            }
+            // MOV
+            2 => cpu.regs.set_reg(reg_dest, op2),
+            // BX
+            3 => {
+                log::info!("Thumb Branch and Exchange to {:#2X}", op2);
+                cpu.branch_exchange(bus, op2);
+                return;
+            }
+            _ => unreachable!(),
+        }
+
+        if reg_dest == 15 {
+            log::debug!("Reloading pipeline in Hi Register Operations!");
+            cpu.regs.r15_pc &= !1;
+            cpu.reload_thumb_pipeline(bus);
+        }
+    }
+
+    // Format 6
+    pub fn pc_relative_load(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let reg_dest = (instr >> 8) & 0x07;
+        // Bit 1 of the PC is forced to 0 so the load is word aligned
+        let address = (cpu.regs.r15_pc & !2).wrapping_add((instr & 0xFF) << 2);
+
+        let value = bus.read_word(address, Access::NonSequential);
+        cpu.regs.set_reg(reg_dest, value);
+        bus.idle(1);
+        log::debug!("Loaded {:#2X} from {:#2X}", value, address);
+    }
+
+    // Format 7
+    pub fn load_store_register_offset(
+        cpu: &mut arm7tdmi::Processor,
+        bus: &mut bus::Bus,
+        instr: u16,
+    ) {
+        let instr = u32::from(instr);
+        let load = (instr >> 11) & 0x01 == 1;
+        let byte = (instr >> 10) & 0x01 == 1;
+        let reg_offset = (instr >> 6) & 0x07;
+        let reg_base = (instr >> 3) & 0x07;
+        let reg_dest = instr & 0x07;
+
+        let address = cpu
+            .regs
+            .get_reg(reg_base)
+            .wrapping_add(cpu.regs.get_reg(reg_offset));
+
+        // Loads take an extra internal cycle to write the register
+        if load {
+            bus.idle(1);
+        }
+
+        match (load, byte) {
+            (false, false) => {
+                bus.write_word(address, cpu.regs.get_reg(reg_dest), Access::NonSequential)
+            }
+            (false, true) => bus.write_byte(
+                address,
+                cpu.regs.get_reg(reg_dest) as u8,
+                Access::NonSequential,
+            ),
+            (true, false) => cpu
+                .regs
+                .set_reg(reg_dest, bus.read_word(address, Access::NonSequential)),
+            (true, true) => cpu.regs.set_reg(
+                reg_dest,
+                u32::from(bus.read_byte(address, Access::NonSequential)),
+            ),
+        }
+    }
+
+    // Format 8
+    pub fn load_store_sign_extended(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let half = (instr >> 11) & 0x01 == 1;
+        let sign = (instr >> 10) & 0x01 == 1;
+        let reg_offset = (instr >> 6) & 0x07;
+        let reg_base = (instr >> 3) & 0x07;
+        let reg_dest = instr & 0x07;
+
+        let address = cpu
+            .regs
+            .get_reg(reg_base)
+            .wrapping_add(cpu.regs.get_reg(reg_offset));
+
+        // Everything but STRH is a load
+        if sign || half {
+            bus.idle(1);
+        }
+
+        match (sign, half) {
+            // STRH
+            (false, false) => {
+                bus.write_half(address, cpu.regs.get_reg(reg_dest), Access::NonSequential)
+            }
+            // LDRH
+            (false, true) => cpu
+                .regs
+                .set_reg(reg_dest, bus.read_half(address, Access::NonSequential)),
+            // LDSB
+            (true, false) => {
+                let value = bus.read_byte(address, Access::NonSequential) as i8 as i32 as u32;
+                cpu.regs.set_reg(reg_dest, value);
+            }
+            // LDSH
+            (true, true) => {
+                // Misaligned halfword loads only sign extend the byte
+                let value = if address & 0x01 == 1 {
+                    bus.read_byte(address, Access::NonSequential) as i8 as i32 as u32
+                } else {
+                    bus.read_half(address, Access::NonSequential) as u16 as i16 as i32 as u32
+                };
+                cpu.regs.set_reg(reg_dest, value);
+            }
+        }
+    }
+
+    // Format 9
+    pub fn load_store_immediate_offset(
+        cpu: &mut arm7tdmi::Processor,
+        bus: &mut bus::Bus,
+        instr: u16,
+    ) {
+        let instr = u32::from(instr);
+        let byte = (instr >> 12) & 0x01 == 1;
+        let load = (instr >> 11) & 0x01 == 1;
+        let offset = (instr >> 6) & 0x1F;
+        let reg_base = (instr >> 3) & 0x07;
+        let reg_dest = instr & 0x07;
+
+        let base = cpu.regs.get_reg(reg_base);
+        if load {
+            bus.idle(1);
+        }
+
+        if byte {
+            let address = base.wrapping_add(offset);
+            if load {
+                cpu.regs.set_reg(
+                    reg_dest,
+                    u32::from(bus.read_byte(address, Access::NonSequential)),
+                );
+            } else {
+                bus.write_byte(
+                    address,
+                    cpu.regs.get_reg(reg_dest) as u8,
+                    Access::NonSequential,
+                );
+            }
+        } else {
+            let address = base.wrapping_add(offset << 2);
+            if load {
+                cpu.regs
+                    .set_reg(reg_dest, bus.read_word(address, Access::NonSequential));
+            } else {
+                bus.write_word(address, cpu.regs.get_reg(reg_dest), Access::NonSequential);
+            }
+        }
+    }
+
+    // Format 10
+    pub fn load_store_halfword(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let load = (instr >> 11) & 0x01 == 1;
+        let offset = ((instr >> 6) & 0x1F) << 1;
+        let reg_base = (instr >> 3) & 0x07;
+        let reg_dest = instr & 0x07;
+
+        let address = cpu.regs.get_reg(reg_base).wrapping_add(offset);
+        if load {
+            bus.idle(1);
+            cpu.regs
+                .set_reg(reg_dest, bus.read_half(address, Access::NonSequential));
+        } else {
+            bus.write_half(address, cpu.regs.get_reg(reg_dest), Access::NonSequential);
+        }
+    }
+
+    // Format 11
+    pub fn sp_relative_load_store(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let load = (instr >> 11) & 0x01 == 1;
+        let reg_dest = (instr >> 8) & 0x07;
+        let address = cpu.regs.get_reg(13).wrapping_add((instr & 0xFF) << 2);
+
+        if load {
+            bus.idle(1);
+            cpu.regs
+                .set_reg(reg_dest, bus.read_word(address, Access::NonSequential));
+        } else {
+            bus.write_word(address, cpu.regs.get_reg(reg_dest), Access::NonSequential);
+        }
+    }
+
+    // Format 12
+    pub fn load_address(cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let from_sp = (instr >> 11) & 0x01 == 1;
+        let reg_dest = (instr >> 8) & 0x07;
+
+        let base = if from_sp {
+            cpu.regs.get_reg(13)
+        } else {
+            cpu.regs.r15_pc & !2
+        };
+        cpu.regs
+            .set_reg(reg_dest, base.wrapping_add((instr & 0xFF) << 2));
+    }
+
+    // Format 13
+    pub fn add_offset_to_sp(cpu: &mut arm7tdmi::Processor, _bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let negative = (instr >> 7) & 0x01 == 1;
+        let offset = (instr & 0x7F) << 2;
+
+        let sp = cpu.regs.get_reg(13);
+        if negative {
+            cpu.regs.set_reg(13, sp.wrapping_sub(offset));
+        } else {
+            cpu.regs.set_reg(13, sp.wrapping_add(offset));
+        }
+    }
+
+    // Format 14
+    pub fn push_pop(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let pop = (instr >> 11) & 0x01 == 1;
+        // PUSH also stores LR, POP also loads PC
+        let extra_reg = (instr >> 8) & 0x01 == 1;
+        let reg_list = instr & 0xFF;
+
+        // The first transfer is non sequential, the rest follow on from it
+        let mut access = Access::NonSequential;
+        let mut address = cpu.regs.get_reg(13);
+        if pop {
+            for r in 0..8 {
+                if (reg_list >> r) & 0x01 == 1 {
+                    cpu.regs.set_reg(r, bus.read_word(address, access));
+                    access = Access::Sequential;
+                    address = address.wrapping_add(4);
+                }
+            }
+            bus.idle(1);
+            if extra_reg {
+                cpu.regs.r15_pc = bus.read_word(address, access) & !1;
+                address = address.wrapping_add(4);
+                cpu.regs.set_reg(13, address);
+                cpu.reload_thumb_pipeline(bus);
+                return;
+            }
+            cpu.regs.set_reg(13, address);
+        } else {
+            let count = reg_list.count_ones() + u32::from(extra_reg);
+            address = address.wrapping_sub(count * 4);
+            cpu.regs.set_reg(13, address);
+
+            for r in 0..8 {
+                if (reg_list >> r) & 0x01 == 1 {
+                    bus.write_word(address, cpu.regs.get_reg(r), access);
+                    access = Access::Sequential;
+                    address = address.wrapping_add(4);
+                }
+            }
+            if extra_reg {
+                bus.write_word(address, cpu.regs.get_reg(14), access);
+            }
+        }
+    }
+
+    // Format 15
+    pub fn multiple_load_store(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let load = (instr >> 11) & 0x01 == 1;
+        let reg_base = (instr >> 8) & 0x07;
+        let reg_list = instr & 0xFF;
+
+        let mut address = cpu.regs.get_reg(reg_base);
+
+        if reg_list == 0 {
+            // An empty list transfers R15 and moves the base by 0x40
+            if load {
+                cpu.regs.r15_pc = bus.read_word(address, Access::NonSequential) & !1;
+                cpu.regs.set_reg(reg_base, address.wrapping_add(0x40));
+                bus.idle(1);
+                cpu.reload_thumb_pipeline(bus);
+            } else {
+                bus.write_word(
+                    address,
+                    cpu.regs.r15_pc.wrapping_add(2),
+                    Access::NonSequential,
+                );
+                cpu.regs.set_reg(reg_base, address.wrapping_add(0x40));
+            }
+            return;
+        }
+
+        let base_new = address.wrapping_add(reg_list.count_ones() * 4);
+        // If the base is the first register stored, the old value is written.
+        // Otherwise, the written back value is stored.
+        let base_first = reg_list & ((1 << reg_base) - 1) == 0;
+
+        let mut access = Access::NonSequential;
+        for r in 0..8 {
+            if (reg_list >> r) & 0x01 == 0 {
+                continue;
+            }
+            if load {
+                cpu.regs.set_reg(r, bus.read_word(address, access));
+            } else if r == reg_base && !base_first {
+                bus.write_word(address, base_new, access);
+            } else {
+                bus.write_word(address, cpu.regs.get_reg(r), access);
+            }
+            access = Access::Sequential;
+            address = address.wrapping_add(4);
+        }
+
+        if load {
+            bus.idle(1);
+        }
+
+        // Loading the base register overrides the writeback
+        if !load || (reg_list >> reg_base) & 0x01 == 0 {
+            cpu.regs.set_reg(reg_base, base_new);
+        }
+    }
+
+    // Format 16
+    pub fn conditional_branch(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let cond = (instr >> 8) & 0x0F;
+        if !cpu.regs.check_cond(cond) {
+            return;
+        }
+
+        // Sign extend the 8 bit offset and turn it into a halfword offset
+        let offset = ((instr & 0xFF) as i8 as i32) << 1;
+        log::info!("Thumb conditional branch with offset {}", offset);
+        cpu.idle_loop = offset == -4;
+        cpu.regs.r15_pc = cpu.regs.r15_pc.wrapping_add(offset as u32);
+        cpu.reload_thumb_pipeline(bus);
+    }
+
+    // Format 17
+    pub fn software_interrupt(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        log::info!("Thumb SWI {:#2X}", instr & 0xFF);
+        if bus.bios.loaded() {
+            cpu.enter_exception(bus, Exception::SoftwareInterrupt);
+        } else {
+            cpu.hle_swi(bus, u32::from(instr & 0xFF));
+        }
+    }
+
+    // Format 18
+    pub fn unconditional_branch(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        // Sign extend the 11 bit offset and turn it into a halfword offset
+        let offset = (((instr & 0x07_FF) << 21) as i32) >> 20;
+        // A branch to itself can only be left through an interrupt
+        cpu.idle_loop = offset == -4;
+        cpu.regs.r15_pc = cpu.regs.r15_pc.wrapping_add(offset as u32);
+        cpu.reload_thumb_pipeline(bus);
+    }
+
+    // Format 19
+    pub fn long_branch_with_link(cpu: &mut arm7tdmi::Processor, bus: &mut bus::Bus, instr: u16) {
+        let instr = u32::from(instr);
+        let low = (instr >> 11) & 0x01 == 1;
+        let offset = instr & 0x07_FF;
+
+        if low {
+            // Second half: jump to LR + offset and link to the next instruction
+            let next = cpu.regs.r15_pc.wrapping_sub(2);
+            cpu.regs.r15_pc = cpu.regs.get_reg(14).wrapping_add(offset << 1) & !1;
+            cpu.regs.set_reg(14, next | 1);
+            cpu.reload_thumb_pipeline(bus);
+        } else {
+            // First half: LR = PC + upper part of the sign extended offset
+            let upper = (((offset << 21) as i32) >> 9) as u32;
+            cpu.regs.set_reg(14, cpu.regs.r15_pc.wrapping_add(upper));
+        }
+    }
+}
@@ -17,6 +17,14 @@ impl Cpu {
         //println!("CPU powering one!");
     }
 
+    pub fn reset(&mut self, bus: &mut bus::Bus) {
+        self.processor.reset(bus);
+    }
+
+    pub fn skip_bios(&mut self, entry: u32) {
+        self.processor.skip_bios(entry);
+    }
+
     pub fn step(&mut self, clocks: u32, bus: &mut bus::Bus) {
         self.processor.step(clocks, bus);
     }
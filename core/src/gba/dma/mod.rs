@@ -0,0 +1,347 @@
+use crate::gba::bus::{self, io, Access};
+use crate::gba::interrupt::Interrupt;
+
+// DMA transfers, see here:
+// https://problemkaputt.de/gbatek-gba-dma-transfers.htm
+
+const DMA_IRQS: [Interrupt; 4] = [
+    Interrupt::Dma0,
+    Interrupt::Dma1,
+    Interrupt::Dma2,
+    Interrupt::Dma3,
+];
+
+// Not every channel can reach every region, so the address
+// and count registers have a different width per channel.
+const SRC_MASKS: [u32; 4] = [0x07_FF_FF_FF, 0x0F_FF_FF_FF, 0x0F_FF_FF_FF, 0x0F_FF_FF_FF];
+const DST_MASKS: [u32; 4] = [0x07_FF_FF_FF, 0x07_FF_FF_FF, 0x07_FF_FF_FF, 0x0F_FF_FF_FF];
+const COUNT_MASKS: [u32; 4] = [0x3F_FF, 0x3F_FF, 0x3F_FF, 0xFF_FF];
+
+// Sound FIFO transfers always move 4 words to a fixed address
+const FIFO_TRANSFER_COUNT: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timing {
+    Immediate,
+    VBlank,
+    HBlank,
+    // Sound FIFO for DMA1 and DMA2, video capture for DMA3
+    Special,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AddressControl {
+    Increment,
+    Decrement,
+    Fixed,
+    // Increments, but the destination is reloaded on repeat
+    Reload,
+}
+
+#[derive(Clone, Copy)]
+struct DmaChannel {
+    id: usize,
+
+    // Registers as written by the CPU
+    src: u32,
+    dst: u32,
+    count: u16,
+    control: u16,
+
+    // Internal registers latched when the channel is enabled
+    internal_src: u32,
+    internal_dst: u32,
+    internal_count: u32,
+
+    // Set when the start condition has been met
+    active: bool,
+}
+
+pub struct Dma {
+    channels: [DmaChannel; 4],
+}
+
+impl Timing {
+    fn from_bits(bits: u16) -> Timing {
+        match bits & 0b11 {
+            0 => Timing::Immediate,
+            1 => Timing::VBlank,
+            2 => Timing::HBlank,
+            _ => Timing::Special,
+        }
+    }
+}
+
+impl AddressControl {
+    fn from_bits(bits: u16) -> AddressControl {
+        match bits & 0b11 {
+            0 => AddressControl::Increment,
+            1 => AddressControl::Decrement,
+            2 => AddressControl::Fixed,
+            _ => AddressControl::Reload,
+        }
+    }
+
+    fn step(self, address: u32, size: u32) -> u32 {
+        match self {
+            AddressControl::Increment | AddressControl::Reload => address.wrapping_add(size),
+            AddressControl::Decrement => address.wrapping_sub(size),
+            AddressControl::Fixed => address,
+        }
+    }
+}
+
+impl DmaChannel {
+    fn new(id: usize) -> DmaChannel {
+        DmaChannel {
+            id,
+            src: 0x0,
+            dst: 0x0,
+            count: 0x0,
+            control: 0x0,
+            internal_src: 0x0,
+            internal_dst: 0x0,
+            internal_count: 0x0,
+            active: false,
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        (self.control >> 15) & 0x01 == 1
+    }
+
+    fn timing(&self) -> Timing {
+        Timing::from_bits(self.control >> 12)
+    }
+
+    fn dst_control(&self) -> AddressControl {
+        AddressControl::from_bits(self.control >> 5)
+    }
+
+    fn src_control(&self) -> AddressControl {
+        AddressControl::from_bits(self.control >> 7)
+    }
+
+    fn repeat(&self) -> bool {
+        (self.control >> 9) & 0x01 == 1
+    }
+
+    fn word_sized(&self) -> bool {
+        (self.control >> 10) & 0x01 == 1
+    }
+
+    fn irq(&self) -> bool {
+        (self.control >> 14) & 0x01 == 1
+    }
+
+    fn is_sound_fifo(&self) -> bool {
+        (self.id == 1 || self.id == 2) && self.timing() == Timing::Special
+    }
+
+    // A count of 0 means the maximum amount of units
+    fn reload_count(&mut self) {
+        let mask = COUNT_MASKS[self.id];
+        let count = u32::from(self.count) & mask;
+        self.internal_count = if count == 0 { mask + 1 } else { count };
+    }
+
+    fn write_control(&mut self, control: u16) {
+        let was_enabled = self.enabled();
+        self.control = control;
+
+        if !self.enabled() {
+            self.active = false;
+            return;
+        }
+
+        // The internal registers are only latched on a 0 -> 1 transition
+        if !was_enabled {
+            self.internal_src = self.src & SRC_MASKS[self.id];
+            self.internal_dst = self.dst & DST_MASKS[self.id];
+            self.reload_count();
+            log::debug!(
+                "DMA{} enabled: {:#2X} -> {:#2X}, {} units, timing {:?}",
+                self.id,
+                self.internal_src,
+                self.internal_dst,
+                self.internal_count,
+                self.timing()
+            );
+
+            if self.timing() == Timing::Immediate {
+                self.active = true;
+            }
+        }
+    }
+}
+
+impl Dma {
+    pub fn new() -> Dma {
+        Dma {
+            channels: [
+                DmaChannel::new(0),
+                DmaChannel::new(1),
+                DmaChannel::new(2),
+                DmaChannel::new(3),
+            ],
+        }
+    }
+
+    /// Whether a channel is waiting to transfer
+    pub fn active(&self) -> bool {
+        self.channels.iter().any(|c| c.active)
+    }
+
+    /// Starts every enabled channel waiting for `timing`. Sound FIFO
+    /// transfers are requested through `request_fifo` instead.
+    pub fn request(&mut self, timing: Timing) {
+        for channel in self.channels.iter_mut() {
+            if channel.enabled() && channel.timing() == timing && !channel.is_sound_fifo() {
+                channel.active = true;
+            }
+        }
+    }
+
+    /// Called when a Direct Sound FIFO at `fifo_address` runs low on samples.
+    pub fn request_fifo(&mut self, fifo_address: u32) {
+        for channel in self.channels[1..=2].iter_mut() {
+            if channel.enabled() && channel.is_sound_fifo() && channel.internal_dst == fifo_address
+            {
+                channel.active = true;
+            }
+        }
+    }
+
+    /// Video capture runs on DMA3 from line 2 up to line 161 and
+    /// disables itself afterwards.
+    pub fn request_video_capture(&mut self, v_count: u16) {
+        let channel = &mut self.channels[3];
+        if !channel.enabled() || channel.timing() != Timing::Special {
+            return;
+        }
+
+        match v_count {
+            2..=161 => channel.active = true,
+            162 => {
+                channel.control &= !(1 << 15);
+                channel.active = false;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn read_io(&self, address: u32) -> u16 {
+        let offset = address - 0x0400_00B0;
+        let channel = &self.channels[(offset / 12) as usize];
+        // Only the control register can be read back
+        match offset % 12 {
+            10 => channel.control,
+            _ => 0x0,
+        }
+    }
+
+    pub fn write_io(&mut self, address: u32, value: u16, mask: u16) {
+        let offset = address - 0x0400_00B0;
+        let channel = &mut self.channels[(offset / 12) as usize];
+        match offset % 12 {
+            half @ (0 | 2) => channel.src = merge_half(channel.src, half, value, mask),
+            half @ (4 | 6) => channel.dst = merge_half(channel.dst, half - 4, value, mask),
+            8 => channel.count = io::merge(channel.count, value, mask),
+            10 => channel.write_control(io::merge(channel.control, value, mask)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl bus::Bus {
+    /// Runs every active DMA channel, lowest channel first as it has the
+    /// highest priority. Returns the amount of cycles the CPU was stalled.
+    pub fn run_dma(&mut self) -> u32 {
+        while let Some(id) = self.dma.channels.iter().position(|c| c.active) {
+            self.dma_transfer(id);
+        }
+        self.take_cycles()
+    }
+
+    fn dma_transfer(&mut self, id: usize) {
+        let mut channel = self.dma.channels[id];
+        let fifo = channel.is_sound_fifo();
+
+        let (word, count, dst_control) = if fifo {
+            (true, FIFO_TRANSFER_COUNT, AddressControl::Fixed)
+        } else {
+            (
+                channel.word_sized(),
+                channel.internal_count,
+                channel.dst_control(),
+            )
+        };
+        let size = if word { 4 } else { 2 };
+        let src_control = channel.src_control();
+
+        log::debug!(
+            "DMA{} transferring {} units from {:#2X} to {:#2X}",
+            id,
+            count,
+            channel.internal_src,
+            channel.internal_dst
+        );
+
+        // How long the transfer is tells the EEPROM how wide its address is
+        if id == 3 && self.cartridge.is_eeprom(channel.internal_dst) {
+            self.cartridge.eeprom_dma(count);
+        }
+
+        // Two internal cycles to get going, then the first unit is
+        // non sequential and every one after that sequential.
+        self.idle(2);
+        for unit in 0..count {
+            let access = if unit == 0 {
+                Access::NonSequential
+            } else {
+                Access::Sequential
+            };
+
+            if word {
+                let value = self.read_word(channel.internal_src & !3, access);
+                self.write_word(channel.internal_dst & !3, value, access);
+            } else {
+                let value = self.read_half(channel.internal_src & !1, access);
+                self.write_half(channel.internal_dst & !1, value, access);
+            }
+            channel.internal_src = src_control.step(channel.internal_src, size);
+            channel.internal_dst = dst_control.step(channel.internal_dst, size);
+        }
+
+        // Immediate transfers can't repeat
+        let repeat = channel.repeat() && channel.timing() != Timing::Immediate;
+        if repeat {
+            channel.reload_count();
+            if dst_control == AddressControl::Reload {
+                channel.internal_dst = channel.dst & DST_MASKS[id];
+            }
+        }
+
+        if channel.irq() {
+            self.interrupt.request(DMA_IRQS[id]);
+        }
+
+        // The registers might have been written to during the transfer,
+        // so only the internal state and the enable bit are written back.
+        let current = &mut self.dma.channels[id];
+        current.internal_src = channel.internal_src;
+        current.internal_dst = channel.internal_dst;
+        current.internal_count = channel.internal_count;
+        if !repeat {
+            current.control &= !(1 << 15);
+        }
+        current.active = false;
+    }
+}
+
+// Addresses are written a halfword at a time
+fn merge_half(old: u32, offset: u32, value: u16, mask: u16) -> u32 {
+    let shift = offset * 8;
+    let half = io::merge((old >> shift) as u16, value, mask);
+    (old & !(0xFF_FF << shift)) | (u32::from(half) << shift)
+}
@@ -0,0 +1,122 @@
+use crate::gba::bus::io;
+use crate::gba::scheduler::{EventKind, Scheduler};
+
+// Interrupt controller, see here:
+// https://problemkaputt.de/gbatek-gba-interrupt-control.htm
+
+// Cycles it takes for a request to reach the CPU's IRQ line
+const IRQ_DELAY: u64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    HBlank,
+    VCount,
+    Timer0,
+    Timer1,
+    Timer2,
+    Timer3,
+    Serial,
+    Dma0,
+    Dma1,
+    Dma2,
+    Dma3,
+    Keypad,
+    GamePak,
+}
+
+pub struct InterruptController {
+    // Interrupt Enable at 0x4000200
+    enable: u16,
+    // Interrupt Request Flags at 0x4000202
+    flags: u16,
+    // Interrupt Master Enable at 0x4000208
+    master_enable: u16,
+
+    // What the CPU actually sees, lagging a little behind the registers
+    irq_line: bool,
+    line_scheduled: bool,
+}
+
+impl Interrupt {
+    pub const fn bit(self) -> u16 {
+        1 << (self as u16)
+    }
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController {
+            enable: 0x0,
+            flags: 0x0,
+            master_enable: 0x0,
+            irq_line: false,
+            line_scheduled: false,
+        }
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        log::debug!("Requesting interrupt {:?}", interrupt);
+        self.flags |= interrupt.bit();
+    }
+
+    /// Whether an enabled interrupt has been requested, regardless of IME.
+    /// This is what wakes the CPU up from HALT.
+    pub fn requested(&self) -> bool {
+        self.enable & self.flags & 0x3F_FF != 0
+    }
+
+    /// Whether any of the given interrupts are enabled and requested.
+    pub fn requested_any(&self, interrupts: &[Interrupt]) -> bool {
+        let mask = interrupts.iter().fold(0, |mask, i| mask | i.bit());
+        self.enable & self.flags & mask != 0
+    }
+
+    /// Whether the CPU should take an IRQ exception, provided the
+    /// I bit in the CPSR is clear.
+    pub fn pending(&self) -> bool {
+        self.irq_line
+    }
+
+    fn signalled(&self) -> bool {
+        self.master_enable & 0x01 == 1 && self.requested()
+    }
+
+    /// Keeps the IRQ line in sync with the registers. Raising it is delayed
+    /// by a few cycles while dropping it takes effect right away.
+    pub fn update_line(&mut self, scheduler: &mut Scheduler) {
+        let signalled = self.signalled();
+        if signalled == self.irq_line || self.line_scheduled {
+            return;
+        }
+
+        if signalled {
+            scheduler.schedule(EventKind::IrqLine, IRQ_DELAY);
+            self.line_scheduled = true;
+        } else {
+            self.irq_line = false;
+        }
+    }
+
+    pub fn raise_line(&mut self) {
+        self.line_scheduled = false;
+        self.irq_line = self.signalled();
+    }
+
+    pub fn read_io(&self, address: u32) -> u16 {
+        match address {
+            0x0400_0200 => self.enable,
+            0x0400_0202 => self.flags,
+            _ => self.master_enable,
+        }
+    }
+
+    pub fn write_io(&mut self, address: u32, value: u16, mask: u16) {
+        match address {
+            0x0400_0200 => self.enable = io::merge(self.enable, value, mask),
+            // Writing 1 to a bit in IF acknowledges the interrupt
+            0x0400_0202 => self.flags &= !(value & mask),
+            _ => self.master_enable = io::merge(self.master_enable, value, mask),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::gba::bus::io;
+use crate::gba::interrupt::{Interrupt, InterruptController};
+
+// Keypad input, see here:
+// https://problemkaputt.de/gbatek-gba-keypad-input.htm
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    A,
+    B,
+    Select,
+    Start,
+    Right,
+    Left,
+    Up,
+    Down,
+    R,
+    L,
+}
+
+pub struct Keypad {
+    // KEYINPUT at 0x4000130, a bit is 0 while the key is pressed
+    input: u16,
+    // KEYCNT at 0x4000132
+    control: u16,
+}
+
+impl Key {
+    const fn bit(self) -> u16 {
+        1 << (self as u16)
+    }
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad {
+            input: 0x03_FF,
+            control: 0x0,
+        }
+    }
+
+    pub fn set_key(&mut self, key: Key, pressed: bool, interrupt: &mut InterruptController) {
+        if pressed {
+            self.input &= !key.bit();
+        } else {
+            self.input |= key.bit();
+        }
+        self.check_interrupt(interrupt);
+    }
+
+    pub fn read_io(&self, address: u32) -> u16 {
+        match address {
+            0x0400_0130 => self.input,
+            _ => self.control,
+        }
+    }
+
+    pub fn write_io(&mut self, value: u16, mask: u16, interrupt: &mut InterruptController) {
+        // KEYINPUT is read only, so this can only be KEYCNT
+        self.control = io::merge(self.control, value, mask);
+        self.check_interrupt(interrupt);
+    }
+
+    /// Whether the KEYCNT condition currently holds. Bit 15 selects between
+    /// any of the selected keys (OR) or all of them (AND) being pressed.
+    pub fn condition_met(&self) -> bool {
+        let selected = self.control & 0x03_FF;
+        let pressed = !self.input & 0x03_FF;
+        if (self.control >> 15) & 0x01 == 1 {
+            selected != 0 && pressed & selected == selected
+        } else {
+            pressed & selected != 0
+        }
+    }
+
+    fn check_interrupt(&self, interrupt: &mut InterruptController) {
+        if (self.control >> 14) & 0x01 == 1 && self.condition_met() {
+            interrupt.request(Interrupt::Keypad);
+        }
+    }
+}
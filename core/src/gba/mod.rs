@@ -1,61 +1,242 @@
+mod bios;
 mod bus;
 mod cartridge;
 mod cpu;
+mod dma;
+mod interrupt;
+mod keypad;
 mod ppu;
+mod scheduler;
+mod serial;
+mod sound;
+mod system;
+mod timer;
 
-const LINES_TOTAL: u32 = 228;
-const LINES_VISIBLE: u32 = 160;
+pub use cartridge::{BackupType, CartridgeHeader, FlashChip, Hardware, LoadError, RtcClock};
+pub use keypad::Key;
 
-const CYCLES_TOTAL_PER_LINE: u32 = 1232;
-const CYCLES_TOTAL_HBLANK0: u32 = 1006;
-const CYCLES_TOTAL_HBLANK1: u32 = CYCLES_TOTAL_PER_LINE - CYCLES_TOTAL_HBLANK0;
+// Where the BIOS jumps to once it's done booting
+const CARTRIDGE_ENTRY: u32 = 0x08_00_00_00;
+const MULTIBOOT_ENTRY: u32 = 0x02_00_00_C0;
+
+// Multiboot images are sent over the link cable into EWRAM, so they can't
+// be any bigger than it
+const MULTIBOOT_MAX_SIZE: usize = 256 * 1024;
 
 pub struct HerodGBA {
     cpu: cpu::Cpu,
     bus: bus::Bus,
+    skip_bios: bool,
+    // Running a multiboot image rather than a cartridge
+    multiboot: bool,
+}
+
+impl Default for HerodGBA {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Nothing the game saved gets lost when we're shut down normally
+impl Drop for HerodGBA {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_save() {
+            log::error!("Could not save: {}", e);
+        }
+    }
 }
 
 impl HerodGBA {
     pub fn new() -> HerodGBA {
+        let b = bios::Bios::new();
         let m = bus::memory::Memory::new();
         let c = cartridge::Cartridge::new();
         let p = ppu::Ppu::new();
+        let i = interrupt::InterruptController::new();
+        let k = keypad::Keypad::new();
+        let s = system::SystemControl::new();
+        let d = dma::Dma::new();
+        let t = timer::Timers::new();
+        let a = sound::DirectSound::new();
+        let l = serial::Serial::new();
 
         HerodGBA {
             cpu: cpu::Cpu::new(),
-            bus: bus::Bus::new(m, c, p),
+            bus: bus::Bus::new(b, m, c, p, i, k, s, d, t, a, l),
+            skip_bios: false,
+            multiboot: false,
         }
     }
 
+    /// Boots through the BIOS if one has been loaded, unless we've been
+    /// told to skip it. The cartridge and BIOS need to be loaded first.
+    /// Multiboot images always skip it, as we can't play the other end of
+    /// the link cable for it to receive them from.
     pub fn power(&mut self) {
-        //println!("herodGBA running!");
+        if self.multiboot {
+            self.cpu.skip_bios(MULTIBOOT_ENTRY);
+        } else if self.bus.bios.loaded() && !self.skip_bios {
+            self.cpu.reset(&mut self.bus);
+        } else {
+            self.cpu.skip_bios(CARTRIDGE_ENTRY);
+        }
+    }
+
+    /// Loads a dump of the real BIOS from `file_name`
+    pub fn load_bios(&mut self, file_name: &str) -> std::io::Result<()> {
+        self.bus.bios.load(file_name)
+    }
+
+    /// Runs the open source BIOS that comes with the core, for when
+    /// there's no BIOS dump to load. Without either of them the BIOS
+    /// functions are emulated directly.
+    pub fn load_replacement_bios(&mut self) {
+        self.bus.bios.load_replacement();
     }
 
-    pub fn load_cartridge_from_args(&mut self) {
+    /// Skips the boot logo and jumps straight into the cartridge
+    pub fn set_skip_bios(&mut self, skip: bool) {
+        self.skip_bios = skip;
+    }
+
+    pub fn load_cartridge_from_args(&mut self) -> Result<&CartridgeHeader, LoadError> {
         let file_name = std::env::args().nth(1).expect("Please specify a ROM!");
         //println!("Running rom {file_name}");
 
-        self.bus.cartridge.load(&file_name);
+        self.load_cartridge(&file_name)
+    }
+
+    /// Inserts the ROM at `file_name`, returning what's in its header
+    pub fn load_cartridge(&mut self, file_name: &str) -> Result<&CartridgeHeader, LoadError> {
+        self.multiboot = false;
+        self.bus.cartridge.load(file_name)
+    }
+
+    /// Loads the multiboot image at `file_name` into EWRAM to be run
+    /// without a cartridge, as if it had come over the link cable
+    pub fn load_multiboot(&mut self, file_name: &str) -> Result<(), LoadError> {
+        self.load_multiboot_data(std::fs::read(file_name)?)
+    }
+
+    /// Loads a multiboot image that's already in memory
+    pub fn load_multiboot_data(&mut self, data: Vec<u8>) -> Result<(), LoadError> {
+        if data.len() > MULTIBOOT_MAX_SIZE {
+            return Err(LoadError::MultibootTooLarge(data.len()));
+        }
+        // They have the same header as a cartridge, which the BIOS checks
+        // the same way before running them
+        let header = CartridgeHeader::parse(&data)?;
+        log::info!(
+            "Loaded multiboot image {} ({}-{})",
+            header.title,
+            header.game_code,
+            header.maker_code
+        );
+
+        self.bus.cartridge.eject();
+        let wram = self.bus.mem.wram_board_mut();
+        wram.fill(0);
+        wram[..data.len()].copy_from_slice(&data);
+        self.multiboot = true;
+        Ok(())
+    }
+
+    /// Inserts the ROM called `entry` from the archive at `file_name`, for
+    /// archives with more than one ROM in them
+    pub fn load_cartridge_entry(
+        &mut self,
+        file_name: &str,
+        entry: &str,
+    ) -> Result<&CartridgeHeader, LoadError> {
+        self.multiboot = false;
+        self.bus.cartridge.load_entry(file_name, Some(entry))
+    }
+
+    /// Inserts the ROM at `file_name` with the IPS, UPS or BPS patches in
+    /// `patches` applied in order
+    pub fn load_cartridge_patched(
+        &mut self,
+        file_name: &str,
+        patches: &[&str],
+    ) -> Result<&CartridgeHeader, LoadError> {
+        self.multiboot = false;
+        self.bus.cartridge.load_patched(file_name, None, patches)
+    }
+
+    /// The header of the inserted cartridge, if there is one
+    pub fn cartridge_header(&self) -> Option<&CartridgeHeader> {
+        self.bus.cartridge.header()
+    }
+
+    /// Writes the save file right away if the game has saved since it was
+    /// last written, instead of waiting for the game to stop saving.
+    pub fn flush_save(&mut self) -> std::io::Result<()> {
+        self.bus.cartridge.flush_save()
+    }
+
+    /// Where the cartridge's RTC gets the time from. Defaults to the host
+    /// clock, a fixed time makes runs repeatable.
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.bus.cartridge.set_rtc_clock(clock);
+    }
+
+    /// The extra hardware on the cartridge, which says which of the sensor
+    /// inputs below the game will look at
+    pub fn hardware(&self) -> &[Hardware] {
+        self.bus.cartridge.hardware()
+    }
+
+    /// How much light the solar sensor sees, from 0 for pitch black to
+    /// 0xFF for direct sunlight
+    pub fn set_solar_level(&mut self, level: u8) {
+        self.bus.cartridge.set_solar_level(level);
+    }
+
+    /// How far the tilt sensor is tilted. Positive x is to the right and
+    /// positive y is towards the player, with the full range of each
+    /// being about as far as anyone would tilt it.
+    pub fn set_tilt(&mut self, x: i16, y: i16) {
+        self.bus.cartridge.set_tilt(x, y);
+    }
+
+    /// How fast the gyro sensor is turning, positive being clockwise
+    pub fn set_gyro_rate(&mut self, rate: i16) {
+        self.bus.cartridge.set_gyro_rate(rate);
+    }
+
+    /// Whether the game has the rumble motor running
+    pub fn rumble(&self) -> bool {
+        self.bus.cartridge.rumble()
+    }
+
+    /// The kind of save memory the cartridge has
+    pub fn backup_type(&self) -> BackupType {
+        self.bus.cartridge.backup_type()
+    }
+
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        self.bus
+            .keypad
+            .set_key(key, pressed, &mut self.bus.interrupt);
+    }
+
+    /// Audio generated since the last call as (left, right) pairs
+    pub fn audio_samples(&mut self) -> Vec<(i16, i16)> {
+        self.bus.sound.drain_samples()
     }
 
     pub fn render_frame(&mut self) -> &Vec<u32> {
-        let mut cycles = 0;
-        //let mut i = 1;
-        while cycles < 280896 {
-            // Do something with HBLANK0 here?
-            self.cpu.step(CYCLES_TOTAL_HBLANK0, &mut self.bus);
-
-            self.bus.ppu.start_hblank();
-            // Do something with HBLANK1 here?
-            self.bus.ppu.render_line();
-            self.bus.ppu.end_hblank();
-
-            self.cpu.step(CYCLES_TOTAL_HBLANK1, &mut self.bus);
-
-            cycles += CYCLES_TOTAL_PER_LINE;
-            // println!("Rendered {} lines", i);
-            //i += 1;
+        // The CPU runs up to the next event, which the bus handles as soon
+        // as it's due. Everything else follows from there.
+        while !self.bus.ppu.take_frame_ready() {
+            let cycles = self
+                .bus
+                .scheduler
+                .cycles_until_next()
+                .expect("The PPU always has an event scheduled");
+            self.cpu.step(cycles.max(1) as u32, &mut self.bus);
         }
+        self.bus.cartridge.end_frame();
         self.bus.ppu.render_screen()
     }
 }
@@ -1,84 +1,120 @@
+use crate::gba::bus::io;
+use crate::gba::interrupt::{Interrupt, InterruptController};
+
+const LINES_TOTAL: u16 = 228;
+pub const LINES_VISIBLE: u16 = 160;
+
+pub const CYCLES_HDRAW: u64 = 1006;
+pub const CYCLES_HBLANK: u64 = 226;
+
 pub struct Ppu {
     vram: Vec<u8>,
     palette: Vec<u32>,
     pram: Vec<u8>,
+    oam: Vec<u8>,
     output: Vec<u32>,
     io_regs: Io,
+    // Set once the last visible line has been drawn
+    frame_ready: bool,
 }
 
+// https://problemkaputt.de/gbatek-lcd-i-o-display-control.htm
 struct Io {
     disp_ctrl: u16,
+    green_swap: u16,
     disp_stat: u16,
     v_count: u16,
+    bg_ctrl: [u16; 4],
+    // BGxHOFS and BGxVOFS for each background
+    bg_offset: [u16; 8],
+    // PA, PB, PC, PD, X and Y for BG2 and BG3, with X and Y being
+    // 32 bits wide.
+    bg_affine: [u16; 16],
+    // WIN0H, WIN1H, WIN0V and WIN1V
+    win_bounds: [u16; 4],
+    win_in: u16,
+    win_out: u16,
+    mosaic: u16,
+    blend_ctrl: u16,
+    blend_alpha: u16,
+    blend_y: u16,
 }
 
 impl Ppu {
     pub fn new() -> Ppu {
         // VRAM is 96kb
         // PRAM is 1kb (512 bytes for BG and 512 for OBJ)
+        // OAM is 1kb (128 sprites of 8 bytes)
         // Dunno if vram is zero initialized
         // Dimensions of GBA screen is 240 x 160
         Ppu {
             vram: vec![0; 96 * 1024],
             palette: vec![0; 512],
-            pram: vec![0; 1 * 1024],
+            pram: vec![0; 1024],
+            oam: vec![0; 1024],
             output: vec![0x0; 240 * 160],
             io_regs: Io::new(),
+            frame_ready: false,
         }
     }
 
-    pub fn read_vram(&self, address: u32) -> u8 {
-        // I believe that this ensures that we mirror the memory?
-        // It basically wraps around the memory or something
-        let mask = if (address >> 17) & 0x01 == 1 {
-            0x01_7F_FF
-        } else {
-            0x00_FF_FF
-        };
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
 
-        let index = (address & mask) as usize;
-        self.vram[index]
+    pub fn vram_mut(&mut self) -> &mut [u8] {
+        &mut self.vram
+    }
+
+    pub fn read_vram(&self, address: u32) -> u8 {
+        self.vram[vram_index(address)]
     }
 
     pub fn read_pram(&self, address: u32) -> u8 {
-        // I believe that this ensures that we mirror the memory?
-        // It basically wraps around the memory or something
-        let mask = if (address >> 17) & 0x01 == 1 {
-            0x01_7F_FF
-        } else {
-            0x00_FF_FF
-        };
+        self.pram[(address & 0x03_FF) as usize]
+    }
 
-        let index = (address & mask) as usize;
-        self.vram[index]
+    pub fn read_oam(&self, address: u32) -> u8 {
+        self.oam[(address & 0x03_FF) as usize]
     }
 
-    pub fn read_io(&self, address: u32) -> u8 {
+    pub fn read_io(&self, address: u32) -> u16 {
+        let io = &self.io_regs;
+        // The write only registers are masked out by the bus
         match address {
-            0x0400_0000 => self.io_regs.disp_ctrl as u8,
-            0x0400_0001 => (self.io_regs.disp_ctrl >> 8) as u8,
-            0x0400_0004 => self.io_regs.disp_stat as u8,
-            0x0400_0005 => (self.io_regs.disp_stat >> 8) as u8,
-            0x0400_0006 => (self.io_regs.v_count) as u8,
-            _ => unimplemented!("Invalid address {:#2X}!", address),
+            0x0400_0000 => io.disp_ctrl,
+            0x0400_0002 => io.green_swap,
+            0x0400_0004 => io.disp_stat,
+            0x0400_0006 => io.v_count,
+            0x0400_0008..=0x0400_000F => io.bg_ctrl[index(address, 0x0400_0008)],
+            0x0400_0048 => io.win_in,
+            0x0400_004A => io.win_out,
+            0x0400_0050 => io.blend_ctrl,
+            0x0400_0052 => io.blend_alpha,
+            _ => 0x0,
         }
     }
 
     pub fn write_vram(&mut self, address: u32, value: u8) {
-        // I believe that this ensures that we mirror the memory?
-        // It basically wraps around the memory or something
-        let mask = if (address >> 17) & 0x01 == 1 {
-            0x01_7F_FF
+        self.vram[vram_index(address)] = value;
+    }
+
+    /// Byte writes only go through to the BG part of VRAM, and get
+    /// written to both bytes of the halfword. The OBJ part ignores them.
+    pub fn vram_byte_writable(&self, address: u32) -> bool {
+        let bg_size = if self.io_regs.disp_ctrl & 0b111 >= 3 {
+            0x1_40_00
         } else {
-            0x01_FF_FF
+            0x1_00_00
         };
-        let index = (address & mask) as usize;
-        self.vram[index] = value;
+        vram_index(address) < bg_size
+    }
+
+    pub fn write_oam(&mut self, address: u32, value: u8) {
+        self.oam[(address & 0x03_FF) as usize] = value;
     }
 
     pub fn write_pram(&mut self, address: u32, value: u8) {
-        // I believe that this ensures that we mirror the memory?
-        // It basically wraps around the memory or something
         let index = (address & 0x03_FF) as usize;
         self.pram[index] = value;
 
@@ -97,28 +133,57 @@ impl Ppu {
         rgba |= g << 8;
         rgba |= b;
 
-        self.palette[palette_idx] = rgba;
+        self.palette[palette_idx / 2] = rgba;
     }
 
-    pub fn write_io(&mut self, address: u32, value: u8) {
-        match address {
-            0x0400_0000 => self.io_regs.disp_ctrl |= u16::from(value),
-            0x0400_0001 => self.io_regs.disp_ctrl |= u16::from(value) << 8,
-            0x0400_0004 => self.io_regs.disp_stat |= u16::from(value),
-            0x0400_0005 => self.io_regs.disp_stat |= u16::from(value) << 8,
-            _ => log::error!("Invalid address {:#2X}!", address),
-            //_ => unimplemented!("Invalid address {:#2X}!", address),
+    pub fn write_io(&mut self, address: u32, value: u16, mask: u16) {
+        if let Some(reg) = self.io_regs.register(address) {
+            *reg = io::merge(*reg, value, mask);
         }
     }
 
-    pub fn start_hblank(&mut self) {
-        // Do we always set the HBLANK flag?
+    pub fn v_count(&self) -> u16 {
+        self.io_regs.v_count
+    }
+
+    pub fn start_hblank(&mut self, interrupt: &mut InterruptController) {
+        // The HBLANK flag is set on every line, even during VBLANK
         self.io_regs.disp_stat |= 0b10;
+        if (self.io_regs.disp_stat >> 4) & 0x01 == 1 {
+            interrupt.request(Interrupt::HBlank);
+        }
     }
 
-    pub fn end_hblank(&mut self) {
-        // Do we always set the HBLANK flag?
+    pub fn end_hblank(&mut self, interrupt: &mut InterruptController) {
         self.io_regs.disp_stat &= !0b10;
+
+        // Move on to the next line
+        self.io_regs.v_count += 1;
+        if self.io_regs.v_count == LINES_TOTAL {
+            self.io_regs.v_count = 0;
+        }
+
+        // Bit 0 = VBLANK, Bit 1 = HBLANK
+        if self.io_regs.v_count == LINES_VISIBLE {
+            self.frame_ready = true;
+            self.io_regs.disp_stat |= 0b01;
+            if (self.io_regs.disp_stat >> 3) & 0x01 == 1 {
+                interrupt.request(Interrupt::VBlank);
+            }
+        } else if self.io_regs.v_count == LINES_TOTAL - 1 {
+            // The VBLANK flag is cleared on the last line
+            self.io_regs.disp_stat &= !0b01;
+        }
+
+        // Bit 2 is set while VCOUNT matches the LYC in bits 8 - 15
+        if self.io_regs.v_count == self.io_regs.disp_stat >> 8 {
+            self.io_regs.disp_stat |= 0b100;
+            if (self.io_regs.disp_stat >> 5) & 0x01 == 1 {
+                interrupt.request(Interrupt::VCount);
+            }
+        } else {
+            self.io_regs.disp_stat &= !0b100;
+        }
     }
 
     // Certaintly not accurate at all, but this should do for now.
@@ -126,18 +191,7 @@ impl Ppu {
     // maybe.
     // Do I need to update the framebuffer per line?
     pub fn render_line(&mut self) {
-        // Bit 0 = VBLANK, Bit 1 = HBLANK
-        if self.io_regs.v_count == 268 {
-            self.io_regs.v_count = 0;
-            // Reset VBLANK?
-            self.io_regs.disp_stat &= !0b01;
-            return;
-        }
-        if self.io_regs.v_count >= 160 {
-            // This is when we set the VBLANK?
-            self.io_regs.disp_stat |= 0b01;
-            // println!("DISP STAT STAT is {}", self.io_regs.disp_stat);
-            self.io_regs.v_count += 1;
+        if self.io_regs.v_count >= LINES_VISIBLE {
             return;
         }
 
@@ -173,7 +227,7 @@ impl Ppu {
             4 => {
                 let buffer_addr = self.io_regs.v_count * 240;
                 let window0 = (self.io_regs.disp_ctrl >> 13) & 0x01 == 1;
-                let mut vram_addr = if window0 {
+                let vram_addr = if window0 {
                     // Frame 0
                     u32::from(self.io_regs.v_count) * 240
                 } else {
@@ -182,14 +236,17 @@ impl Ppu {
                 };
 
                 for i in 0..240 {
-                    let idx = self.vram[vram_addr as usize] as usize;
+                    let idx = self.vram[(vram_addr + u32::from(i)) as usize] as usize;
                     self.output[(i + buffer_addr) as usize] = self.palette[idx];
-                    vram_addr += 1;
                 }
             }
             _ => panic!("Video mode {} not implemented yet!", mode),
         }
-        self.io_regs.v_count += 1;
+    }
+
+    /// Whether a frame has been finished since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
     }
 
     pub fn render_screen(&self) -> &Vec<u32> {
@@ -197,12 +254,63 @@ impl Ppu {
     }
 }
 
+// VRAM is mirrored every 128kb, but there's only 96kb of it. The last
+// 32kb of each mirror are the same as the 32kb before them.
+fn vram_index(address: u32) -> usize {
+    let index = (address & 0x01_FF_FF) as usize;
+    if index >= 0x1_80_00 {
+        index - 0x80_00
+    } else {
+        index
+    }
+}
+
 impl Io {
     fn new() -> Io {
         Io {
             disp_ctrl: 0x0,
+            green_swap: 0x0,
             disp_stat: 0x0,
             v_count: 0x0,
+            bg_ctrl: [0x0; 4],
+            bg_offset: [0x0; 8],
+            // The affine backgrounds start out unrotated and unscaled
+            bg_affine: [
+                0x1_00, 0x0, 0x0, 0x1_00, 0x0, 0x0, 0x0, 0x0, 0x1_00, 0x0, 0x0, 0x1_00, 0x0, 0x0,
+                0x0, 0x0,
+            ],
+            win_bounds: [0x0; 4],
+            win_in: 0x0,
+            win_out: 0x0,
+            mosaic: 0x0,
+            blend_ctrl: 0x0,
+            blend_alpha: 0x0,
+            blend_y: 0x0,
         }
     }
+
+    // The register a write to `address` ends up in, VCOUNT is read only
+    fn register(&mut self, address: u32) -> Option<&mut u16> {
+        let reg = match address {
+            0x0400_0000 => &mut self.disp_ctrl,
+            0x0400_0002 => &mut self.green_swap,
+            0x0400_0004 => &mut self.disp_stat,
+            0x0400_0008..=0x0400_000F => &mut self.bg_ctrl[index(address, 0x0400_0008)],
+            0x0400_0010..=0x0400_001F => &mut self.bg_offset[index(address, 0x0400_0010)],
+            0x0400_0020..=0x0400_003F => &mut self.bg_affine[index(address, 0x0400_0020)],
+            0x0400_0040..=0x0400_0047 => &mut self.win_bounds[index(address, 0x0400_0040)],
+            0x0400_0048 => &mut self.win_in,
+            0x0400_004A => &mut self.win_out,
+            0x0400_004C => &mut self.mosaic,
+            0x0400_0050 => &mut self.blend_ctrl,
+            0x0400_0052 => &mut self.blend_alpha,
+            0x0400_0054 => &mut self.blend_y,
+            _ => return None,
+        };
+        Some(reg)
+    }
+}
+
+fn index(address: u32, base: u32) -> usize {
+    ((address - base) / 2) as usize
 }
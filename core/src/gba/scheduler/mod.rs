@@ -0,0 +1,77 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Instead of polling every subsystem after each instruction, anything that
+// happens at a known point in time gets scheduled here and handled once the
+// CPU has run up to it.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventKind {
+    HBlankStart,
+    HBlankEnd,
+    TimerOverflow(usize),
+    AudioSample,
+    IrqLine,
+}
+
+// Ordered by timestamp first so the heap hands out the earliest event
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Event {
+    timestamp: u64,
+    kind: EventKind,
+}
+
+pub struct Scheduler {
+    timestamp: u64,
+    events: BinaryHeap<Reverse<Event>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            timestamp: 0,
+            events: BinaryHeap::new(),
+        }
+    }
+
+    /// Amount of cycles that have passed since power on
+    pub fn now(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn advance(&mut self, cycles: u32) {
+        self.timestamp += u64::from(cycles);
+    }
+
+    /// Schedules `kind` to happen `cycles` from now
+    pub fn schedule(&mut self, kind: EventKind, cycles: u64) {
+        self.schedule_at(kind, self.timestamp + cycles);
+    }
+
+    pub fn schedule_at(&mut self, kind: EventKind, timestamp: u64) {
+        self.events.push(Reverse(Event { timestamp, kind }));
+    }
+
+    pub fn cancel(&mut self, kind: EventKind) {
+        self.events.retain(|Reverse(e)| e.kind != kind);
+    }
+
+    /// Cycles left until the next event, if there is one
+    pub fn cycles_until_next(&self) -> Option<u64> {
+        self.events
+            .peek()
+            .map(|Reverse(e)| e.timestamp.saturating_sub(self.timestamp))
+    }
+
+    /// Pops the next event that is due along with the timestamp it was
+    /// scheduled for, which might be a few cycles in the past.
+    pub fn pop_due(&mut self) -> Option<(EventKind, u64)> {
+        match self.events.peek() {
+            Some(Reverse(e)) if e.timestamp <= self.timestamp => {
+                let Reverse(e) = self.events.pop().unwrap();
+                Some((e.kind, e.timestamp))
+            }
+            _ => None,
+        }
+    }
+}
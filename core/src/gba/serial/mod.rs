@@ -0,0 +1,67 @@
+use crate::gba::bus::io;
+
+// Serial communication registers, see here:
+// https://problemkaputt.de/gbatek-gba-communication-ports.htm
+//
+// There's no link cable to talk to, so transfers never happen and the
+// registers only hold what was written to them.
+
+pub struct Serial {
+    // SIODATA32 or SIOMULTI0-3 at 0x4000120
+    data: [u16; 4],
+    // SIOCNT at 0x4000128
+    control: u16,
+    // SIODATA8 or SIOMLT_SEND at 0x400012A
+    send: u16,
+    // RCNT at 0x4000134, which picks the mode together with SIOCNT
+    mode: u16,
+    // JOYCNT at 0x4000140
+    joy_control: u16,
+    // JOY_RECV at 0x4000150 and JOY_TRANS at 0x4000154
+    joy_data: [u16; 4],
+    // JOYSTAT at 0x4000158
+    joy_status: u16,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: [0x0; 4],
+            control: 0x0,
+            send: 0x0,
+            mode: 0x0,
+            joy_control: 0x0,
+            joy_data: [0x0; 4],
+            joy_status: 0x0,
+        }
+    }
+
+    pub fn read_io(&self, address: u32) -> u16 {
+        match address {
+            0x0400_0120..=0x0400_0127 => self.data[data_index(address, 0x0400_0120)],
+            0x0400_0128 => self.control,
+            0x0400_012A => self.send,
+            0x0400_0134 => self.mode,
+            0x0400_0140 => self.joy_control,
+            0x0400_0150..=0x0400_0157 => self.joy_data[data_index(address, 0x0400_0150)],
+            _ => self.joy_status,
+        }
+    }
+
+    pub fn write_io(&mut self, address: u32, value: u16, mask: u16) {
+        let register = match address {
+            0x0400_0120..=0x0400_0127 => &mut self.data[data_index(address, 0x0400_0120)],
+            0x0400_0128 => &mut self.control,
+            0x0400_012A => &mut self.send,
+            0x0400_0134 => &mut self.mode,
+            0x0400_0140 => &mut self.joy_control,
+            0x0400_0150..=0x0400_0157 => &mut self.joy_data[data_index(address, 0x0400_0150)],
+            _ => &mut self.joy_status,
+        };
+        *register = io::merge(*register, value, mask);
+    }
+}
+
+fn data_index(address: u32, base: u32) -> usize {
+    ((address - base) / 2) as usize
+}
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+
+use crate::gba::bus::io;
+use crate::gba::dma::Dma;
+
+// Only the Direct Sound FIFOs for now, which is what timers 0 and 1 clock
+// and DMA1/DMA2 refill. See here:
+// https://problemkaputt.de/gbatek-gba-sound-channel-a-and-b-dma-sound.htm
+
+const FIFO_CAPACITY: usize = 32;
+const FIFO_ADDRESSES: [u32; 2] = [0x0400_00A0, 0x0400_00A4];
+
+// Samples are generated at 32768Hz
+pub const CYCLES_PER_SAMPLE: u64 = 512;
+// Roughly a tenth of a second worth of audio, older samples get dropped
+// if the frontend doesn't keep up.
+const OUTPUT_CAPACITY: usize = 4096;
+
+struct Fifo {
+    samples: VecDeque<i8>,
+    // The sample currently being played
+    current: i8,
+}
+
+pub struct DirectSound {
+    fifos: [Fifo; 2],
+    // SOUNDCNT_H at 0x4000082
+    control: u16,
+    // SOUNDBIAS at 0x4000088
+    bias: u16,
+    // The tone, wave and noise channels aren't emulated yet, so their
+    // registers and the wave RAM at 0x4000060 - 0x400009F are just kept
+    // around to be read back.
+    psg: [u16; 0x20],
+    // Stereo samples as (left, right)
+    output: VecDeque<(i16, i16)>,
+}
+
+impl Fifo {
+    fn new() -> Fifo {
+        Fifo {
+            samples: VecDeque::with_capacity(FIFO_CAPACITY),
+            current: 0,
+        }
+    }
+
+    fn push(&mut self, value: u8) {
+        if self.samples.len() < FIFO_CAPACITY {
+            self.samples.push_back(value as i8);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+        self.current = 0;
+    }
+}
+
+impl DirectSound {
+    pub fn new() -> DirectSound {
+        DirectSound {
+            fifos: [Fifo::new(), Fifo::new()],
+            control: 0x0,
+            bias: 0x2_00,
+            psg: [0x0; 0x20],
+            output: VecDeque::with_capacity(OUTPUT_CAPACITY),
+        }
+    }
+
+    pub fn read_io(&self, address: u32) -> u16 {
+        match address {
+            0x0400_0082 => self.control,
+            0x0400_0088 => self.bias,
+            0x0400_00A0..=0x0400_00A7 => 0x0,
+            _ => self.psg[psg_index(address)],
+        }
+    }
+
+    pub fn write_io(&mut self, address: u32, value: u16, mask: u16) {
+        match address {
+            0x0400_0082 => {
+                self.control = io::merge(self.control, value, mask);
+                let written = value & mask;
+                if (written >> 11) & 0x01 == 1 {
+                    self.fifos[0].reset();
+                }
+                if (written >> 15) & 0x01 == 1 {
+                    self.fifos[1].reset();
+                }
+            }
+            0x0400_0088 => self.bias = io::merge(self.bias, value, mask),
+            0x0400_00A0..=0x0400_00A7 => {
+                let fifo = &mut self.fifos[((address - 0x0400_00A0) / 4) as usize];
+                // Every byte written goes into the FIFO, lowest first
+                for byte in 0..2 {
+                    if (mask >> (byte * 8)) & 0xFF != 0 {
+                        fifo.push((value >> (byte * 8)) as u8);
+                    }
+                }
+            }
+            _ => {
+                let index = psg_index(address);
+                self.psg[index] = io::merge(self.psg[index], value, mask);
+            }
+        }
+    }
+
+    /// Called whenever timer 0 or 1 overflows. Every FIFO clocked by that
+    /// timer moves on to its next sample and asks for a refill once it's
+    /// half empty.
+    pub fn timer_overflow(&mut self, timer: usize, dma: &mut Dma) {
+        for (i, fifo) in self.fifos.iter_mut().enumerate() {
+            // Bit 10 selects the timer for FIFO A, bit 14 for FIFO B
+            let selected = ((self.control >> (10 + i * 4)) & 0x01) as usize;
+            if selected != timer {
+                continue;
+            }
+
+            if let Some(sample) = fifo.samples.pop_front() {
+                fifo.current = sample;
+                log::trace!("FIFO {} playing sample {}", i, fifo.current);
+            }
+            if fifo.samples.len() <= FIFO_CAPACITY / 2 {
+                dma.request_fifo(FIFO_ADDRESSES[i]);
+            }
+        }
+    }
+
+    /// Mixes the current sample of both FIFOs into the output buffer.
+    pub fn generate_sample(&mut self) {
+        let (mut left, mut right) = (0i16, 0i16);
+        for (i, fifo) in self.fifos.iter().enumerate() {
+            // Bit 2 for FIFO A and bit 3 for FIFO B select 100% over 50%
+            let full_volume = (self.control >> (2 + i)) & 0x01 == 1;
+            let sample = if full_volume {
+                i16::from(fifo.current) << 2
+            } else {
+                i16::from(fifo.current) << 1
+            };
+
+            let enable = self.control >> (8 + i * 4);
+            if enable & 0x01 == 1 {
+                right += sample;
+            }
+            if (enable >> 1) & 0x01 == 1 {
+                left += sample;
+            }
+        }
+
+        if self.output.len() == OUTPUT_CAPACITY {
+            self.output.pop_front();
+        }
+        self.output.push_back((left, right));
+    }
+
+    pub fn drain_samples(&mut self) -> Vec<(i16, i16)> {
+        self.output.drain(..).collect()
+    }
+}
+
+fn psg_index(address: u32) -> usize {
+    ((address - 0x0400_0060) / 2) as usize
+}
@@ -0,0 +1,67 @@
+use crate::gba::interrupt::{Interrupt, InterruptController};
+
+// System control registers, see here:
+// https://problemkaputt.de/gbatek-gba-system-control.htm
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerState {
+    Running,
+    // Woken up by any enabled interrupt
+    Halted,
+    // Only keypad, serial and game pak interrupts can wake us up
+    Stopped,
+}
+
+pub struct SystemControl {
+    // POSTFLG at 0x4000300
+    post_flag: u8,
+    pub power_state: PowerState,
+}
+
+impl SystemControl {
+    pub fn new() -> SystemControl {
+        SystemControl {
+            post_flag: 0x0,
+            power_state: PowerState::Running,
+        }
+    }
+
+    /// Leaves HALT or STOP if an interrupt that can wake us up has been
+    /// requested. Returns whether the CPU is able to run.
+    pub fn wake_up(&mut self, interrupt: &InterruptController) -> bool {
+        let wake = match self.power_state {
+            PowerState::Running => return true,
+            PowerState::Halted => interrupt.requested(),
+            PowerState::Stopped => {
+                interrupt.requested_any(&[Interrupt::Keypad, Interrupt::Serial, Interrupt::GamePak])
+            }
+        };
+
+        if wake {
+            log::debug!("Waking up from {:?}", self.power_state);
+            self.power_state = PowerState::Running;
+        }
+        wake
+    }
+
+    // HALTCNT is write only, so only POSTFLG can be read
+    pub fn read_io(&self) -> u16 {
+        u16::from(self.post_flag)
+    }
+
+    pub fn write_io(&mut self, value: u16, mask: u16) {
+        if mask & 0x00_FF != 0 {
+            self.post_flag = (value & 0x01) as u8;
+        }
+
+        // HALTCNT, bit 7 selects between HALT and STOP
+        if mask & 0xFF_00 != 0 {
+            self.power_state = if (value >> 15) & 0x01 == 1 {
+                PowerState::Stopped
+            } else {
+                PowerState::Halted
+            };
+            log::debug!("Entering {:?}", self.power_state);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::gba::bus::io;
+use crate::gba::interrupt::{Interrupt, InterruptController};
+use crate::gba::scheduler::{EventKind, Scheduler};
+
+// Timers, see here:
+// https://problemkaputt.de/gbatek-gba-timers.htm
+//
+// Running timers aren't ticked. We remember when they were started and work
+// the counter out from the current timestamp whenever it's read, while the
+// overflow is scheduled ahead of time.
+
+const TIMER_IRQS: [Interrupt; 4] = [
+    Interrupt::Timer0,
+    Interrupt::Timer1,
+    Interrupt::Timer2,
+    Interrupt::Timer3,
+];
+
+// Prescaler selection for F/1, F/64, F/256 and F/1024 as shift amounts
+const PRESCALER_SHIFTS: [u32; 4] = [0, 6, 8, 10];
+
+#[derive(Clone, Copy)]
+struct Timer {
+    id: usize,
+    reload: u16,
+    // Counter value at the time of `start`
+    counter: u16,
+    control: u16,
+    start: u64,
+}
+
+pub struct Timers {
+    timers: [Timer; 4],
+}
+
+impl Timer {
+    fn new(id: usize) -> Timer {
+        Timer {
+            id,
+            reload: 0x0,
+            counter: 0x0,
+            control: 0x0,
+            start: 0,
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        (self.control >> 7) & 0x01 == 1
+    }
+
+    // Timer 0 has nothing to count up from
+    fn cascade(&self) -> bool {
+        self.id != 0 && (self.control >> 2) & 0x01 == 1
+    }
+
+    fn irq(&self) -> bool {
+        (self.control >> 6) & 0x01 == 1
+    }
+
+    fn prescaler_shift(&self) -> u32 {
+        PRESCALER_SHIFTS[(self.control & 0b11) as usize]
+    }
+
+    // Only timers running off the system clock count by themselves
+    fn free_running(&self) -> bool {
+        self.enabled() && !self.cascade()
+    }
+
+    fn counter(&self, now: u64) -> u16 {
+        if !self.free_running() {
+            return self.counter;
+        }
+        let ticks = (now - self.start) >> self.prescaler_shift();
+        self.counter.wrapping_add(ticks as u16)
+    }
+
+    fn schedule_overflow(&self, scheduler: &mut Scheduler) {
+        let ticks = 0x1_00_00 - u64::from(self.counter);
+        scheduler.schedule_at(
+            EventKind::TimerOverflow(self.id),
+            self.start + (ticks << self.prescaler_shift()),
+        );
+    }
+
+    fn write_control(&mut self, control: u16, scheduler: &mut Scheduler) {
+        let now = scheduler.now();
+        let was_enabled = self.enabled();
+
+        // Freeze the counter under the old settings before switching over
+        self.counter = self.counter(now);
+        self.control = control & 0x00_C7;
+        self.start = now;
+
+        if self.enabled() && !was_enabled {
+            self.counter = self.reload;
+        }
+
+        scheduler.cancel(EventKind::TimerOverflow(self.id));
+        if self.free_running() {
+            self.schedule_overflow(scheduler);
+        }
+    }
+}
+
+impl Timers {
+    pub fn new() -> Timers {
+        Timers {
+            timers: [Timer::new(0), Timer::new(1), Timer::new(2), Timer::new(3)],
+        }
+    }
+
+    pub fn read_io(&self, address: u32, scheduler: &Scheduler) -> u16 {
+        let offset = address - 0x0400_0100;
+        let timer = &self.timers[(offset / 4) as usize];
+        match offset % 4 {
+            0 => timer.counter(scheduler.now()),
+            _ => timer.control,
+        }
+    }
+
+    pub fn write_io(&mut self, address: u32, value: u16, mask: u16, scheduler: &mut Scheduler) {
+        let offset = address - 0x0400_0100;
+        let timer = &mut self.timers[(offset / 4) as usize];
+        match offset % 4 {
+            // Writes to the counter only set the reload value
+            0 => timer.reload = io::merge(timer.reload, value, mask),
+            _ => timer.write_control(io::merge(timer.control, value, mask), scheduler),
+        }
+    }
+
+    /// Handles the scheduled overflow of timer `id`, which was due at
+    /// `timestamp`. Returns a bitmask of every timer that overflowed,
+    /// including the ones counting up behind it.
+    pub fn overflow(
+        &mut self,
+        id: usize,
+        timestamp: u64,
+        scheduler: &mut Scheduler,
+        interrupt: &mut InterruptController,
+    ) -> u8 {
+        let timer = &mut self.timers[id];
+        timer.counter = timer.reload;
+        timer.start = timestamp;
+        timer.schedule_overflow(scheduler);
+
+        self.cascade(id, interrupt)
+    }
+
+    fn cascade(&mut self, id: usize, interrupt: &mut InterruptController) -> u8 {
+        if self.timers[id].irq() {
+            interrupt.request(TIMER_IRQS[id]);
+        }
+
+        let mut overflowed = 1 << id;
+        if let Some(next) = self.timers.get_mut(id + 1) {
+            if next.enabled() && next.cascade() {
+                next.counter = next.counter.wrapping_add(1);
+                if next.counter == 0 {
+                    next.counter = next.reload;
+                    overflowed |= self.cascade(id + 1, interrupt);
+                }
+            }
+        }
+        overflowed
+    }
+}
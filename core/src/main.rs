@@ -11,8 +11,17 @@ fn main() {
         .unwrap();
 
     let mut test_gba = gba::HerodGBA::new();
+    test_gba
+        .load_cartridge_from_args()
+        .unwrap_or_else(|e| panic!("{}", e));
+    match std::env::args().nth(2).as_deref() {
+        Some("--replacement-bios") => test_gba.load_replacement_bios(),
+        Some(bios) => test_gba
+            .load_bios(bios)
+            .unwrap_or_else(|e| panic!("Could not load BIOS: {}", e)),
+        None => {}
+    }
     test_gba.power();
-    test_gba.load_cartridge_from_args();
 
     // benchmark(&mut test_gba);
     loop {
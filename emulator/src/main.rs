@@ -29,16 +29,85 @@ fn main() {
     });
 
     let mut test_gba = gba::HerodGBA::new();
+    let rom = std::env::args().nth(1).expect("Please specify a ROM!");
+    if rom.ends_with(".mb") {
+        test_gba.load_multiboot(&rom)
+    } else {
+        test_gba.load_cartridge(&rom).map(|_| ())
+    }
+    .unwrap_or_else(|e| panic!("{}", e));
+    match std::env::args().nth(2).as_deref() {
+        Some("--replacement-bios") => test_gba.load_replacement_bios(),
+        Some(bios) => test_gba
+            .load_bios(bios)
+            .unwrap_or_else(|e| panic!("Could not load BIOS: {}", e)),
+        None => {}
+    }
     test_gba.power();
-    test_gba.load_cartridge_from_args();
 
     // Limit to max ~60 fps update rate
     window.set_target_fps(60);
 
+    let keys = [
+        (Key::X, gba::Key::A),
+        (Key::Z, gba::Key::B),
+        (Key::Backspace, gba::Key::Select),
+        (Key::Enter, gba::Key::Start),
+        (Key::Right, gba::Key::Right),
+        (Key::Left, gba::Key::Left),
+        (Key::Up, gba::Key::Up),
+        (Key::Down, gba::Key::Down),
+        (Key::S, gba::Key::R),
+        (Key::A, gba::Key::L),
+    ];
+
+    // Cartridge sensors are driven from the keyboard too
+    let hardware = test_gba.hardware().to_vec();
+    let mut solar_level: u8 = 0x80;
+    let mut rumble = false;
+
     while window.is_open() && !window.is_key_down(Key::Escape) {
+        for (host, key) in keys {
+            test_gba.set_key(key, window.is_key_down(host));
+        }
+        if hardware.contains(&gba::Hardware::Solar) {
+            if window.is_key_down(Key::Equal) {
+                solar_level = solar_level.saturating_add(4);
+            }
+            if window.is_key_down(Key::Minus) {
+                solar_level = solar_level.saturating_sub(4);
+            }
+            test_gba.set_solar_level(solar_level);
+        }
+        if hardware.contains(&gba::Hardware::Tilt) {
+            let x = axis(&window, Key::L, Key::J);
+            let y = axis(&window, Key::K, Key::I);
+            test_gba.set_tilt(x, y);
+        }
+        if hardware.contains(&gba::Hardware::Gyro) {
+            test_gba.set_gyro_rate(axis(&window, Key::E, Key::Q));
+        }
         buffer = test_gba.render_frame();
 
         // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
-        window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+        window.update_with_buffer(buffer, WIDTH, HEIGHT).unwrap();
+
+        if test_gba.rumble() != rumble {
+            rumble = test_gba.rumble();
+            window.set_title(if rumble {
+                "Test - ESC to exit (rumbling)"
+            } else {
+                "Test - ESC to exit"
+            });
+        }
+    }
+}
+
+// Full tilt one way or the other while a key is held
+fn axis(window: &Window, positive: Key, negative: Key) -> i16 {
+    match (window.is_key_down(positive), window.is_key_down(negative)) {
+        (true, false) => i16::MAX,
+        (false, true) => i16::MIN,
+        _ => 0,
     }
 }